@@ -0,0 +1,4 @@
+alter table targets drop column mqtt_password;
+alter table targets drop column mqtt_username;
+alter table targets drop column mqtt_broker;
+alter table targets alter column endpoint set not null;
@@ -0,0 +1,4 @@
+alter table targets alter column endpoint drop not null;
+alter table targets add column mqtt_broker varchar null;
+alter table targets add column mqtt_username varchar null;
+alter table targets add column mqtt_password varchar null;
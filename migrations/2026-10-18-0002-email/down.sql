@@ -0,0 +1 @@
+alter table targets drop column email_address;
\ No newline at end of file
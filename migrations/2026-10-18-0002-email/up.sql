@@ -0,0 +1 @@
+alter table targets add column email_address varchar null;
\ No newline at end of file
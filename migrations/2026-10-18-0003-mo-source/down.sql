@@ -0,0 +1,2 @@
+alter table mo_messages drop column source;
+drop type mo_source;
\ No newline at end of file
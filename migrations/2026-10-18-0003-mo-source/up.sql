@@ -0,0 +1,6 @@
+create type mo_source as enum (
+    'direct_ip',
+    'rock_block'
+);
+
+alter table mo_messages add column source mo_source not null default 'direct_ip';
\ No newline at end of file
@@ -0,0 +1,3 @@
+alter table devices drop column mt_provider;
+drop table mt_providers;
+drop type mt_provider_kind;
\ No newline at end of file
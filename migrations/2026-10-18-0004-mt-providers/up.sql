@@ -0,0 +1,13 @@
+create type mt_provider_kind as enum (
+    'rock_block'
+);
+
+create table mt_providers (
+    id uuid primary key,
+    kind mt_provider_kind not null,
+    endpoint varchar not null,
+    username varchar not null,
+    password varchar not null
+);
+
+alter table devices add column mt_provider uuid null references mt_providers(id);
\ No newline at end of file
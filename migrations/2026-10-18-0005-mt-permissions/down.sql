@@ -0,0 +1,2 @@
+drop table audit_log;
+drop table mt_permissions;
\ No newline at end of file
@@ -0,0 +1,14 @@
+create table mt_permissions (
+    id uuid primary key,
+    target uuid references targets(id) not null,
+    imei char(15) not null,
+    unique (target, imei)
+);
+
+create table audit_log (
+    id uuid primary key,
+    time timestamp not null,
+    target uuid null references targets(id) on delete set null,
+    event varchar not null,
+    detail varchar not null
+);
\ No newline at end of file
@@ -0,0 +1,2 @@
+drop table request_nonces;
+alter table targets drop column mt_signature_version;
\ No newline at end of file
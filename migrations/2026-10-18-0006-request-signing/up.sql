@@ -0,0 +1,10 @@
+alter table targets add column mt_signature_version smallint not null default 1;
+
+create table request_nonces (
+    target uuid references targets(id) on delete cascade not null,
+    nonce varchar not null,
+    seen timestamp not null,
+    primary key (target, nonce)
+);
+
+create index request_nonces_seen on request_nonces (seen);
\ No newline at end of file
@@ -0,0 +1,9 @@
+alter table targets add column hmac_key bytea null;
+
+update targets set hmac_key = (
+    select key from target_keys where target_keys.target = targets.id order by created desc limit 1
+);
+
+alter table targets alter column hmac_key set not null;
+
+drop table target_keys;
\ No newline at end of file
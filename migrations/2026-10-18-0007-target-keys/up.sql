@@ -0,0 +1,14 @@
+create table target_keys (
+    id uuid primary key,
+    target uuid references targets(id) on delete cascade not null,
+    key bytea not null,
+    created timestamp not null,
+    expires timestamp null
+);
+
+create index target_keys_target on target_keys (target);
+
+insert into target_keys (id, target, key, created, expires)
+    select gen_random_uuid(), id, hmac_key, now() at time zone 'utc', null from targets;
+
+alter table targets drop column hmac_key;
\ No newline at end of file
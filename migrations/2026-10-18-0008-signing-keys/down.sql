@@ -0,0 +1,3 @@
+drop table signing_keys;
+alter table targets drop column webhook_signing;
+drop type webhook_signing;
\ No newline at end of file
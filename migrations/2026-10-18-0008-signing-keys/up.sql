@@ -0,0 +1,15 @@
+create type webhook_signing as enum (
+    'hmac',
+    'ed25519'
+);
+
+alter table targets add column webhook_signing webhook_signing not null default 'hmac';
+
+create table signing_keys (
+    id uuid primary key,
+    private_key bytea not null,
+    public_key bytea not null,
+    created timestamp not null,
+    activates timestamp not null,
+    expires timestamp null
+);
\ No newline at end of file
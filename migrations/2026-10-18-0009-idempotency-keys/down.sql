@@ -0,0 +1,2 @@
+drop table idempotency_keys;
+alter table mt_messages drop column idempotency_key;
\ No newline at end of file
@@ -0,0 +1,10 @@
+alter table mt_messages add column idempotency_key varchar null;
+
+create table idempotency_keys (
+    target uuid references targets(id) on delete cascade not null,
+    key varchar not null,
+    request_hash bytea not null,
+    message uuid references mt_messages(id) on delete cascade not null,
+    created timestamp not null,
+    primary key (target, key)
+);
\ No newline at end of file
@@ -0,0 +1,2 @@
+alter table mt_messages drop column metadata;
+alter table mt_messages drop column client_reference;
\ No newline at end of file
@@ -0,0 +1,2 @@
+alter table mt_messages add column client_reference varchar null;
+alter table mt_messages add column metadata jsonb null;
\ No newline at end of file
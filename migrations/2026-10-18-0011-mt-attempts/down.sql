@@ -0,0 +1,5 @@
+drop index mt_messages_target_received;
+
+alter table mt_messages drop column completed;
+alter table mt_messages drop column last_attempt;
+alter table mt_messages drop column attempts;
\ No newline at end of file
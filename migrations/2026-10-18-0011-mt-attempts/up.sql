@@ -0,0 +1,5 @@
+alter table mt_messages add column attempts integer not null default 0;
+alter table mt_messages add column last_attempt timestamp null;
+alter table mt_messages add column completed timestamp null;
+
+create index mt_messages_target_received on mt_messages (target, received desc, id desc);
\ No newline at end of file
@@ -0,0 +1 @@
+drop index mo_messages_imei_received;
\ No newline at end of file
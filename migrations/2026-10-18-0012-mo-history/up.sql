@@ -0,0 +1 @@
+create index mo_messages_imei_received on mo_messages (imei, received desc, id desc);
\ No newline at end of file
@@ -0,0 +1,12 @@
+update mt_messages set message_status = null where message_status = 'cancelled';
+
+alter type message_status rename to message_status_old;
+create type message_status as enum (
+    'delivered',
+    'invalid_imei',
+    'payload_size_exceeded',
+    'message_queue_full',
+    'resources_unavailable'
+);
+alter table mt_messages alter column message_status type message_status using message_status::text::message_status;
+drop type message_status_old;
\ No newline at end of file
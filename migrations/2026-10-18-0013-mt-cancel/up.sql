@@ -0,0 +1 @@
+alter type message_status add value 'cancelled';
\ No newline at end of file
@@ -0,0 +1,29 @@
+drop index mt_messages_scheduled;
+
+alter table mt_messages drop column expires_at;
+alter table mt_messages drop column not_before;
+
+update mt_messages set message_status = null where message_status = 'expired';
+update mt_messages set processing_status = 'received' where processing_status = 'scheduled';
+
+alter type message_status rename to message_status_old;
+create type message_status as enum (
+    'delivered',
+    'invalid_imei',
+    'payload_size_exceeded',
+    'message_queue_full',
+    'resources_unavailable',
+    'cancelled'
+);
+alter table mt_messages alter column message_status type message_status using message_status::text::message_status;
+drop type message_status_old;
+
+alter type processing_status rename to processing_status_old;
+create type processing_status as enum (
+    'received',
+    'done',
+    'failed'
+);
+alter table mo_messages alter column processing_status type processing_status using processing_status::text::processing_status;
+alter table mt_messages alter column processing_status type processing_status using processing_status::text::processing_status;
+drop type processing_status_old;
\ No newline at end of file
@@ -0,0 +1,7 @@
+alter type processing_status add value 'scheduled';
+alter type message_status add value 'expired';
+
+alter table mt_messages add column not_before timestamp null;
+alter table mt_messages add column expires_at timestamp null;
+
+create index mt_messages_scheduled on mt_messages (processing_status, not_before);
\ No newline at end of file
@@ -0,0 +1,2 @@
+drop table mt_schedule_runs;
+drop table mt_schedules;
\ No newline at end of file
@@ -0,0 +1,24 @@
+create table mt_schedules (
+    id uuid primary key,
+    target uuid references targets(id) on delete cascade not null,
+    imei char(15) not null,
+    cron varchar not null,
+    data bytea not null,
+    priority int2 not null,
+    client_reference varchar null,
+    metadata jsonb null,
+    paused boolean not null default false,
+    next_run timestamp null,
+    created timestamp not null
+);
+
+create index mt_schedules_next_run on mt_schedules (next_run) where not paused;
+
+create table mt_schedule_runs (
+    id uuid primary key,
+    schedule uuid references mt_schedules(id) on delete cascade not null,
+    run_time timestamp not null,
+    message uuid references mt_messages(id) on delete set null null,
+    error varchar null,
+    unique (schedule, run_time)
+);
\ No newline at end of file
@@ -0,0 +1,4 @@
+drop index mt_messages_batch;
+alter table mt_messages drop column batch;
+
+drop table mt_batches;
\ No newline at end of file
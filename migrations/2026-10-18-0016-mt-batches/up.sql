@@ -0,0 +1,14 @@
+create table mt_batches (
+    id uuid primary key,
+    target uuid references targets(id) on delete cascade not null,
+    client_reference varchar null,
+    notify boolean not null default false,
+    created timestamp not null,
+    completed timestamp null
+);
+
+create index mt_batches_incomplete on mt_batches (created) where completed is null;
+
+alter table mt_messages add column batch uuid references mt_batches(id) on delete set null null;
+
+create index mt_messages_batch on mt_messages (batch);
\ No newline at end of file
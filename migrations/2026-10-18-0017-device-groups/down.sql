@@ -0,0 +1,12 @@
+alter table mt_schedule_runs drop column batch;
+
+delete from mt_schedules where imei is null;
+alter table mt_schedules drop constraint mt_schedules_destination;
+alter table mt_schedules drop column device_group;
+alter table mt_schedules alter column imei set not null;
+
+alter table mt_batches drop column device_group;
+
+drop table device_tags;
+drop table device_group_members;
+drop table device_groups;
\ No newline at end of file
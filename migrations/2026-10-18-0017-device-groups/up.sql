@@ -0,0 +1,28 @@
+create table device_groups (
+    id uuid primary key,
+    target uuid references targets(id) on delete cascade not null,
+    name varchar not null,
+    created timestamp not null,
+    unique (target, name)
+);
+
+create table device_group_members (
+    device_group uuid references device_groups(id) on delete cascade not null,
+    device uuid references devices(id) on delete cascade not null,
+    primary key (device_group, device)
+);
+
+create table device_tags (
+    device uuid references devices(id) on delete cascade not null,
+    key varchar not null,
+    value varchar not null,
+    primary key (device, key)
+);
+
+alter table mt_batches add column device_group uuid references device_groups(id) on delete set null null;
+
+alter table mt_schedules alter column imei drop not null;
+alter table mt_schedules add column device_group uuid references device_groups(id) on delete cascade null;
+alter table mt_schedules add constraint mt_schedules_destination check ((imei is null) != (device_group is null));
+
+alter table mt_schedule_runs add column batch uuid references mt_batches(id) on delete set null null;
\ No newline at end of file
@@ -0,0 +1 @@
+alter table devices drop column metadata;
\ No newline at end of file
@@ -0,0 +1 @@
+alter table devices add column metadata jsonb null;
\ No newline at end of file
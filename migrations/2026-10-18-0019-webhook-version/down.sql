@@ -0,0 +1 @@
+alter table targets drop column webhook_version;
\ No newline at end of file
@@ -0,0 +1 @@
+alter table targets add column webhook_version smallint not null default 1;
\ No newline at end of file
@@ -0,0 +1,2 @@
+alter table targets drop column cloud_events;
+drop type cloud_events_mode;
\ No newline at end of file
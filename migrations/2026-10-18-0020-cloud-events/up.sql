@@ -0,0 +1,6 @@
+create type cloud_events_mode as enum (
+    'structured',
+    'binary'
+);
+
+alter table targets add column cloud_events cloud_events_mode null;
\ No newline at end of file
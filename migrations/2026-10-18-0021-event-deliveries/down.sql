@@ -0,0 +1,2 @@
+drop table event_deliveries;
+drop type delivery_channel;
\ No newline at end of file
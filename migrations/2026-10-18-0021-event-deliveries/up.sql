@@ -0,0 +1,14 @@
+create type delivery_channel as enum (
+    'webhook',
+    'mqtt',
+    'email'
+);
+
+create table event_deliveries (
+    event uuid not null,
+    channel delivery_channel not null,
+    delivered timestamp not null,
+    primary key (event, channel)
+);
+
+create index event_deliveries_delivered on event_deliveries (delivered);
\ No newline at end of file
@@ -0,0 +1 @@
+alter table targets drop column email_token;
\ No newline at end of file
@@ -0,0 +1 @@
+alter table targets add column email_token varchar null;
\ No newline at end of file
@@ -0,0 +1 @@
+drop index mo_messages_rock_block_unique;
\ No newline at end of file
@@ -0,0 +1,7 @@
+-- Replays of the same push, which would otherwise be processed again
+delete from mo_messages a using mo_messages b
+where a.source = 'rock_block' and b.source = 'rock_block' and a.imei = b.imei and a.mo_msn = b.mo_msn
+    and a.time_of_session = b.time_of_session and (a.received, a.id) > (b.received, b.id);
+
+create unique index mo_messages_rock_block_unique on mo_messages (imei, mo_msn, time_of_session)
+    where source = 'rock_block';
\ No newline at end of file
@@ -0,0 +1 @@
+drop index signing_keys_current;
\ No newline at end of file
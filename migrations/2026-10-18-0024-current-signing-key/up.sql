@@ -0,0 +1,6 @@
+-- Workers starting together could each have created a key; keep the newest, retiring the others as a rotation would
+update signing_keys set expires = now() at time zone 'utc' + interval '7 days'
+where expires is null and id <> (select id from signing_keys where expires is null order by activates desc limit 1);
+
+-- Only the newest key is left without an expiry, so at most one key may be
+create unique index signing_keys_current on signing_keys ((expires is null)) where expires is null;
\ No newline at end of file
@@ -0,0 +1,12 @@
+update mt_messages set processing_status = 'received' where processing_status = 'sending';
+
+alter type processing_status rename to processing_status_old;
+create type processing_status as enum (
+    'received',
+    'done',
+    'failed',
+    'scheduled'
+);
+alter table mo_messages alter column processing_status type processing_status using processing_status::text::processing_status;
+alter table mt_messages alter column processing_status type processing_status using processing_status::text::processing_status;
+drop type processing_status_old;
\ No newline at end of file
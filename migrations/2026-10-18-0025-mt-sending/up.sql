@@ -0,0 +1 @@
+alter type processing_status add value 'sending';
\ No newline at end of file
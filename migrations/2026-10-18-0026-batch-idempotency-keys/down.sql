@@ -0,0 +1,4 @@
+delete from idempotency_keys where message is null;
+alter table idempotency_keys drop constraint idempotency_keys_request;
+alter table idempotency_keys drop column batch;
+alter table idempotency_keys alter column message set not null;
\ No newline at end of file
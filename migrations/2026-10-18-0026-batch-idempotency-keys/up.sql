@@ -0,0 +1,3 @@
+alter table idempotency_keys alter column message drop not null;
+alter table idempotency_keys add column batch uuid references mt_batches(id) on delete cascade null;
+alter table idempotency_keys add constraint idempotency_keys_request check ((message is null) <> (batch is null));
\ No newline at end of file
@@ -0,0 +1 @@
+drop index devices_imei_unique;
\ No newline at end of file
@@ -0,0 +1,2 @@
+-- Fails if an IMEI is already registered to several devices, which must be resolved by hand
+create unique index devices_imei_unique on devices (imei);
\ No newline at end of file
@@ -0,0 +1 @@
+alter table mo_messages drop column requeued;
\ No newline at end of file
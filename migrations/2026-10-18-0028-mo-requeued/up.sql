@@ -0,0 +1 @@
+alter table mo_messages add column requeued timestamp null;
\ No newline at end of file
@@ -0,0 +1,2 @@
+drop index audit_log_message;
+alter table audit_log drop column message;
\ No newline at end of file
@@ -0,0 +1,7 @@
+alter table audit_log add column message uuid null;
+
+-- Entries about a message have so far only named it in their detail
+update audit_log set message = substring(detail from 'message ([0-9a-f-]{36})')::uuid
+where event in ('mo_requeued', 'mt_requeued', 'mt_cancelled', 'mt_queue_flushed');
+
+create index audit_log_message on audit_log (message) where message is not null;
\ No newline at end of file
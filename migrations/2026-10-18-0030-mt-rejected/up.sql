@@ -0,0 +1 @@
+alter type message_status add value 'rejected';
\ No newline at end of file
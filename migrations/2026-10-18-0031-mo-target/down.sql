@@ -0,0 +1,2 @@
+drop index mo_messages_target_received;
+alter table mo_messages drop column target;
\ No newline at end of file
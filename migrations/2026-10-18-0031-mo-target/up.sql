@@ -0,0 +1,6 @@
+alter table mo_messages add column target uuid null references targets(id) on delete set null;
+
+-- Messages received so far are taken to belong to their device's current owner
+update mo_messages set target = devices.target from devices where devices.imei = mo_messages.imei;
+
+create index mo_messages_target_received on mo_messages (target, received desc, id desc);
\ No newline at end of file
@@ -0,0 +1 @@
+alter table mt_messages add column requeued timestamp null;
\ No newline at end of file
@@ -0,0 +1 @@
+drop index targets_email_address_unique;
\ No newline at end of file
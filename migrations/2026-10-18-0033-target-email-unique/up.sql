@@ -0,0 +1,4 @@
+update targets set email_address = lower(email_address) where email_address <> lower(email_address);
+
+-- Fails if several targets share an email address, which must be resolved by hand
+create unique index targets_email_address_unique on targets (lower(email_address));
\ No newline at end of file
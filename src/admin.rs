@@ -0,0 +1,433 @@
+use base64::prelude::*;
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use diesel_async::scoped_futures::ScopedFutureExt;
+
+/// Shortest email token accepted, so that it can't be guessed
+const MIN_EMAIL_TOKEN_LENGTH: usize = 16;
+
+#[derive(Debug)]
+pub enum AdminError {
+    NotFound,
+    InvalidIMEI,
+    InvalidEndpoint,
+    InvalidMQTTBroker,
+    InvalidEmailAddress,
+    InvalidEmailToken,
+    InvalidSignatureVersion,
+    InvalidWebhookVersion,
+    InvalidMetadata,
+    UnknownTarget,
+    UnknownProvider,
+    /// The IMEI is already registered to a device
+    IMEITaken,
+    /// Another target already has the email address
+    EmailAddressTaken,
+    /// The target still has devices or messages, and can't be deleted
+    InUse,
+    Internal,
+}
+
+impl From<diesel::result::Error> for AdminError {
+    fn from(err: diesel::result::Error) -> Self {
+        match err {
+            diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::ForeignKeyViolation, _) =>
+                AdminError::InUse,
+            // Another device was given the IMEI at the same time
+            diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, info)
+                if info.constraint_name() == Some("devices_imei_unique") => AdminError::IMEITaken,
+            diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, info)
+                if info.constraint_name() == Some("targets_email_address_unique") => AdminError::EmailAddressTaken,
+            err => {
+                error!("Failed to access database: {}", err);
+                AdminError::Internal
+            }
+        }
+    }
+}
+
+/// Checks an IMEI is 15 digits, and that its last digit is the correct Luhn check digit
+pub(crate) fn valid_imei(imei: &str) -> bool {
+    if imei.len() != 15 || !imei.chars().all(|c| c.is_ascii_digit()) {
+        return false;
+    }
+
+    let sum: u32 = imei.chars().rev()
+        .map(|c| c.to_digit(10).unwrap())
+        .enumerate()
+        .map(|(i, d)| if i % 2 == 1 {
+            let d = d * 2;
+            if d > 9 { d - 9 } else { d }
+        } else {
+            d
+        })
+        .sum();
+
+    sum.is_multiple_of(10)
+}
+
+fn valid_endpoint(endpoint: &str) -> bool {
+    match reqwest::Url::parse(endpoint) {
+        Ok(u) => matches!(u.scheme(), "http" | "https") && u.host().is_some(),
+        Err(_) => false
+    }
+}
+
+fn valid_mqtt_broker(broker: &str) -> bool {
+    // Parsed in the same way as when connecting, which requires a client ID
+    let mut url = match reqwest::Url::parse(broker) {
+        Ok(u) => u,
+        Err(_) => return false
+    };
+    url.query_pairs_mut().append_pair("client_id", "kosmos");
+    rumqttc::MqttOptions::parse_url(url.to_string()).is_ok()
+}
+
+fn target_info(target: crate::models::Target) -> crate::types::Target {
+    crate::types::Target {
+        id: target.id,
+        endpoint: target.endpoint,
+        mqtt_broker: target.mqtt_broker,
+        mqtt_username: target.mqtt_username,
+        email_address: target.email_address,
+        mt_signature_version: target.mt_signature_version as u8,
+        webhook_signing: target.webhook_signing.into(),
+        webhook_version: target.webhook_version as u8,
+        cloud_events: target.cloud_events.map(Into::into),
+    }
+}
+
+fn device_info(device: crate::models::Device) -> crate::types::AdminDevice {
+    crate::types::AdminDevice {
+        id: device.id,
+        imei: device.imei,
+        target: device.target,
+        mt_provider: device.mt_provider,
+        metadata: device.metadata,
+    }
+}
+
+/// Validates a target's configuration, returning the target to be stored
+fn make_target(id: uuid::Uuid, config: crate::types::TargetConfig) -> Result<crate::models::Target, AdminError> {
+    if let Some(endpoint) = &config.endpoint {
+        if !valid_endpoint(endpoint) {
+            return Err(AdminError::InvalidEndpoint);
+        }
+    }
+    if let Some(broker) = &config.mqtt_broker {
+        if !valid_mqtt_broker(broker) {
+            return Err(AdminError::InvalidMQTTBroker);
+        }
+    }
+    // Incoming email is matched against this in lower case
+    let email_address = match config.email_address {
+        Some(address) => match address.parse::<lettre::Address>() {
+            Ok(_) => Some(address.to_lowercase()),
+            Err(_) => return Err(AdminError::InvalidEmailAddress)
+        },
+        None => None
+    };
+    if let Some(token) = &config.email_token {
+        if token.len() < MIN_EMAIL_TOKEN_LENGTH || !token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+            return Err(AdminError::InvalidEmailToken);
+        }
+    }
+    let mt_signature_version = match config.mt_signature_version {
+        None | Some(1) => 1,
+        Some(2) => 2,
+        Some(_) => return Err(AdminError::InvalidSignatureVersion)
+    };
+    let webhook_version = match config.webhook_version {
+        None | Some(1) => 1,
+        Some(2) => 2,
+        Some(_) => return Err(AdminError::InvalidWebhookVersion)
+    };
+
+    Ok(crate::models::Target {
+        id,
+        endpoint: config.endpoint,
+        mqtt_broker: config.mqtt_broker,
+        mqtt_username: config.mqtt_username,
+        mqtt_password: config.mqtt_password,
+        email_address,
+        mt_signature_version,
+        webhook_signing: config.webhook_signing.map_or(crate::models::WebhookSigning::Hmac, Into::into),
+        webhook_version,
+        cloud_events: config.cloud_events.map(Into::into),
+        email_token: config.email_token,
+    })
+}
+
+/// Creates a target, along with its first HMAC key
+pub(crate) async fn create_target(
+    db_conn: &mut crate::DBConn, config: crate::types::TargetConfig,
+) -> Result<crate::types::NewTarget, AdminError> {
+    let target = make_target(uuid::Uuid::new_v4(), config)?;
+    let key = crate::models::TargetKey {
+        id: uuid::Uuid::new_v4(),
+        target: target.id,
+        key: crate::keys::generate_key(),
+        created: chrono::Utc::now().naive_utc(),
+        expires: None,
+    };
+
+    let target_ref = &target;
+    let key_ref = &key;
+    db_conn.transaction::<_, diesel::result::Error, _>(|conn| async move {
+        diesel::insert_into(crate::schema::targets::dsl::targets)
+            .values(target_ref)
+            .execute(conn).await?;
+
+        diesel::insert_into(crate::schema::target_keys::dsl::target_keys)
+            .values(key_ref)
+            .execute(conn).await?;
+
+        Ok(())
+    }.scope_boxed()).await?;
+
+    crate::audit::record(db_conn, Some(target.id), "target_created", format!("Target {} created", target.id)).await;
+
+    Ok(crate::types::NewTarget {
+        target: target_info(target),
+        key: crate::types::NewTargetKey {
+            id: key.id,
+            key: BASE64_STANDARD.encode(&key.key),
+            created: key.created.and_utc(),
+        },
+    })
+}
+
+pub(crate) async fn get_target(
+    db_conn: &mut crate::DBConn, target_id: uuid::Uuid,
+) -> Result<crate::types::Target, AdminError> {
+    crate::schema::targets::dsl::targets
+        .filter(crate::schema::targets::dsl::id.eq(target_id))
+        .get_result::<crate::models::Target>(db_conn).await
+        .optional()?
+        .map(target_info)
+        .ok_or(AdminError::NotFound)
+}
+
+pub(crate) async fn list_targets(db_conn: &mut crate::DBConn) -> Result<Vec<crate::types::Target>, AdminError> {
+    Ok(crate::schema::targets::dsl::targets
+        .order_by(crate::schema::targets::dsl::id.asc())
+        .load::<crate::models::Target>(db_conn).await?
+        .into_iter()
+        .map(target_info)
+        .collect())
+}
+
+/// Replaces a target's configuration. Its keys are managed separately, and secrets not given
+/// are left as they are, since they can't be read back to send them again.
+pub(crate) async fn update_target(
+    db_conn: &mut crate::DBConn, target_id: uuid::Uuid, config: crate::types::TargetConfig,
+) -> Result<crate::types::Target, AdminError> {
+    let keep_mqtt_password = config.mqtt_password.is_none();
+    let keep_email_token = config.email_token.is_none();
+    let target = make_target(target_id, config)?;
+
+    let target = diesel::update(crate::schema::targets::dsl::targets)
+        .filter(crate::schema::targets::dsl::id.eq(target_id))
+        .set((
+            crate::schema::targets::dsl::endpoint.eq(target.endpoint),
+            crate::schema::targets::dsl::mqtt_broker.eq(target.mqtt_broker),
+            crate::schema::targets::dsl::mqtt_username.eq(target.mqtt_username),
+            (!keep_mqtt_password).then(|| crate::schema::targets::dsl::mqtt_password.eq(target.mqtt_password)),
+            crate::schema::targets::dsl::email_address.eq(target.email_address),
+            crate::schema::targets::dsl::mt_signature_version.eq(target.mt_signature_version),
+            crate::schema::targets::dsl::webhook_signing.eq(target.webhook_signing),
+            crate::schema::targets::dsl::webhook_version.eq(target.webhook_version),
+            crate::schema::targets::dsl::cloud_events.eq(target.cloud_events),
+            (!keep_email_token).then(|| crate::schema::targets::dsl::email_token.eq(target.email_token)),
+        ))
+        .get_result::<crate::models::Target>(db_conn).await
+        .optional()?
+        .ok_or(AdminError::NotFound)?;
+
+    crate::audit::record(db_conn, Some(target.id), "target_updated", format!("Target {} updated", target.id)).await;
+
+    Ok(target_info(target))
+}
+
+/// Deletes a target. Targets that still own devices or have sent messages can't be deleted.
+pub(crate) async fn delete_target(db_conn: &mut crate::DBConn, target_id: uuid::Uuid) -> Result<(), AdminError> {
+    let deleted = diesel::delete(crate::schema::targets::dsl::targets)
+        .filter(crate::schema::targets::dsl::id.eq(target_id))
+        .execute(db_conn).await?;
+    if deleted == 0 {
+        return Err(AdminError::NotFound);
+    }
+
+    crate::audit::record(db_conn, None, "target_deleted", format!("Target {} deleted", target_id)).await;
+
+    Ok(())
+}
+
+/// Validates a device's configuration, returning the device to be stored
+async fn make_device(
+    db_conn: &mut crate::DBConn, id: uuid::Uuid, config: crate::types::DeviceConfig,
+) -> Result<crate::models::Device, AdminError> {
+    if !valid_imei(&config.imei) {
+        return Err(AdminError::InvalidIMEI);
+    }
+    crate::mt::check_reference(None, config.metadata.as_ref())
+        .map_err(|_| AdminError::InvalidMetadata)?;
+
+    let target_exists = diesel::select(diesel::dsl::exists(
+        crate::schema::targets::dsl::targets
+            .filter(crate::schema::targets::dsl::id.eq(config.target))
+    )).get_result::<bool>(db_conn).await?;
+    if !target_exists {
+        return Err(AdminError::UnknownTarget);
+    }
+
+    if let Some(provider) = config.mt_provider {
+        let provider_exists = diesel::select(diesel::dsl::exists(
+            crate::schema::mt_providers::dsl::mt_providers
+                .filter(crate::schema::mt_providers::dsl::id.eq(provider))
+        )).get_result::<bool>(db_conn).await?;
+        if !provider_exists {
+            return Err(AdminError::UnknownProvider);
+        }
+    }
+
+    // MO messages are routed by IMEI, so each can only belong to one device. This gives a clear
+    // error up front; the unique index catches devices created concurrently.
+    let imei_taken = diesel::select(diesel::dsl::exists(
+        crate::schema::devices::dsl::devices
+            .filter(crate::schema::devices::dsl::imei.eq(&config.imei))
+            .filter(crate::schema::devices::dsl::id.ne(id))
+    )).get_result::<bool>(db_conn).await?;
+    if imei_taken {
+        return Err(AdminError::IMEITaken);
+    }
+
+    Ok(crate::models::Device {
+        id,
+        imei: config.imei,
+        target: config.target,
+        mt_provider: config.mt_provider,
+        metadata: config.metadata,
+    })
+}
+
+pub(crate) async fn create_device(
+    db_conn: &mut crate::DBConn, config: crate::types::DeviceConfig,
+) -> Result<crate::types::AdminDevice, AdminError> {
+    let device = make_device(db_conn, uuid::Uuid::new_v4(), config).await?;
+
+    diesel::insert_into(crate::schema::devices::dsl::devices)
+        .values(&device)
+        .execute(db_conn).await?;
+
+    crate::audit::record(
+        db_conn, Some(device.target), "device_created",
+        format!("Device {} created for IMEI {}", device.id, device.imei)
+    ).await;
+
+    Ok(device_info(device))
+}
+
+pub(crate) async fn find_device(
+    db_conn: &mut crate::DBConn, imei: &str,
+) -> Result<crate::types::AdminDevice, AdminError> {
+    crate::schema::devices::dsl::devices
+        .filter(crate::schema::devices::dsl::imei.eq(imei))
+        .get_result::<crate::models::Device>(db_conn).await
+        .optional()?
+        .map(device_info)
+        .ok_or(AdminError::NotFound)
+}
+
+pub(crate) async fn get_device(
+    db_conn: &mut crate::DBConn, device_id: uuid::Uuid,
+) -> Result<crate::types::AdminDevice, AdminError> {
+    crate::schema::devices::dsl::devices
+        .filter(crate::schema::devices::dsl::id.eq(device_id))
+        .get_result::<crate::models::Device>(db_conn).await
+        .optional()?
+        .map(device_info)
+        .ok_or(AdminError::NotFound)
+}
+
+pub(crate) async fn list_devices(
+    db_conn: &mut crate::DBConn, target_id: Option<uuid::Uuid>,
+) -> Result<Vec<crate::types::AdminDevice>, AdminError> {
+    let mut query = crate::schema::devices::dsl::devices.into_boxed();
+    if let Some(target_id) = target_id {
+        query = query.filter(crate::schema::devices::dsl::target.eq(target_id));
+    }
+
+    Ok(query
+        .order_by(crate::schema::devices::dsl::imei.asc())
+        .load::<crate::models::Device>(db_conn).await?
+        .into_iter()
+        .map(device_info)
+        .collect())
+}
+
+/// Replaces a device's configuration. When a device is moved to another target, its tags and group
+/// memberships are removed, and MO messages it sent before the move stay with the old target.
+pub(crate) async fn update_device(
+    db_conn: &mut crate::DBConn, device_id: uuid::Uuid, config: crate::types::DeviceConfig,
+) -> Result<crate::types::AdminDevice, AdminError> {
+    let existing = crate::schema::devices::dsl::devices
+        .filter(crate::schema::devices::dsl::id.eq(device_id))
+        .get_result::<crate::models::Device>(db_conn).await
+        .optional()?
+        .ok_or(AdminError::NotFound)?;
+    let device = make_device(db_conn, device_id, config).await?;
+
+    let device_ref = &device;
+    let moved = existing.target != device.target;
+    db_conn.transaction::<_, diesel::result::Error, _>(|conn| async move {
+        diesel::update(crate::schema::devices::dsl::devices)
+            .filter(crate::schema::devices::dsl::id.eq(device_ref.id))
+            .set((
+                crate::schema::devices::dsl::imei.eq(&device_ref.imei),
+                crate::schema::devices::dsl::target.eq(device_ref.target),
+                crate::schema::devices::dsl::mt_provider.eq(device_ref.mt_provider),
+                crate::schema::devices::dsl::metadata.eq(&device_ref.metadata),
+            ))
+            .execute(conn).await?;
+
+        if moved {
+            diesel::delete(crate::schema::device_tags::dsl::device_tags)
+                .filter(crate::schema::device_tags::dsl::device.eq(device_ref.id))
+                .execute(conn).await?;
+            diesel::delete(crate::schema::device_group_members::dsl::device_group_members)
+                .filter(crate::schema::device_group_members::dsl::device.eq(device_ref.id))
+                .execute(conn).await?;
+        }
+
+        Ok(())
+    }.scope_boxed()).await?;
+
+    crate::audit::record(
+        db_conn, Some(device.target), "device_updated",
+        format!("Device {} updated, IMEI {}", device.id, device.imei)
+    ).await;
+    if moved {
+        crate::audit::record(
+            db_conn, Some(existing.target), "device_updated",
+            format!("Device {} moved to target {}", device.id, device.target)
+        ).await;
+    }
+
+    Ok(device_info(device))
+}
+
+pub(crate) async fn delete_device(db_conn: &mut crate::DBConn, device_id: uuid::Uuid) -> Result<(), AdminError> {
+    let device = diesel::delete(crate::schema::devices::dsl::devices)
+        .filter(crate::schema::devices::dsl::id.eq(device_id))
+        .get_result::<crate::models::Device>(db_conn).await
+        .optional()?
+        .ok_or(AdminError::NotFound)?;
+
+    crate::audit::record(
+        db_conn, Some(device.target), "device_deleted",
+        format!("Device {} deleted, IMEI {}", device.id, device.imei)
+    ).await;
+
+    Ok(())
+}
\ No newline at end of file
@@ -0,0 +1,30 @@
+use diesel_async::RunQueryDsl;
+
+/// Records an event in the audit log. Failures are logged, but otherwise ignored, so that
+/// auditing can't block the action being audited.
+pub(crate) async fn record(db_conn: &mut crate::DBConn, target: Option<uuid::Uuid>, event: &str, detail: String) {
+    record_message(db_conn, target, None, event, detail).await
+}
+
+/// Records an event about an MO or MT message in the audit log, so that it can be found by the
+/// message's ID
+pub(crate) async fn record_message(
+    db_conn: &mut crate::DBConn, target: Option<uuid::Uuid>, message: Option<uuid::Uuid>, event: &str, detail: String,
+) {
+    info!("Audit: {} (target {:?}): {}", event, target, detail);
+
+    let entry = crate::models::AuditLogEntry {
+        id: uuid::Uuid::new_v4(),
+        time: chrono::Utc::now().naive_utc(),
+        target,
+        event: event.to_string(),
+        detail,
+        message,
+    };
+
+    if let Err(err) = diesel::insert_into(crate::schema::audit_log::dsl::audit_log)
+        .values(&entry)
+        .execute(db_conn).await {
+        error!("Failed to insert audit log entry: {}", err);
+    }
+}
\ No newline at end of file
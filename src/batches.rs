@@ -0,0 +1,208 @@
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use diesel_async::scoped_futures::ScopedFutureExt;
+
+/// Maximum number of devices listed in a single batch request; a device group may be larger
+pub const MAX_BATCH_SIZE: usize = 1000;
+/// Number of messages inserted per query, keeping under Postgres's limit on bind parameters
+const INSERT_CHUNK_SIZE: usize = 1000;
+
+#[derive(Debug)]
+pub enum BatchError {
+    NotFound,
+    Empty,
+    TooLarge,
+    /// A device appears more than once in the batch, with the index of the repeat
+    DuplicateIMEI(usize),
+    UnknownGroup,
+    /// A message in the batch was invalid, with its index
+    Invalid(usize, crate::mt::SubmitError),
+    IdempotencyConflict,
+    Internal,
+}
+
+/// Returns the batch previously created with an idempotency key, if any
+async fn idempotent_batch(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target, key: &crate::mt::IdempotencyKey,
+) -> Result<Option<crate::models::MTBatch>, BatchError> {
+    match crate::mt::idempotent_request(db_conn, target, key).await {
+        Ok(Some(crate::models::IdempotencyKey { batch: Some(id), .. })) => get(db_conn, target, id).await.map(Some),
+        // The key was used to submit a single message
+        Ok(Some(_)) | Err(crate::mt::SubmitError::IdempotencyConflict) => Err(BatchError::IdempotencyConflict),
+        Ok(None) => Ok(None),
+        Err(_) => Err(BatchError::Internal),
+    }
+}
+
+/// Creates a batch of MT messages, accepting every message or none, for the scheduler to send
+pub(crate) async fn create(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target, request: crate::types::NewMTBatch,
+    idempotency_key: Option<crate::mt::IdempotencyKey>,
+) -> Result<crate::models::MTBatch, BatchError> {
+    if let Some(key) = &idempotency_key {
+        if let Some(batch) = idempotent_batch(db_conn, target, key).await? {
+            return Ok(batch);
+        }
+    }
+
+    if request.imeis.len() + request.messages.len() > MAX_BATCH_SIZE {
+        return Err(BatchError::TooLarge);
+    }
+
+    // Devices in a group are sent the batch payload, the same as those listed by IMEI
+    let group_imeis = match request.group {
+        Some(group_id) => {
+            let group = match crate::devices::get_group(db_conn, target, group_id).await {
+                Ok(g) => g,
+                Err(crate::devices::DeviceError::NotFound) => return Err(BatchError::UnknownGroup),
+                Err(_) => return Err(BatchError::Internal),
+            };
+            crate::devices::group_imeis(db_conn, group.id).await.map_err(|err| {
+                error!("Failed to get device group members: {}", err);
+                BatchError::Internal
+            })?
+        }
+        None => Vec::new()
+    };
+
+    let destinations = request.imeis.into_iter()
+        .chain(group_imeis)
+        .map(|imei| (imei, request.payload.clone()))
+        .chain(request.messages.into_iter().map(|m| (m.imei, m.payload.or_else(|| request.payload.clone()))))
+        .collect::<Vec<_>>();
+
+    if destinations.is_empty() {
+        return Err(BatchError::Empty);
+    }
+
+    let batch = crate::models::MTBatch {
+        id: uuid::Uuid::new_v4(),
+        target: target.id,
+        client_reference: request.client_reference.clone(),
+        notify: request.notify,
+        created: chrono::Utc::now().naive_utc(),
+        completed: None,
+        device_group: request.group,
+    };
+
+    let mut seen = std::collections::HashSet::new();
+    let mut mt_messages = Vec::with_capacity(destinations.len());
+    for (i, (imei, payload)) in destinations.into_iter().enumerate() {
+        if !seen.insert(imei.clone()) {
+            return Err(BatchError::DuplicateIMEI(i));
+        }
+
+        let payload = payload.ok_or(BatchError::Invalid(i, crate::mt::SubmitError::InvalidPayload))?;
+        let mut mt_message = crate::mt::prepare_message(db_conn, target, crate::types::MTMessage {
+            imei,
+            payload,
+            priority: request.priority,
+            client_reference: request.client_reference.clone(),
+            metadata: request.metadata.clone(),
+            not_before: request.not_before,
+            expires_at: request.expires_at,
+        }).await.map_err(|err| match err {
+            crate::mt::SubmitError::Internal => BatchError::Internal,
+            err => BatchError::Invalid(i, err)
+        })?;
+        mt_message.processing_status = crate::models::ProcessingStatus::Scheduled;
+        mt_message.batch = Some(batch.id);
+        mt_messages.push(mt_message);
+    }
+
+    let idempotency_key_row = idempotency_key.as_ref().map(|k| crate::models::IdempotencyKey {
+        target: target.id,
+        key: k.key.clone(),
+        request_hash: k.request_hash.clone(),
+        message: None,
+        created: batch.created,
+        batch: Some(batch.id),
+    });
+
+    let batch_ref = &batch;
+    let mt_messages_ref = &mt_messages;
+    let idempotency_key_row_ref = &idempotency_key_row;
+    if let Err(err) = db_conn.transaction::<_, diesel::result::Error, _>(|conn| async move {
+        diesel::insert_into(crate::schema::mt_batches::dsl::mt_batches)
+            .values(batch_ref)
+            .execute(conn).await?;
+
+        for chunk in mt_messages_ref.chunks(INSERT_CHUNK_SIZE) {
+            diesel::insert_into(crate::schema::mt_messages::dsl::mt_messages)
+                .values(chunk)
+                .execute(conn).await?;
+        }
+
+        if let Some(k) = idempotency_key_row_ref {
+            diesel::insert_into(crate::schema::idempotency_keys::dsl::idempotency_keys)
+                .values(k)
+                .execute(conn).await?;
+        }
+
+        Ok(())
+    }.scope_boxed()).await {
+        return match (err, &idempotency_key) {
+            // A concurrent request with the same key got there first
+            (diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _), Some(key)) =>
+                match idempotent_batch(db_conn, target, key).await? {
+                    Some(b) => Ok(b),
+                    None => Err(BatchError::Internal)
+                },
+            (err, _) => {
+                error!("Failed to insert batch: {}", err);
+                Err(BatchError::Internal)
+            }
+        };
+    }
+
+    Ok(batch)
+}
+
+pub(crate) async fn get(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target, batch_id: uuid::Uuid,
+) -> Result<crate::models::MTBatch, BatchError> {
+    match crate::schema::mt_batches::dsl::mt_batches
+        .filter(crate::schema::mt_batches::dsl::id.eq(batch_id))
+        .filter(crate::schema::mt_batches::dsl::target.eq(&target.id))
+        .get_result::<crate::models::MTBatch>(db_conn).await
+        .optional() {
+        Ok(Some(b)) => Ok(b),
+        Ok(None) => Err(BatchError::NotFound),
+        Err(err) => {
+            error!("Failed to get batch: {}", err);
+            Err(BatchError::Internal)
+        }
+    }
+}
+
+/// Counts the messages in a batch by status
+pub(crate) async fn batch_status(
+    db_conn: &mut crate::DBConn, batch: crate::models::MTBatch,
+) -> diesel::result::QueryResult<crate::types::MTBatchStatus> {
+    let counts = crate::schema::mt_messages::dsl::mt_messages
+        .filter(crate::schema::mt_messages::dsl::batch.eq(batch.id))
+        .group_by((
+            crate::schema::mt_messages::dsl::processing_status,
+            crate::schema::mt_messages::dsl::message_status,
+        ))
+        .select((
+            crate::schema::mt_messages::dsl::processing_status,
+            crate::schema::mt_messages::dsl::message_status,
+            diesel::dsl::count_star(),
+        ))
+        .load::<(crate::models::ProcessingStatus, Option<crate::models::MessageStatus>, i64)>(db_conn).await?;
+
+    Ok(crate::types::MTBatchStatus {
+        id: batch.id,
+        client_reference: batch.client_reference,
+        group: batch.device_group,
+        created: batch.created.and_utc(),
+        completed: batch.completed.map(|t| t.and_utc()),
+        total: counts.iter().map(|(_, _, c)| *c as u32).sum(),
+        counts: counts.into_iter().map(|(processing_status, message_status, count)| crate::types::MTBatchCount {
+            processing_status: processing_status.into(),
+            message_status: message_status.map(Into::into),
+            count: count as u32,
+        }).collect(),
+    })
+}
@@ -0,0 +1,290 @@
+use base64::prelude::*;
+use clap::Parser;
+
+#[derive(Parser, Debug)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(clap::Args, Debug)]
+struct APIArgs {
+    /// Base URL of the Kosmos API server
+    #[arg(long, env = "KOSMOS_URL")]
+    url: reqwest::Url,
+
+    #[arg(long, env = "KOSMOS_TARGET_ID")]
+    target_id: uuid::Uuid,
+
+    /// The target's HMAC key, Base64 encoded
+    #[arg(long, env = "KOSMOS_KEY")]
+    key: String,
+
+    #[arg(long, default_value_t = 2, value_parser = clap::value_parser!(u8).range(1..=2))]
+    signature_version: u8,
+}
+
+#[derive(clap::Subcommand, Debug)]
+enum Command {
+    /// Sign and submit an MT message, printing its ID
+    #[command(group(clap::ArgGroup::new("payload").required(true)))]
+    Submit {
+        #[command(flatten)]
+        api: APIArgs,
+
+        imei: String,
+
+        /// File to read the payload from
+        #[arg(long, group = "payload")]
+        file: Option<std::path::PathBuf>,
+        /// Payload as hex
+        #[arg(long, group = "payload")]
+        hex: Option<String>,
+        /// Payload as Base64
+        #[arg(long, group = "payload")]
+        base64: Option<String>,
+
+        #[arg(long, value_parser = clap::value_parser!(u8).range(1..=5))]
+        priority: Option<u8>,
+        #[arg(long)]
+        client_reference: Option<String>,
+        /// Metadata as a JSON object
+        #[arg(long)]
+        metadata: Option<String>,
+        /// RFC 3339 time to hold the message until
+        #[arg(long)]
+        not_before: Option<chrono::DateTime<chrono::Utc>>,
+        /// RFC 3339 time after which the message won't be delivered
+        #[arg(long)]
+        expires_at: Option<chrono::DateTime<chrono::Utc>>,
+        #[arg(long)]
+        idempotency_key: Option<String>,
+    },
+    /// Print the status of an MT message
+    Status {
+        #[command(flatten)]
+        api: APIArgs,
+
+        id: uuid::Uuid,
+
+        /// Poll until the message has been delivered or has failed
+        #[arg(long)]
+        wait: bool,
+
+        /// Seconds between polls
+        #[arg(long, default_value_t = 5)]
+        interval: u64,
+    },
+    /// Verify a captured webhook body against a target's key
+    Verify {
+        /// The target's HMAC key, Base64 encoded. Not read from KOSMOS_KEY, so that it can't clash with --jwks
+        #[arg(long, required_unless_present = "jwks")]
+        key: Option<String>,
+
+        /// File containing Kosmos's JWK set, for targets whose webhooks are signed with Ed25519
+        #[arg(long, conflicts_with_all = ["key", "mac"])]
+        jwks: Option<std::path::PathBuf>,
+
+        /// Value of the Kosmos-MAC header
+        #[arg(long, required_unless_present = "signature")]
+        mac: Option<String>,
+
+        /// Value of the Kosmos-Signature header
+        #[arg(long)]
+        signature: Option<String>,
+
+        /// A `ce-` header of a binary mode CloudEvent, as `name: value`; may be repeated
+        #[arg(long = "header")]
+        headers: Vec<String>,
+
+        /// File containing the exact request body, or - for standard input
+        body: std::path::PathBuf,
+    },
+}
+
+fn decode_key(key: &str) -> Vec<u8> {
+    match BASE64_STANDARD.decode(key.trim()) {
+        Ok(k) => k,
+        Err(err) => {
+            eprintln!("Invalid key: {}", err);
+            std::process::exit(2);
+        }
+    }
+}
+
+fn api_client(api: APIArgs) -> kosmos::client::Client {
+    let key = decode_key(&api.key);
+    let signature_version = match api.signature_version {
+        1 => kosmos::client::SignatureVersion::V1,
+        _ => kosmos::client::SignatureVersion::V2,
+    };
+    kosmos::client::Client::new(api.url, api.target_id, key, signature_version)
+}
+
+fn read_file(path: &std::path::Path) -> Vec<u8> {
+    let result = if path == std::path::Path::new("-") {
+        let mut buf = Vec::new();
+        std::io::Read::read_to_end(&mut std::io::stdin(), &mut buf).map(|_| buf)
+    } else {
+        std::fs::read(path)
+    };
+    match result {
+        Ok(d) => d,
+        Err(err) => {
+            eprintln!("Failed to read {}: {}", path.display(), err);
+            std::process::exit(2);
+        }
+    }
+}
+
+#[tokio::main]
+async fn main() {
+    pretty_env_logger::init();
+    openssl_probe::init_ssl_cert_env_vars();
+    let args = Args::parse();
+
+    match args.command {
+        Command::Submit {
+            api, imei, file, hex, base64, priority, client_reference, metadata, not_before, expires_at,
+            idempotency_key
+        } => {
+            let payload = match (file, hex, base64) {
+                (Some(path), _, _) => read_file(&path),
+                (_, Some(h), _) => match hex::decode(h.trim()) {
+                    Ok(d) => d,
+                    Err(err) => {
+                        eprintln!("Invalid hex payload: {}", err);
+                        std::process::exit(2);
+                    }
+                },
+                (_, _, Some(b)) => match BASE64_STANDARD.decode(b.trim()) {
+                    Ok(d) => d,
+                    Err(err) => {
+                        eprintln!("Invalid Base64 payload: {}", err);
+                        std::process::exit(2);
+                    }
+                },
+                _ => unreachable!(),
+            };
+            let metadata = match metadata.map(|m| serde_json::from_str::<serde_json::Value>(&m)).transpose() {
+                Ok(m) => m,
+                Err(err) => {
+                    eprintln!("Invalid metadata: {}", err);
+                    std::process::exit(2);
+                }
+            };
+
+            let client = api_client(api);
+            match client.submit_mt(&kosmos::types::MTMessage {
+                imei,
+                payload: BASE64_STANDARD.encode(payload),
+                priority,
+                client_reference,
+                metadata,
+                not_before,
+                expires_at,
+            }, idempotency_key.as_deref()).await {
+                Ok(id) => println!("{}", id),
+                Err(err) => {
+                    eprintln!("Failed to submit message: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        Command::Status { api, id, wait, interval } => {
+            let client = api_client(api);
+            loop {
+                let status = match client.get_mt(id).await {
+                    Ok(s) => s,
+                    Err(err) => {
+                        eprintln!("Failed to get message status: {}", err);
+                        std::process::exit(1);
+                    }
+                };
+                let finished = matches!(
+                    status.processing_status,
+                    kosmos::types::ProcessingStatus::Done | kosmos::types::ProcessingStatus::Failed
+                );
+                if !wait || finished {
+                    println!("{}", serde_json::to_string_pretty(&status).unwrap());
+                    break;
+                }
+                tokio::time::sleep(std::time::Duration::from_secs(interval)).await;
+            }
+        }
+        Command::Verify { key, jwks, mac, signature, headers, body } => {
+            let headers = headers.iter()
+                .map(|h| match h.split_once(':') {
+                    Some((name, value)) => (name.trim(), value.trim()),
+                    None => {
+                        eprintln!("Invalid header: {}", h);
+                        std::process::exit(2);
+                    }
+                })
+                .collect::<Vec<_>>();
+            let body = read_file(&body);
+            let content = kosmos::signing::webhook_signed_content(headers, &body);
+            let mut valid = true;
+
+            let key = key.map(|k| decode_key(&k));
+            let jwks = jwks.map(|path| match serde_json::from_slice::<kosmos::types::JWKSet>(&read_file(&path)) {
+                Ok(j) => j,
+                Err(err) => {
+                    eprintln!("Invalid JWK set: {}", err);
+                    std::process::exit(2);
+                }
+            });
+
+            if let (Some(mac), Some(key)) = (mac, &key) {
+                if kosmos::signing::verify_webhook_mac(key, &body, &mac) {
+                    println!("Kosmos-MAC: valid");
+                } else {
+                    println!("Kosmos-MAC: invalid");
+                    valid = false;
+                }
+            }
+
+            if let Some(signature) = signature {
+                let header = match kosmos::signing::parse_signature_header(&signature) {
+                    Some(h) => h,
+                    None => {
+                        println!("Kosmos-Signature: malformed");
+                        std::process::exit(1);
+                    }
+                };
+                let result = match (&key, &jwks) {
+                    (Some(_), _) if header.v1.is_none() => Err("not signed with an HMAC key"),
+                    (Some(key), _) => Ok(kosmos::signing::verify_webhook_signature(key, &header, &content)),
+                    (None, Some(jwks)) => match jwks.keys.iter().find(|k| k.kid == header.key_id) {
+                        Some(jwk) => match BASE64_URL_SAFE_NO_PAD.decode(&jwk.x) {
+                            Ok(public_key) => Ok(kosmos::signing::verify_webhook_ed25519_signature(
+                                &public_key, &header, &content
+                            )),
+                            Err(_) => Err("key in JWK set is malformed"),
+                        },
+                        None => Err("signed with a key not in the JWK set"),
+                    },
+                    (None, None) => unreachable!(),
+                };
+                match result {
+                    Ok(true) => {
+                        let age = chrono::Utc::now().timestamp() - header.timestamp;
+                        println!("Kosmos-Signature: valid, signed with key {} {} seconds ago", header.key_id, age);
+                    }
+                    Ok(false) => {
+                        println!("Kosmos-Signature: invalid");
+                        valid = false;
+                    }
+                    Err(reason) => {
+                        println!("Kosmos-Signature: {}", reason);
+                        valid = false;
+                    }
+                }
+            }
+
+            if !valid {
+                std::process::exit(1);
+            }
+        }
+    }
+}
\ No newline at end of file
@@ -10,6 +10,13 @@ struct Args {
 
     #[arg(long, env)]
     db_url: String,
+
+    #[arg(long, env)]
+    rockblock_public_key: Option<std::path::PathBuf>,
+
+    /// Bearer token for the admin API, which is disabled if this isn't set
+    #[arg(long, env)]
+    admin_token: Option<String>,
 }
 
 #[tokio::main]
@@ -27,5 +34,5 @@ async fn main() {
     let db_config = diesel_async::pooled_connection::AsyncDieselConnectionManager::<diesel_async::AsyncPgConnection>::new(args.db_url);
     let db_pool = std::sync::Arc::new(mobc::Pool::new(db_config));
 
-    kosmos::http::run(args.listen_address, args.amqp_addr, db_pool).await;
+    kosmos::http::run(args.listen_address, args.amqp_addr, db_pool, args.rockblock_public_key, args.admin_token).await;
 }
\ No newline at end of file
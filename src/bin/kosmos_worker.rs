@@ -7,6 +7,12 @@ struct Args {
 
     #[arg(long, env)]
     db_url: String,
+
+    #[arg(long, env)]
+    smtp_url: Option<String>,
+
+    #[arg(long, env, default_value = "sbdservice@localhost")]
+    email_from: lettre::message::Mailbox,
 }
 
 #[tokio::main]
@@ -24,5 +30,5 @@ async fn main() {
     let db_config = diesel_async::pooled_connection::AsyncDieselConnectionManager::<diesel_async::AsyncPgConnection>::new(args.db_url);
     let db_pool = std::sync::Arc::new(mobc::Pool::new(db_config));
 
-    kosmos::worker::run_worker(args.amqp_addr, db_pool).await;
+    kosmos::worker::run_worker(args.amqp_addr, db_pool, args.smtp_url, args.email_from).await;
 }
\ No newline at end of file
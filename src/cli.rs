@@ -0,0 +1,432 @@
+use base64::prelude::*;
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::RunQueryDsl;
+
+#[derive(clap::Subcommand, Debug)]
+pub enum Command {
+    /// Manage targets
+    #[command(subcommand)]
+    Target(TargetCommand),
+    /// Manage the HMAC keys of a target
+    #[command(subcommand)]
+    Key(KeyCommand),
+    /// Manage the Ed25519 keys webhooks are signed with
+    #[command(subcommand)]
+    SigningKey(SigningKeyCommand),
+    /// Manage devices
+    #[command(subcommand)]
+    Device(DeviceCommand),
+    /// Show an MO or MT message, and the audit log entries that mention it
+    Message {
+        id: uuid::Uuid,
+    },
+    /// Queue a failed MO or MT message to be processed again
+    Requeue {
+        id: uuid::Uuid,
+    },
+    /// Show the number of messages waiting to be processed, or that failed
+    Queues,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum TargetCommand {
+    /// Create a target, printing its first HMAC key
+    Create {
+        #[arg(long)]
+        endpoint: Option<String>,
+        #[arg(long)]
+        mqtt_broker: Option<String>,
+        #[arg(long)]
+        mqtt_username: Option<String>,
+        #[arg(long)]
+        mqtt_password: Option<String>,
+        #[arg(long)]
+        email_address: Option<String>,
+        #[arg(long)]
+        email_token: Option<String>,
+        #[arg(long, default_value_t = 1)]
+        mt_signature_version: u8,
+        #[arg(long, value_enum, default_value_t = WebhookSigning::Hmac)]
+        webhook_signing: WebhookSigning,
+        #[arg(long, default_value_t = 1)]
+        webhook_version: u8,
+        #[arg(long, value_enum)]
+        cloud_events: Option<CloudEventsMode>,
+    },
+    List,
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum WebhookSigning {
+    Hmac,
+    Ed25519,
+}
+
+impl From<WebhookSigning> for crate::types::WebhookSigning {
+    fn from(value: WebhookSigning) -> Self {
+        match value {
+            WebhookSigning::Hmac => Self::Hmac,
+            WebhookSigning::Ed25519 => Self::Ed25519,
+        }
+    }
+}
+
+#[derive(clap::ValueEnum, Clone, Copy, Debug)]
+pub enum CloudEventsMode {
+    Structured,
+    Binary,
+}
+
+impl From<CloudEventsMode> for crate::types::CloudEventsMode {
+    fn from(value: CloudEventsMode) -> Self {
+        match value {
+            CloudEventsMode::Structured => Self::Structured,
+            CloudEventsMode::Binary => Self::Binary,
+        }
+    }
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum KeyCommand {
+    /// List a target's keys that haven't expired
+    List {
+        target: uuid::Uuid,
+    },
+    /// Add a key to a target, leaving its other keys valid
+    Generate {
+        target: uuid::Uuid,
+    },
+    /// Add a key to a target, and expire its other keys after a grace period
+    Rotate {
+        target: uuid::Uuid,
+        /// Seconds until the previous keys expire, defaults to 7 days
+        #[arg(long)]
+        grace_period: Option<u32>,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum SigningKeyCommand {
+    /// List the keys published for receivers to verify webhooks with
+    List,
+    /// Add a key to be used after a delay, and expire the current keys a week after that
+    Rotate {
+        /// Seconds until the new key is used, defaults to 1 day
+        #[arg(long)]
+        publish_delay: Option<u32>,
+    },
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum DeviceCommand {
+    Add {
+        imei: String,
+        target: uuid::Uuid,
+        #[arg(long)]
+        mt_provider: Option<uuid::Uuid>,
+    },
+    List {
+        #[arg(long)]
+        target: Option<uuid::Uuid>,
+    },
+    /// Move a device to another target
+    Move {
+        imei: String,
+        target: uuid::Uuid,
+    },
+    Remove {
+        imei: String,
+    },
+}
+
+fn print_json<T: serde::Serialize>(value: &T) {
+    println!("{}", serde_json::to_string_pretty(value).unwrap());
+}
+
+/// Runs an admin command, returning whether it succeeded
+pub async fn run(db_pool: crate::DBPool, amqp_addr: String, command: Command) -> bool {
+    let mut db_conn = match db_pool.get().await {
+        Ok(c) => c,
+        Err(err) => {
+            error!("Failed to get DB connection: {}", err);
+            return false;
+        }
+    };
+
+    let result = match command {
+        Command::Target(c) => target(&mut db_conn, c).await,
+        Command::Key(c) => key(&mut db_conn, c).await,
+        Command::SigningKey(c) => signing_key(&mut db_conn, c).await,
+        Command::Device(c) => device(&mut db_conn, c).await,
+        Command::Message { id } => message(&mut db_conn, id).await,
+        Command::Requeue { id } => requeue(&mut db_conn, amqp_addr, id).await,
+        Command::Queues => queues(&mut db_conn).await,
+    };
+
+    match result {
+        Ok(()) => true,
+        Err(err) => {
+            error!("{}", err);
+            false
+        }
+    }
+}
+
+async fn target(db_conn: &mut crate::DBConn, command: TargetCommand) -> Result<(), String> {
+    match command {
+        TargetCommand::Create {
+            endpoint, mqtt_broker, mqtt_username, mqtt_password, email_address, email_token, mt_signature_version,
+            webhook_signing, webhook_version, cloud_events
+        } => {
+            let target = crate::admin::create_target(db_conn, crate::types::TargetConfig {
+                endpoint,
+                mqtt_broker,
+                mqtt_username,
+                mqtt_password,
+                email_address,
+                email_token,
+                mt_signature_version: Some(mt_signature_version),
+                webhook_signing: Some(webhook_signing.into()),
+                webhook_version: Some(webhook_version),
+                cloud_events: cloud_events.map(Into::into),
+            }).await.map_err(|e| format!("Failed to create target: {:?}", e))?;
+            print_json(&target);
+        }
+        TargetCommand::List => {
+            let targets = crate::admin::list_targets(db_conn).await
+                .map_err(|e| format!("Failed to list targets: {:?}", e))?;
+            print_json(&targets);
+        }
+    }
+    Ok(())
+}
+
+async fn key(db_conn: &mut crate::DBConn, command: KeyCommand) -> Result<(), String> {
+    let target_id = match &command {
+        KeyCommand::List { target } | KeyCommand::Generate { target } | KeyCommand::Rotate { target, .. } => *target
+    };
+    crate::admin::get_target(db_conn, target_id).await
+        .map_err(|e| format!("Failed to get target: {:?}", e))?;
+
+    let key = match command {
+        KeyCommand::List { .. } => {
+            let keys = crate::keys::active_keys(target_id, db_conn).await
+                .map_err(|e| format!("Failed to get target keys: {}", e))?;
+            print_json(&keys.into_iter().map(|k| crate::types::TargetKey {
+                id: k.id,
+                created: k.created.and_utc(),
+                expires: k.expires.map(|e| e.and_utc()),
+            }).collect::<Vec<_>>());
+            return Ok(());
+        }
+        KeyCommand::Generate { .. } => {
+            let key = crate::keys::add(target_id, db_conn).await
+                .map_err(|e| format!("Failed to generate key: {}", e))?;
+            crate::audit::record(db_conn, Some(target_id), "key_created", format!("Key {} created", key.id)).await;
+            key
+        }
+        KeyCommand::Rotate { grace_period, .. } => {
+            let grace_period = grace_period.map_or(crate::keys::DEFAULT_GRACE_PERIOD, |g| g as i64);
+            let key = crate::keys::rotate(target_id, grace_period, db_conn).await
+                .map_err(|e| format!("Failed to rotate keys: {}", e))?;
+            crate::audit::record(
+                db_conn, Some(target_id), "key_rotated",
+                format!("Key {} created, previous keys expire in {} seconds", key.id, grace_period)
+            ).await;
+            key
+        }
+    };
+
+    print_json(&crate::types::NewTargetKey {
+        id: key.id,
+        key: BASE64_STANDARD.encode(&key.key),
+        created: key.created.and_utc(),
+    });
+    Ok(())
+}
+
+async fn signing_key(db_conn: &mut crate::DBConn, command: SigningKeyCommand) -> Result<(), String> {
+    let keys = match command {
+        SigningKeyCommand::List => crate::jwks::published_keys(db_conn).await
+            .map_err(|e| format!("Failed to get signing keys: {}", e))?,
+        SigningKeyCommand::Rotate { publish_delay } => {
+            let publish_delay = publish_delay.map_or(crate::jwks::DEFAULT_PUBLISH_DELAY, |d| d as i64);
+            let key = crate::jwks::rotate(db_conn, publish_delay).await
+                .map_err(|e| format!("Failed to rotate signing keys: {}", e))?;
+            crate::audit::record(
+                db_conn, None, "signing_key_rotated",
+                format!("Signing key {} created, used from {}", key.id, key.activates.and_utc())
+            ).await;
+            vec![key]
+        }
+    };
+
+    print_json(&keys.into_iter().map(|k| crate::types::SigningKey {
+        id: k.id,
+        created: k.created.and_utc(),
+        activates: k.activates.and_utc(),
+        expires: k.expires.map(|e| e.and_utc()),
+    }).collect::<Vec<_>>());
+    Ok(())
+}
+
+async fn device(db_conn: &mut crate::DBConn, command: DeviceCommand) -> Result<(), String> {
+    match command {
+        DeviceCommand::Add { imei, target, mt_provider } => {
+            let device = crate::admin::create_device(db_conn, crate::types::DeviceConfig {
+                imei,
+                target,
+                mt_provider,
+                metadata: None,
+            }).await.map_err(|e| format!("Failed to add device: {:?}", e))?;
+            print_json(&device);
+        }
+        DeviceCommand::List { target } => {
+            let devices = crate::admin::list_devices(db_conn, target).await
+                .map_err(|e| format!("Failed to list devices: {:?}", e))?;
+            print_json(&devices);
+        }
+        DeviceCommand::Move { imei, target } => {
+            let device = crate::admin::find_device(db_conn, &imei).await
+                .map_err(|e| format!("Failed to find device: {:?}", e))?;
+            let device = crate::admin::update_device(db_conn, device.id, crate::types::DeviceConfig {
+                imei: device.imei,
+                target,
+                mt_provider: device.mt_provider,
+                metadata: device.metadata,
+            }).await.map_err(|e| format!("Failed to move device: {:?}", e))?;
+            print_json(&device);
+        }
+        DeviceCommand::Remove { imei } => {
+            let device = crate::admin::find_device(db_conn, &imei).await
+                .map_err(|e| format!("Failed to find device: {:?}", e))?;
+            crate::admin::delete_device(db_conn, device.id).await
+                .map_err(|e| format!("Failed to remove device: {:?}", e))?;
+        }
+    }
+    Ok(())
+}
+
+async fn message(db_conn: &mut crate::DBConn, id: uuid::Uuid) -> Result<(), String> {
+    let mt_message = crate::schema::mt_messages::dsl::mt_messages
+        .filter(crate::schema::mt_messages::dsl::id.eq(id))
+        .get_result::<crate::models::MTMessage>(db_conn).await
+        .optional()
+        .map_err(|e| format!("Failed to get MT message: {}", e))?;
+
+    let message = match mt_message {
+        Some(m) => serde_json::json!({
+            "direction": "mt",
+            "target": m.target,
+            "message": crate::mt::message_info(m),
+        }),
+        None => {
+            let mo_message = crate::schema::mo_messages::dsl::mo_messages
+                .filter(crate::schema::mo_messages::dsl::id.eq(id))
+                .get_result::<crate::models::MOMessage>(db_conn).await
+                .optional()
+                .map_err(|e| format!("Failed to get MO message: {}", e))?
+                .ok_or_else(|| format!("No message with ID {}", id))?;
+            serde_json::json!({
+                "direction": "mo",
+                "message": crate::mo::message_info(mo_message),
+            })
+        }
+    };
+
+    let audit_log = crate::schema::audit_log::dsl::audit_log
+        .filter(crate::schema::audit_log::dsl::message.eq(id))
+        .order_by(crate::schema::audit_log::dsl::time.asc())
+        .load::<crate::models::AuditLogEntry>(db_conn).await
+        .map_err(|e| format!("Failed to get audit log: {}", e))?;
+
+    print_json(&serde_json::json!({
+        "message": message,
+        "audit_log": audit_log.into_iter().map(|e| crate::types::AuditLogEntry {
+            time: e.time.and_utc(),
+            target: e.target,
+            event: e.event,
+            detail: e.detail,
+        }).collect::<Vec<_>>(),
+    }));
+    Ok(())
+}
+
+async fn requeue(db_conn: &mut crate::DBConn, amqp_addr: String, id: uuid::Uuid) -> Result<(), String> {
+    let celery_app = celery::app!(
+        broker = AMQP { amqp_addr },
+        tasks = [],
+        task_routes = [],
+    ).await.map_err(|e| format!("Failed to setup celery: {}", e))?;
+
+    let mo_requeued = diesel::update(crate::schema::mo_messages::dsl::mo_messages)
+        .filter(crate::schema::mo_messages::dsl::id.eq(id))
+        .filter(crate::schema::mo_messages::dsl::processing_status.eq(crate::models::ProcessingStatus::Failed))
+        .set((
+            crate::schema::mo_messages::dsl::processing_status.eq(crate::models::ProcessingStatus::Received),
+            crate::schema::mo_messages::dsl::requeued.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .execute(db_conn).await
+        .map_err(|e| format!("Failed to requeue MO message: {}", e))?;
+    if mo_requeued != 0 {
+        celery_app.send_task(crate::worker::process_message::new(id)).await
+            .map_err(|e| format!("Failed to send task: {}", e))?;
+        crate::audit::record_message(db_conn, None, Some(id), "mo_requeued", format!("MO message {} requeued", id)).await;
+        return Ok(());
+    }
+
+    // Delivery is retried for as long as if the message had just been sent, unless it has expired
+    let mt_target = diesel::update(crate::schema::mt_messages::dsl::mt_messages)
+        .filter(crate::schema::mt_messages::dsl::id.eq(id))
+        .filter(crate::schema::mt_messages::dsl::processing_status.eq(crate::models::ProcessingStatus::Failed))
+        .set((
+            crate::schema::mt_messages::dsl::processing_status.eq(crate::models::ProcessingStatus::Received),
+            crate::schema::mt_messages::dsl::message_status.eq(None::<crate::models::MessageStatus>),
+            crate::schema::mt_messages::dsl::completed.eq(None::<chrono::NaiveDateTime>),
+            crate::schema::mt_messages::dsl::requeued.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .returning(crate::schema::mt_messages::dsl::target)
+        .get_result::<uuid::Uuid>(db_conn).await
+        .optional()
+        .map_err(|e| format!("Failed to requeue MT message: {}", e))?;
+    match mt_target {
+        Some(target) => {
+            celery_app.send_task(crate::worker::deliver_mt::new(id)).await
+                .map_err(|e| format!("Failed to send task: {}", e))?;
+            crate::audit::record_message(
+                db_conn, Some(target), Some(id), "mt_requeued", format!("MT message {} requeued", id)
+            ).await;
+            Ok(())
+        }
+        None => Err(format!("No failed message with ID {}", id))
+    }
+}
+
+async fn queues(db_conn: &mut crate::DBConn) -> Result<(), String> {
+    let mo_counts = crate::schema::mo_messages::dsl::mo_messages
+        .filter(crate::schema::mo_messages::dsl::processing_status.ne(crate::models::ProcessingStatus::Done))
+        .group_by(crate::schema::mo_messages::dsl::processing_status)
+        .select((crate::schema::mo_messages::dsl::processing_status, diesel::dsl::count_star()))
+        .load::<(crate::models::ProcessingStatus, i64)>(db_conn).await
+        .map_err(|e| format!("Failed to count MO messages: {}", e))?;
+    let mt_counts = crate::schema::mt_messages::dsl::mt_messages
+        .filter(crate::schema::mt_messages::dsl::processing_status.ne(crate::models::ProcessingStatus::Done))
+        .group_by(crate::schema::mt_messages::dsl::processing_status)
+        .select((crate::schema::mt_messages::dsl::processing_status, diesel::dsl::count_star()))
+        .load::<(crate::models::ProcessingStatus, i64)>(db_conn).await
+        .map_err(|e| format!("Failed to count MT messages: {}", e))?;
+
+    let count = |counts: &[(crate::models::ProcessingStatus, i64)], status: crate::models::ProcessingStatus| {
+        counts.iter().find(|(s, _)| *s == status).map_or(0, |(_, c)| *c)
+    };
+
+    print_json(&crate::types::QueueDepths {
+        mo_received: count(&mo_counts, crate::models::ProcessingStatus::Received),
+        mo_failed: count(&mo_counts, crate::models::ProcessingStatus::Failed),
+        mt_received: count(&mt_counts, crate::models::ProcessingStatus::Received),
+        mt_scheduled: count(&mt_counts, crate::models::ProcessingStatus::Scheduled),
+        mt_sending: count(&mt_counts, crate::models::ProcessingStatus::Sending),
+        mt_failed: count(&mt_counts, crate::models::ProcessingStatus::Failed),
+    });
+    Ok(())
+}
\ No newline at end of file
@@ -0,0 +1,734 @@
+use base64::prelude::*;
+
+/// A client for the Kosmos MT API, signing requests in the same way the API server checks them
+pub struct Client {
+    http_client: reqwest::Client,
+    base_url: reqwest::Url,
+    target_id: uuid::Uuid,
+    key: Vec<u8>,
+    signature_version: SignatureVersion,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum SignatureVersion {
+    V1,
+    V2,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    HTTP(reqwest::Error),
+    /// The API server rejected the request, explaining why
+    API(crate::types::Problem),
+    /// The API server returned an error status, with its body
+    Status(reqwest::StatusCode, String),
+    InvalidResponse,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::HTTP(err) => write!(f, "HTTP error: {}", err),
+            Self::API(problem) => match (&problem.detail, &problem.field) {
+                (Some(detail), Some(field)) => write!(f, "{} ({}): {}", problem.code, field, detail),
+                (Some(detail), None) => write!(f, "{}: {}", problem.code, detail),
+                _ => write!(f, "{}", problem.code),
+            },
+            Self::Status(status, body) if body.is_empty() => write!(f, "API returned {}", status),
+            Self::Status(status, body) => write!(f, "API returned {}: {}", status, body),
+            Self::InvalidResponse => write!(f, "Invalid response from API"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Self::HTTP(err)
+    }
+}
+
+impl Client {
+    pub fn new(
+        base_url: reqwest::Url, target_id: uuid::Uuid, key: Vec<u8>, signature_version: SignatureVersion,
+    ) -> Self {
+        Self {
+            http_client: reqwest::Client::new(),
+            base_url,
+            target_id,
+            key,
+            signature_version,
+        }
+    }
+
+    fn url(&self, path: &str) -> reqwest::Url {
+        let (path, query) = match path.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (path, None),
+        };
+        // Keep any path the API server is mounted at
+        let mut url = self.base_url.clone();
+        url.set_path(&format!("{}{}", self.base_url.path().trim_end_matches('/'), path));
+        url.set_query(query);
+        url
+    }
+
+    /// Builds a signed request; requests without a body are always signed with version 2
+    fn request(&self, method: reqwest::Method, path: &str, body: Vec<u8>) -> reqwest::RequestBuilder {
+        let url = self.url(path);
+        let mut req = self.http_client.request(method.clone(), url.clone())
+            .header("Kosmos-Target-ID", self.target_id.to_string());
+
+        let signature_version = if body.is_empty() {
+            SignatureVersion::V2
+        } else {
+            self.signature_version
+        };
+
+        let mac = match signature_version {
+            SignatureVersion::V1 => crate::signing::request_mac_v1(&self.key, &body),
+            SignatureVersion::V2 => {
+                let timestamp = chrono::Utc::now().timestamp();
+                let nonce = hex::encode(rand::random::<[u8; 16]>());
+                let signed_path = match url.query() {
+                    Some(q) => format!("{}?{}", url.path(), q),
+                    None => url.path().to_string(),
+                };
+                req = req
+                    .header("Kosmos-Signature-Version", "2")
+                    .header("Kosmos-Timestamp", timestamp.to_string())
+                    .header("Kosmos-Nonce", &nonce);
+                crate::signing::request_mac_v2(&self.key, method.as_str(), &signed_path, timestamp, &nonce, &body)
+            }
+        };
+
+        req.header("Kosmos-MAC", BASE64_STANDARD.encode(mac)).body(body)
+    }
+
+    async fn send(&self, req: reqwest::RequestBuilder) -> Result<reqwest::Response, Error> {
+        let res = req.send().await?;
+        if !res.status().is_success() {
+            let status = res.status();
+            let body = res.text().await.unwrap_or_default();
+            return Err(match serde_json::from_str::<crate::types::Problem>(&body) {
+                Ok(problem) => Error::API(problem),
+                Err(_) => Error::Status(status, body),
+            });
+        }
+        Ok(res)
+    }
+
+    /// Submits an MT message, returning its ID
+    pub async fn submit_mt(
+        &self, message: &crate::types::MTMessage, idempotency_key: Option<&str>,
+    ) -> Result<uuid::Uuid, Error> {
+        let body = serde_json::to_vec(message).unwrap();
+        let mut req = self.request(reqwest::Method::POST, "/submit_mt", body)
+            .header("Content-Type", "application/json");
+        if let Some(key) = idempotency_key {
+            req = req.header("Idempotency-Key", key);
+        }
+
+        let res = self.send(req).await?;
+        uuid::Uuid::parse_str(res.text().await?.trim()).map_err(|_| Error::InvalidResponse)
+    }
+
+    async fn get_json<T: serde::de::DeserializeOwned>(&self, path: &str, query: &[(&str, String)]) -> Result<T, Error> {
+        let mut path = path.to_string();
+        if !query.is_empty() {
+            path.push('?');
+            path.push_str(reqwest::Url::parse("http://localhost").unwrap().query_pairs_mut()
+                .extend_pairs(query)
+                .finish().query().unwrap_or_default());
+        }
+        let req = self.request(reqwest::Method::GET, &path, Vec::new());
+        let res = self.send(req).await?;
+        serde_json::from_str(&res.text().await?).map_err(|_| Error::InvalidResponse)
+    }
+
+    /// Fetches the status of an MT message
+    pub async fn get_mt(&self, message_id: uuid::Uuid) -> Result<crate::types::MTMessageInfo, Error> {
+        self.get_json(&format!("/mt/{}", message_id), &[]).await
+    }
+
+    /// Lists the target's MT messages, newest first
+    pub async fn list_mt(&self, query: &MTQuery) -> Result<crate::types::MTMessageList, Error> {
+        let mut pairs = query.common.pairs();
+        if let Some(status) = &query.processing_status {
+            pairs.push(("processing_status", enum_str(status)));
+        }
+        if let Some(status) = &query.message_status {
+            pairs.push(("message_status", enum_str(status)));
+        }
+        if let Some(batch) = query.batch {
+            pairs.push(("batch", batch.to_string()));
+        }
+        self.get_json("/mt", &pairs).await
+    }
+
+    /// Cancels an MT message, or if it has already been accepted by the gateway and `flush` is set,
+    /// flushes the device's MT queue
+    pub async fn cancel_mt(&self, message_id: uuid::Uuid, flush: bool) -> Result<CancelOutcome, Error> {
+        let path = if flush {
+            format!("/mt/{}?flush=true", message_id)
+        } else {
+            format!("/mt/{}", message_id)
+        };
+        let req = self.request(reqwest::Method::DELETE, &path, Vec::new());
+        let res = self.send(req).await?;
+        Ok(if res.status() == reqwest::StatusCode::ACCEPTED {
+            CancelOutcome::FlushRequested
+        } else {
+            CancelOutcome::Cancelled
+        })
+    }
+
+    pub async fn get_mo(&self, message_id: uuid::Uuid) -> Result<crate::types::MOMessageInfo, Error> {
+        self.get_json(&format!("/mo/{}", message_id), &[]).await
+    }
+
+    pub async fn get_mo_payload(&self, message_id: uuid::Uuid) -> Result<Vec<u8>, Error> {
+        let req = self.request(reqwest::Method::GET, &format!("/mo/{}/payload", message_id), Vec::new());
+        let res = self.send(req).await?;
+        Ok(res.bytes().await?.to_vec())
+    }
+
+    /// Lists MO messages from the target's devices, newest first
+    pub async fn list_mo(&self, query: &MOQuery) -> Result<crate::types::MOMessageList, Error> {
+        let mut pairs = query.common.pairs();
+        if let Some(status) = &query.session_status {
+            pairs.push(("session_status", enum_str(status)));
+        }
+        if let Some(status) = &query.processing_status {
+            pairs.push(("processing_status", enum_str(status)));
+        }
+        self.get_json("/mo", &pairs).await
+    }
+
+    pub async fn create_batch(
+        &self, batch: &crate::types::NewMTBatch, idempotency_key: Option<&str>,
+    ) -> Result<crate::types::MTBatchStatus, Error> {
+        let body = serde_json::to_vec(batch).unwrap();
+        let mut req = self.request(reqwest::Method::POST, "/batches", body)
+            .header("Content-Type", "application/json");
+        if let Some(key) = idempotency_key {
+            req = req.header("Idempotency-Key", key);
+        }
+        let res = self.send(req).await?;
+        serde_json::from_str(&res.text().await?).map_err(|_| Error::InvalidResponse)
+    }
+
+    pub async fn get_batch(&self, batch_id: uuid::Uuid) -> Result<crate::types::MTBatchStatus, Error> {
+        self.get_json(&format!("/batches/{}", batch_id), &[]).await
+    }
+
+    /// Fetches the public keys Kosmos signs webhooks with, for use with [`WebhookVerifier::ed25519`]
+    pub async fn jwks(&self) -> Result<crate::types::JWKSet, Error> {
+        self.get_json("/.well-known/jwks.json", &[]).await
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum CancelOutcome {
+    Cancelled,
+    FlushRequested,
+}
+
+/// Filters shared by MT and MO listings
+#[derive(Debug, Default)]
+pub struct MessageQuery {
+    pub imei: Option<String>,
+    pub group: Option<uuid::Uuid>,
+    pub since: Option<chrono::DateTime<chrono::Utc>>,
+    pub until: Option<chrono::DateTime<chrono::Utc>>,
+    /// The `next_cursor` from the previous page
+    pub cursor: Option<String>,
+    pub limit: Option<u32>,
+}
+
+impl MessageQuery {
+    fn pairs(&self) -> Vec<(&'static str, String)> {
+        let mut pairs = Vec::new();
+        if let Some(imei) = &self.imei {
+            pairs.push(("imei", imei.clone()));
+        }
+        if let Some(group) = self.group {
+            pairs.push(("group", group.to_string()));
+        }
+        if let Some(since) = self.since {
+            pairs.push(("since", since.to_rfc3339()));
+        }
+        if let Some(until) = self.until {
+            pairs.push(("until", until.to_rfc3339()));
+        }
+        if let Some(cursor) = &self.cursor {
+            pairs.push(("cursor", cursor.clone()));
+        }
+        if let Some(limit) = self.limit {
+            pairs.push(("limit", limit.to_string()));
+        }
+        pairs
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MTQuery {
+    pub common: MessageQuery,
+    pub processing_status: Option<crate::types::ProcessingStatus>,
+    pub message_status: Option<crate::types::MessageStatus>,
+    pub batch: Option<uuid::Uuid>,
+}
+
+#[derive(Debug, Default)]
+pub struct MOQuery {
+    pub common: MessageQuery,
+    pub session_status: Option<crate::types::SessionStatus>,
+    pub processing_status: Option<crate::types::ProcessingStatus>,
+}
+
+/// The name of a status as used in the API
+fn enum_str<T: serde::Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(serde_json::Value::String(s)) => s,
+        _ => unreachable!()
+    }
+}
+
+/// How old a webhook's signature may be before it's rejected, in seconds
+pub const MAX_WEBHOOK_AGE: i64 = 300;
+
+#[derive(Debug)]
+pub enum WebhookError {
+    /// No `Kosmos-Signature` header was present, or only a `Kosmos-MAC` header when those aren't
+    /// accepted
+    MissingSignature,
+    InvalidSignature,
+    /// The signature was made with an Ed25519 key that isn't in the verifier's JWK set, which
+    /// should be fetched again in case the key is new
+    UnknownKey(uuid::Uuid),
+    /// The signature was made too long ago, and may be a replay
+    Expired,
+    InvalidBody,
+}
+
+impl std::fmt::Display for WebhookError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::MissingSignature => write!(f, "Webhook isn't signed"),
+            Self::InvalidSignature => write!(f, "Invalid webhook signature"),
+            Self::UnknownKey(id) => write!(f, "Webhook signed with unknown key {}", id),
+            Self::Expired => write!(f, "Webhook signature has expired"),
+            Self::InvalidBody => write!(f, "Invalid webhook body"),
+        }
+    }
+}
+
+impl std::error::Error for WebhookError {}
+
+enum VerifierKey {
+    Hmac(Vec<u8>),
+    /// Raw Ed25519 public keys by ID
+    Ed25519(std::collections::HashMap<uuid::Uuid, Vec<u8>>),
+}
+
+/// How webhooks sent to a target are verified. Only the `Kosmos-Signature` header is accepted
+/// unless [`WebhookVerifier::allow_mac_only`] is used.
+pub struct WebhookVerifier {
+    key: VerifierKey,
+    allow_mac_only: bool,
+}
+
+impl WebhookVerifier {
+    /// Verifies webhooks signed with the target's HMAC key
+    pub fn hmac(key: Vec<u8>) -> Self {
+        Self {
+            key: VerifierKey::Hmac(key),
+            allow_mac_only: false,
+        }
+    }
+
+    /// Verifies webhooks signed with Kosmos's Ed25519 keys, from the JWK set it publishes at
+    /// `/.well-known/jwks.json`. Keys other than Ed25519 ones are ignored.
+    pub fn ed25519(jwks: &crate::types::JWKSet) -> Self {
+        let keys = jwks.keys.iter()
+            .filter(|k| k.kty == "OKP" && k.crv == "Ed25519")
+            .filter_map(|k| Some((k.kid, BASE64_URL_SAFE_NO_PAD.decode(&k.x).ok()?)))
+            .collect();
+        Self {
+            key: VerifierKey::Ed25519(keys),
+            allow_mac_only: false,
+        }
+    }
+
+    /// Also accepts webhooks with only a `Kosmos-MAC` header, which can't be checked for freshness
+    pub fn allow_mac_only(mut self) -> Self {
+        self.allow_mac_only = true;
+        self
+    }
+
+    /// `content` is what `Kosmos-Signature` covers, which for binary mode CloudEvents isn't just the body
+    fn verify<T: serde::de::DeserializeOwned>(
+        &self, body: &[u8], content: &[u8], signature: Option<&str>, mac: Option<&str>,
+    ) -> Result<T, WebhookError> {
+        match (signature, mac, &self.key) {
+            (Some(signature), _, key) => {
+                let header = crate::signing::parse_signature_header(signature)
+                    .ok_or(WebhookError::InvalidSignature)?;
+                let valid = match key {
+                    VerifierKey::Hmac(key) => crate::signing::verify_webhook_signature(key, &header, content),
+                    VerifierKey::Ed25519(keys) => {
+                        let public_key = keys.get(&header.key_id)
+                            .ok_or(WebhookError::UnknownKey(header.key_id))?;
+                        crate::signing::verify_webhook_ed25519_signature(public_key, &header, content)
+                    }
+                };
+                if !valid {
+                    return Err(WebhookError::InvalidSignature);
+                }
+                if (chrono::Utc::now().timestamp() - header.timestamp).abs() > MAX_WEBHOOK_AGE {
+                    return Err(WebhookError::Expired);
+                }
+            }
+            (None, Some(mac), VerifierKey::Hmac(key)) if self.allow_mac_only => {
+                if !crate::signing::verify_webhook_mac(key, body, mac) {
+                    return Err(WebhookError::InvalidSignature);
+                }
+            }
+            (None, _, _) => return Err(WebhookError::MissingSignature)
+        }
+
+        serde_json::from_slice(body).map_err(|_| WebhookError::InvalidBody)
+    }
+
+    fn verify_headers<T: serde::de::DeserializeOwned>(
+        &self, body: &[u8], headers: &reqwest::header::HeaderMap,
+    ) -> Result<T, WebhookError> {
+        let content = crate::signing::webhook_signed_content(
+            headers.iter().filter_map(|(name, value)| Some((name.as_str(), value.to_str().ok()?))), body
+        );
+        self.verify(
+            body,
+            &content,
+            headers.get("Kosmos-Signature").and_then(|h| h.to_str().ok()),
+            headers.get("Kosmos-MAC").and_then(|h| h.to_str().ok()),
+        )
+    }
+}
+
+/// Checks a webhook's signature and age, returning its contents; `body` must be exactly as received
+pub fn verify_webhook(
+    body: &[u8], headers: &reqwest::header::HeaderMap, verifier: &WebhookVerifier,
+) -> Result<crate::types::WebhookMessage, WebhookError> {
+    verifier.verify_headers(body, headers)
+}
+
+/// As [`verify_webhook`], for targets whose webhooks are signed with the HMAC key `key`
+pub fn verify_webhook_with_key(
+    body: &[u8], headers: &reqwest::header::HeaderMap, key: &[u8],
+) -> Result<crate::types::WebhookMessage, WebhookError> {
+    verify_webhook(body, headers, &WebhookVerifier::hmac(key.to_vec()))
+}
+
+/// As [`verify_webhook`], for targets using version 2 of the webhook format
+pub fn verify_webhook_envelope(
+    body: &[u8], headers: &reqwest::header::HeaderMap, verifier: &WebhookVerifier,
+) -> Result<crate::types::WebhookEnvelope, WebhookError> {
+    verifier.verify_headers(body, headers)
+}
+
+/// As [`verify_webhook`], for targets receiving CloudEvents in structured mode
+pub fn verify_cloud_event(
+    body: &[u8], headers: &reqwest::header::HeaderMap, verifier: &WebhookVerifier,
+) -> Result<crate::types::CloudEvent, WebhookError> {
+    verifier.verify_headers(body, headers)
+}
+
+/// As [`verify_cloud_event`], for targets also using version 2 of the webhook format
+pub fn verify_cloud_event_envelope(
+    body: &[u8], headers: &reqwest::header::HeaderMap, verifier: &WebhookVerifier,
+) -> Result<crate::types::CloudEvent<crate::types::WebhookEnvelope>, WebhookError> {
+    verifier.verify_headers(body, headers)
+}
+
+/// A Rocket data guard for a webhook checked with the managed [`WebhookVerifier`]
+pub struct VerifiedWebhook<T = crate::types::WebhookMessage>(pub T);
+
+#[rocket::async_trait]
+impl<'r, T: serde::de::DeserializeOwned> rocket::data::FromData<'r> for VerifiedWebhook<T> {
+    type Error = WebhookError;
+
+    async fn from_data(request: &'r rocket::request::Request<'_>, data: rocket::data::Data<'r>) -> rocket::data::Outcome<'r, Self> {
+        use rocket::data::ToByteUnit;
+
+        let verifier = match request.rocket().state::<WebhookVerifier>() {
+            Some(v) => v,
+            None => {
+                error!("No webhook verifier configured");
+                return rocket::data::Outcome::Error((rocket::http::Status::InternalServerError, WebhookError::MissingSignature));
+            }
+        };
+
+        let body = match data.open(1.mebibytes()).into_bytes().await {
+            Ok(b) if b.is_complete() => b.into_inner(),
+            _ => return rocket::data::Outcome::Error((rocket::http::Status::BadRequest, WebhookError::InvalidBody)),
+        };
+
+        let headers = request.headers().iter()
+            .map(|h| (h.name.to_string(), h.value.to_string()))
+            .collect::<Vec<_>>();
+        let content = crate::signing::webhook_signed_content(
+            headers.iter().map(|(name, value)| (name.as_str(), value.as_str())), &body
+        );
+        match verifier.verify(
+            &body,
+            &content,
+            request.headers().get_one("Kosmos-Signature"),
+            request.headers().get_one("Kosmos-MAC"),
+        ) {
+            Ok(m) => rocket::data::Outcome::Success(VerifiedWebhook(m)),
+            Err(WebhookError::InvalidBody) =>
+                rocket::data::Outcome::Error((rocket::http::Status::BadRequest, WebhookError::InvalidBody)),
+            Err(err) => rocket::data::Outcome::Error((rocket::http::Status::Unauthorized, err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::prelude::*;
+
+    const BODY: &[u8] = br#"{"id":"5f6c2a2e-8c4a-4f4e-9d6b-3c1f0f8a7b21"}"#;
+
+    fn hmac_headers(key: &[u8], timestamp: i64) -> reqwest::header::HeaderMap {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("Kosmos-Signature", crate::signing::webhook_signature_header(
+            &uuid::Uuid::new_v4(), key, timestamp, BODY
+        ).parse().unwrap());
+        headers.insert("Kosmos-MAC", BASE64_STANDARD.encode(crate::signing::request_mac_v1(key, BODY)).parse().unwrap());
+        headers
+    }
+
+    fn mac_only_headers(key: &[u8]) -> reqwest::header::HeaderMap {
+        let mut headers = hmac_headers(key, chrono::Utc::now().timestamp());
+        headers.remove("Kosmos-Signature");
+        headers
+    }
+
+    fn ed25519_key() -> (uuid::Uuid, Vec<u8>, crate::types::JWKSet) {
+        let pkey = openssl::pkey::PKey::generate_ed25519().unwrap();
+        let id = uuid::Uuid::new_v4();
+        let jwks = crate::types::JWKSet {
+            keys: vec![crate::types::JWK {
+                kty: "OKP".to_string(),
+                crv: "Ed25519".to_string(),
+                x: BASE64_URL_SAFE_NO_PAD.encode(pkey.raw_public_key().unwrap()),
+                kid: id,
+                key_use: "sig".to_string(),
+                alg: "EdDSA".to_string(),
+            }],
+        };
+        (id, pkey.raw_private_key().unwrap(), jwks)
+    }
+
+    fn ed25519_headers(key_id: &uuid::Uuid, private_key: &[u8], timestamp: i64) -> reqwest::header::HeaderMap {
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("Kosmos-Signature", crate::signing::webhook_ed25519_signature_header(
+            key_id, private_key, timestamp, BODY
+        ).unwrap().parse().unwrap());
+        headers
+    }
+
+    fn verify(verifier: &super::WebhookVerifier, headers: &reqwest::header::HeaderMap) -> Result<serde_json::Value, super::WebhookError> {
+        verifier.verify_headers(BODY, headers)
+    }
+
+    #[test]
+    fn hmac_valid() {
+        let verifier = super::WebhookVerifier::hmac(b"key".to_vec());
+        let headers = hmac_headers(b"key", chrono::Utc::now().timestamp());
+        assert_eq!(verify(&verifier, &headers).unwrap()["id"], "5f6c2a2e-8c4a-4f4e-9d6b-3c1f0f8a7b21");
+    }
+
+    #[test]
+    fn hmac_rejects_bad_signatures() {
+        let verifier = super::WebhookVerifier::hmac(b"key".to_vec());
+        let headers = hmac_headers(b"other key", chrono::Utc::now().timestamp());
+        assert!(matches!(verify(&verifier, &headers), Err(super::WebhookError::InvalidSignature)));
+        assert!(matches!(
+            verifier.verify_headers::<serde_json::Value>(b"{}", &hmac_headers(b"key", chrono::Utc::now().timestamp())),
+            Err(super::WebhookError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn hmac_rejects_old_signatures() {
+        let verifier = super::WebhookVerifier::hmac(b"key".to_vec());
+        let headers = hmac_headers(b"key", chrono::Utc::now().timestamp() - super::MAX_WEBHOOK_AGE - 60);
+        assert!(matches!(verify(&verifier, &headers), Err(super::WebhookError::Expired)));
+    }
+
+    #[test]
+    fn verify_with_key() {
+        let body = br#"{"type":"mt_message_status","id":"5f6c2a2e-8c4a-4f4e-9d6b-3c1f0f8a7b21","status":"delivered"}"#;
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("Kosmos-Signature", crate::signing::webhook_signature_header(
+            &uuid::Uuid::new_v4(), b"key", chrono::Utc::now().timestamp(), body
+        ).parse().unwrap());
+
+        assert!(matches!(
+            super::verify_webhook_with_key(body, &headers, b"key"),
+            Ok(crate::types::WebhookMessage::MTMessageStatus(_))
+        ));
+        assert!(matches!(
+            super::verify_webhook_with_key(body, &headers, b"other key"),
+            Err(super::WebhookError::InvalidSignature)
+        ));
+    }
+
+    #[test]
+    fn mac_only_is_opt_in() {
+        let headers = mac_only_headers(b"key");
+        let verifier = super::WebhookVerifier::hmac(b"key".to_vec());
+        assert!(matches!(verify(&verifier, &headers), Err(super::WebhookError::MissingSignature)));
+
+        let verifier = super::WebhookVerifier::hmac(b"key".to_vec()).allow_mac_only();
+        assert!(verify(&verifier, &headers).is_ok());
+        let verifier = super::WebhookVerifier::hmac(b"other key".to_vec()).allow_mac_only();
+        assert!(matches!(verify(&verifier, &headers), Err(super::WebhookError::InvalidSignature)));
+    }
+
+    #[test]
+    fn missing_signature() {
+        let verifier = super::WebhookVerifier::hmac(b"key".to_vec()).allow_mac_only();
+        assert!(matches!(
+            verify(&verifier, &reqwest::header::HeaderMap::new()),
+            Err(super::WebhookError::MissingSignature)
+        ));
+    }
+
+    #[test]
+    fn ed25519_valid() {
+        let (id, private_key, jwks) = ed25519_key();
+        let verifier = super::WebhookVerifier::ed25519(&jwks);
+        let headers = ed25519_headers(&id, &private_key, chrono::Utc::now().timestamp());
+        assert!(verify(&verifier, &headers).is_ok());
+    }
+
+    #[test]
+    fn ed25519_rejects_bad_signatures() {
+        let (id, _, jwks) = ed25519_key();
+        let (_, other_private_key, _) = ed25519_key();
+        let verifier = super::WebhookVerifier::ed25519(&jwks);
+        let headers = ed25519_headers(&id, &other_private_key, chrono::Utc::now().timestamp());
+        assert!(matches!(verify(&verifier, &headers), Err(super::WebhookError::InvalidSignature)));
+
+        // An HMAC signature doesn't satisfy an Ed25519 verifier, even with the MAC-only fallback
+        let verifier = super::WebhookVerifier::ed25519(&jwks).allow_mac_only();
+        assert!(matches!(verify(&verifier, &mac_only_headers(b"key")), Err(super::WebhookError::MissingSignature)));
+    }
+
+    #[test]
+    fn ed25519_unknown_key() {
+        let (_, private_key, jwks) = ed25519_key();
+        let verifier = super::WebhookVerifier::ed25519(&jwks);
+        let other_id = uuid::Uuid::new_v4();
+        let headers = ed25519_headers(&other_id, &private_key, chrono::Utc::now().timestamp());
+        assert!(matches!(verify(&verifier, &headers), Err(super::WebhookError::UnknownKey(id)) if id == other_id));
+    }
+
+    #[test]
+    fn ed25519_rejects_old_signatures() {
+        let (id, private_key, jwks) = ed25519_key();
+        let verifier = super::WebhookVerifier::ed25519(&jwks);
+        let headers = ed25519_headers(&id, &private_key, chrono::Utc::now().timestamp() - super::MAX_WEBHOOK_AGE - 60);
+        assert!(matches!(verify(&verifier, &headers), Err(super::WebhookError::Expired)));
+    }
+
+    #[test]
+    fn binary_cloud_event_attributes_are_signed() {
+        let ce_headers = [("ce-id", "1"), ("ce-type", "kosmos.mt_message_status"), ("ce-specversion", "1.0")];
+        let content = crate::signing::webhook_signed_content(ce_headers, BODY);
+        let mut headers = reqwest::header::HeaderMap::new();
+        headers.insert("Kosmos-Signature", crate::signing::webhook_signature_header(
+            &uuid::Uuid::new_v4(), b"key", chrono::Utc::now().timestamp(), &content
+        ).parse().unwrap());
+        for (name, value) in ce_headers {
+            headers.insert(name, value.parse().unwrap());
+        }
+
+        let verifier = super::WebhookVerifier::hmac(b"key".to_vec());
+        assert!(verify(&verifier, &headers).is_ok());
+
+        let mut changed = headers.clone();
+        changed.insert("ce-type", "kosmos.mo_message".parse().unwrap());
+        assert!(matches!(verify(&verifier, &changed), Err(super::WebhookError::InvalidSignature)));
+
+        let mut removed = headers.clone();
+        removed.remove("ce-id");
+        assert!(matches!(verify(&verifier, &removed), Err(super::WebhookError::InvalidSignature)));
+    }
+
+    #[test]
+    fn binary_cloud_event_mac_covers_body() {
+        let mut headers = mac_only_headers(b"key");
+        headers.insert("ce-id", "1".parse().unwrap());
+        headers.insert("ce-type", "kosmos.mt_message_status".parse().unwrap());
+        let verifier = super::WebhookVerifier::hmac(b"key".to_vec()).allow_mac_only();
+        assert!(verify(&verifier, &headers).is_ok());
+    }
+
+    fn client(signature_version: super::SignatureVersion) -> super::Client {
+        super::Client::new(
+            "http://localhost/api/".parse().unwrap(), uuid::uuid!("0b3b6a2e-6f5c-4a8e-9b1d-2c7e4f5a6b7c"),
+            b"key".to_vec(), signature_version,
+        )
+    }
+
+    fn header<'a>(req: &'a reqwest::Request, name: &str) -> Option<&'a str> {
+        req.headers().get(name).map(|h| h.to_str().unwrap())
+    }
+
+    #[test]
+    fn request_v1() {
+        let req = client(super::SignatureVersion::V1)
+            .request(reqwest::Method::POST, "/submit_mt", b"{}".to_vec())
+            .build().unwrap();
+        assert_eq!(req.url().as_str(), "http://localhost/api/submit_mt");
+        assert_eq!(header(&req, "Kosmos-Target-ID"), Some("0b3b6a2e-6f5c-4a8e-9b1d-2c7e4f5a6b7c"));
+        assert_eq!(header(&req, "Kosmos-Signature-Version"), None);
+        let mac = BASE64_STANDARD.encode(crate::signing::request_mac_v1(b"key", b"{}"));
+        assert_eq!(header(&req, "Kosmos-MAC"), Some(mac.as_str()));
+    }
+
+    #[test]
+    fn request_v2() {
+        let req = client(super::SignatureVersion::V2)
+            .request(reqwest::Method::GET, "/mt?limit=5", Vec::new())
+            .build().unwrap();
+        assert_eq!(req.url().as_str(), "http://localhost/api/mt?limit=5");
+        assert_eq!(header(&req, "Kosmos-Signature-Version"), Some("2"));
+        let timestamp = header(&req, "Kosmos-Timestamp").unwrap().parse::<i64>().unwrap();
+        assert!((chrono::Utc::now().timestamp() - timestamp).abs() < 5);
+        let nonce = header(&req, "Kosmos-Nonce").unwrap();
+        assert_eq!(nonce.len(), 32);
+        // The path signed includes the one the API server is mounted at
+        let mac = BASE64_STANDARD.encode(crate::signing::request_mac_v2(
+            b"key", "GET", "/api/mt?limit=5", timestamp, nonce, b""
+        ));
+        assert_eq!(header(&req, "Kosmos-MAC"), Some(mac.as_str()));
+    }
+
+    #[test]
+    fn bodyless_requests_use_v2() {
+        let req = client(super::SignatureVersion::V1)
+            .request(reqwest::Method::DELETE, "/mt/5f6c2a2e-8c4a-4f4e-9d6b-3c1f0f8a7b21", Vec::new())
+            .build().unwrap();
+        assert_eq!(header(&req, "Kosmos-Signature-Version"), Some("2"));
+
+        let req = client(super::SignatureVersion::V1)
+            .request(reqwest::Method::GET, "/mt", Vec::new())
+            .build().unwrap();
+        assert_eq!(header(&req, "Kosmos-Signature-Version"), Some("2"));
+    }
+}
\ No newline at end of file
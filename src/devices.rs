@@ -0,0 +1,255 @@
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use diesel_async::scoped_futures::ScopedFutureExt;
+
+/// Limits on device tags, to keep them small enough to include in every webhook
+pub const MAX_TAGS: usize = 32;
+pub(crate) const MAX_TAG_KEY_LENGTH: usize = 64;
+pub(crate) const MAX_TAG_VALUE_LENGTH: usize = 255;
+pub(crate) const MAX_GROUP_NAME_LENGTH: usize = 64;
+
+#[derive(Debug)]
+pub enum DeviceError {
+    NotFound,
+    InvalidTags,
+    InvalidName,
+    NameTaken,
+    Internal,
+}
+
+impl From<diesel::result::Error> for DeviceError {
+    fn from(err: diesel::result::Error) -> Self {
+        error!("Failed to access devices: {}", err);
+        DeviceError::Internal
+    }
+}
+
+pub(crate) async fn get_device(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target, imei: &str,
+) -> Result<crate::models::Device, DeviceError> {
+    crate::schema::devices::dsl::devices
+        .filter(crate::schema::devices::dsl::imei.eq(imei))
+        .filter(crate::schema::devices::dsl::target.eq(&target.id))
+        .get_result::<crate::models::Device>(db_conn).await
+        .optional()?
+        .ok_or(DeviceError::NotFound)
+}
+
+/// The tags set on a device
+pub(crate) async fn tags(
+    db_conn: &mut crate::DBConn, device_id: uuid::Uuid,
+) -> diesel::result::QueryResult<std::collections::BTreeMap<String, String>> {
+    Ok(crate::schema::device_tags::dsl::device_tags
+        .filter(crate::schema::device_tags::dsl::device.eq(device_id))
+        .load::<crate::models::DeviceTag>(db_conn).await?
+        .into_iter()
+        .map(|t| (t.key, t.value))
+        .collect())
+}
+
+/// Fills in the tags and group memberships of devices
+pub(crate) async fn device_info(
+    db_conn: &mut crate::DBConn, devices: Vec<crate::models::Device>,
+) -> Result<Vec<crate::types::Device>, DeviceError> {
+    let ids = devices.iter().map(|d| d.id).collect::<Vec<_>>();
+
+    let tags = crate::schema::device_tags::dsl::device_tags
+        .filter(crate::schema::device_tags::dsl::device.eq_any(&ids))
+        .load::<crate::models::DeviceTag>(db_conn).await?;
+    let memberships = crate::schema::device_group_members::dsl::device_group_members
+        .filter(crate::schema::device_group_members::dsl::device.eq_any(&ids))
+        .select((
+            crate::schema::device_group_members::dsl::device,
+            crate::schema::device_group_members::dsl::device_group,
+        ))
+        .load::<(uuid::Uuid, uuid::Uuid)>(db_conn).await?;
+
+    Ok(devices.into_iter().map(|d| crate::types::Device {
+        tags: tags.iter()
+            .filter(|t| t.device == d.id)
+            .map(|t| (t.key.clone(), t.value.clone()))
+            .collect(),
+        groups: memberships.iter()
+            .filter(|(device, _)| *device == d.id)
+            .map(|(_, group)| *group)
+            .collect(),
+        imei: d.imei,
+    }).collect())
+}
+
+pub(crate) async fn list_devices(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target,
+) -> Result<Vec<crate::models::Device>, DeviceError> {
+    Ok(crate::schema::devices::dsl::devices
+        .filter(crate::schema::devices::dsl::target.eq(&target.id))
+        .order_by(crate::schema::devices::dsl::imei.asc())
+        .load::<crate::models::Device>(db_conn).await?)
+}
+
+fn valid_tags(tags: &std::collections::BTreeMap<String, String>) -> bool {
+    tags.len() <= MAX_TAGS && tags.iter().all(|(k, v)| {
+        !k.is_empty() && k.len() <= MAX_TAG_KEY_LENGTH && v.len() <= MAX_TAG_VALUE_LENGTH
+    })
+}
+
+/// Replaces all the tags on a device
+pub(crate) async fn set_tags(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target, imei: &str,
+    tags: std::collections::BTreeMap<String, String>,
+) -> Result<crate::models::Device, DeviceError> {
+    if !valid_tags(&tags) {
+        return Err(DeviceError::InvalidTags);
+    }
+
+    let device = get_device(db_conn, target, imei).await?;
+
+    let new_tags = tags.into_iter().map(|(key, value)| crate::models::DeviceTag {
+        device: device.id,
+        key,
+        value,
+    }).collect::<Vec<_>>();
+
+    let device_id = device.id;
+    let new_tags_ref = &new_tags;
+    db_conn.transaction::<_, diesel::result::Error, _>(|conn| async move {
+        diesel::delete(crate::schema::device_tags::dsl::device_tags)
+            .filter(crate::schema::device_tags::dsl::device.eq(device_id))
+            .execute(conn).await?;
+
+        diesel::insert_into(crate::schema::device_tags::dsl::device_tags)
+            .values(new_tags_ref)
+            .execute(conn).await?;
+
+        Ok(())
+    }.scope_boxed()).await?;
+
+    crate::audit::record(
+        db_conn, Some(target.id), "device_tags_updated",
+        format!("Tags on IMEI {} updated", device.imei)
+    ).await;
+
+    Ok(device)
+}
+
+/// The IMEIs of the devices in a group
+pub(crate) async fn group_imeis(
+    db_conn: &mut crate::DBConn, group_id: uuid::Uuid,
+) -> diesel::result::QueryResult<Vec<String>> {
+    crate::schema::devices::dsl::devices
+        .inner_join(crate::schema::device_group_members::table)
+        .filter(crate::schema::device_group_members::dsl::device_group.eq(group_id))
+        .select(crate::schema::devices::dsl::imei)
+        .order_by(crate::schema::devices::dsl::imei.asc())
+        .load::<String>(db_conn).await
+}
+
+pub(crate) async fn group_info(
+    db_conn: &mut crate::DBConn, group: crate::models::DeviceGroup,
+) -> Result<crate::types::DeviceGroup, DeviceError> {
+    let devices = group_imeis(db_conn, group.id).await?;
+
+    Ok(crate::types::DeviceGroup {
+        id: group.id,
+        name: group.name,
+        created: group.created.and_utc(),
+        devices,
+    })
+}
+
+pub(crate) async fn create_group(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target, request: crate::types::NewDeviceGroup,
+) -> Result<crate::models::DeviceGroup, DeviceError> {
+    if request.name.is_empty() || request.name.len() > MAX_GROUP_NAME_LENGTH {
+        return Err(DeviceError::InvalidName);
+    }
+
+    let group = crate::models::DeviceGroup {
+        id: uuid::Uuid::new_v4(),
+        target: target.id,
+        name: request.name,
+        created: chrono::Utc::now().naive_utc(),
+    };
+
+    let inserted = diesel::insert_into(crate::schema::device_groups::dsl::device_groups)
+        .values(&group)
+        .on_conflict_do_nothing()
+        .execute(db_conn).await?;
+    if inserted == 0 {
+        return Err(DeviceError::NameTaken);
+    }
+
+    crate::audit::record(
+        db_conn, Some(target.id), "device_group_created",
+        format!("Device group {} ({}) created", group.id, group.name)
+    ).await;
+
+    Ok(group)
+}
+
+pub(crate) async fn get_group(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target, group_id: uuid::Uuid,
+) -> Result<crate::models::DeviceGroup, DeviceError> {
+    crate::schema::device_groups::dsl::device_groups
+        .filter(crate::schema::device_groups::dsl::id.eq(group_id))
+        .filter(crate::schema::device_groups::dsl::target.eq(&target.id))
+        .get_result::<crate::models::DeviceGroup>(db_conn).await
+        .optional()?
+        .ok_or(DeviceError::NotFound)
+}
+
+pub(crate) async fn list_groups(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target,
+) -> Result<Vec<crate::models::DeviceGroup>, DeviceError> {
+    Ok(crate::schema::device_groups::dsl::device_groups
+        .filter(crate::schema::device_groups::dsl::target.eq(&target.id))
+        .order_by(crate::schema::device_groups::dsl::name.asc())
+        .load::<crate::models::DeviceGroup>(db_conn).await?)
+}
+
+/// Deletes a group, along with any schedules sending to it. Batches already sent to the group
+/// are kept.
+pub(crate) async fn delete_group(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target, group_id: uuid::Uuid,
+) -> Result<(), DeviceError> {
+    let deleted = diesel::delete(crate::schema::device_groups::dsl::device_groups)
+        .filter(crate::schema::device_groups::dsl::id.eq(group_id))
+        .filter(crate::schema::device_groups::dsl::target.eq(&target.id))
+        .execute(db_conn).await?;
+    if deleted == 0 {
+        return Err(DeviceError::NotFound);
+    }
+
+    crate::audit::record(db_conn, Some(target.id), "device_group_deleted", format!("Device group {} deleted", group_id)).await;
+
+    Ok(())
+}
+
+/// Adds a device to, or removes it from, a group. Both must belong to the target.
+pub(crate) async fn set_member(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target, group_id: uuid::Uuid, imei: &str, member: bool,
+) -> Result<(), DeviceError> {
+    let group = get_group(db_conn, target, group_id).await?;
+    let device = get_device(db_conn, target, imei).await?;
+
+    if member {
+        diesel::insert_into(crate::schema::device_group_members::dsl::device_group_members)
+            .values((
+                crate::schema::device_group_members::dsl::device_group.eq(group.id),
+                crate::schema::device_group_members::dsl::device.eq(device.id),
+            ))
+            .on_conflict_do_nothing()
+            .execute(db_conn).await?;
+    } else {
+        diesel::delete(crate::schema::device_group_members::dsl::device_group_members)
+            .filter(crate::schema::device_group_members::dsl::device_group.eq(group.id))
+            .filter(crate::schema::device_group_members::dsl::device.eq(device.id))
+            .execute(db_conn).await?;
+    }
+
+    crate::audit::record(
+        db_conn, Some(target.id), if member { "device_group_member_added" } else { "device_group_member_removed" },
+        format!("IMEI {} {} device group {}", device.imei, if member { "added to" } else { "removed from" }, group.id)
+    ).await;
+
+    Ok(())
+}
\ No newline at end of file
@@ -0,0 +1,490 @@
+use base64::prelude::*;
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::RunQueryDsl;
+use mail_parser::MimeHeaders;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+
+diesel::sql_function! {
+    fn lower(x: diesel::sql_types::Nullable<diesel::sql_types::Text>) -> diesel::sql_types::Nullable<diesel::sql_types::Text>;
+}
+
+const MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+/// How long a client may take to send each line before the connection is closed
+const IDLE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(300);
+/// Longest line accepted in commands or message data, including the line ending, as in RFC 5321
+const MAX_LINE_LENGTH: u64 = 1000;
+
+/// Formats an MO message in the same way as Iridium's email delivery
+pub(crate) fn mo_message_email(
+    from: lettre::message::Mailbox, to: &str, message: &crate::models::MOMessage,
+) -> Result<lettre::Message, String> {
+    let to = to.parse::<lettre::message::Mailbox>()
+        .map_err(|e| format!("invalid email address: {}", e))?;
+
+    let session_status = match message.session_status {
+        crate::models::SessionStatus::Successful => "00 - Transfer OK",
+        crate::models::SessionStatus::SuccessfulTooLarge =>
+            "01 - Transfer OK, but the MT message in the queue was too large to be included in the session",
+        crate::models::SessionStatus::SuccessfulUnacceptableLocation =>
+            "02 - Transfer OK, but the reported location was determined to be of unacceptable quality",
+        _ => return Err("unsuccessful sessions can't be sent by email".to_string())
+    };
+
+    let mut body = format!(
+        "MOMSN: {}\nMTMSN: {}\nTime of Session (UTC): {}\nSession Status: {}\nMessage Size (bytes): {}\n\n",
+        message.mo_msn as u16, message.mt_msn as u16,
+        message.time_of_session.format("%a %b %d %H:%M:%S %Y"), session_status,
+        message.data.as_ref().map_or(0, |d| d.len()),
+    );
+    if let (Some(latitude), Some(longitude), Some(cep_radius)) = (message.latitude, message.longitude, message.cep_radius) {
+        body.push_str(&format!(
+            "Unit Location: Lat = {:.5} Long = {:.5}\nCEPradius = {}\n", latitude, longitude, cep_radius
+        ));
+    }
+
+    let mut parts = lettre::message::MultiPart::mixed()
+        .singlepart(lettre::message::SinglePart::plain(body));
+    if let Some(data) = &message.data {
+        parts = parts.singlepart(
+            lettre::message::Attachment::new(format!("{}_{:06}.sbd", message.imei, message.mo_msn as u16))
+                .body(data.clone(), lettre::message::header::ContentType::parse("application/x-zip-compressed").unwrap())
+        );
+    }
+
+    lettre::Message::builder()
+        .from(from)
+        .to(to)
+        .subject(format!("SBD Msg From Unit: {}", message.imei))
+        .multipart(parts)
+        .map_err(|e| format!("failed to build email: {}", e))
+}
+
+pub async fn receive_mt(
+    listen_address: std::net::SocketAddr,
+    amqp_addr: String,
+    db_pool: crate::DBPool,
+) {
+    let celery_app = match celery::app!(
+        broker = AMQP { amqp_addr },
+        tasks = [],
+        task_routes = [],
+        acks_late = false,
+    ).await {
+        Ok(a) => a,
+        Err(err) => {
+            error!("Failed to setup celery: {}", err);
+            return;
+        }
+    };
+
+    let listener = match tokio::net::TcpListener::bind(listen_address).await {
+        Ok(l) => l,
+        Err(err) => {
+            error!("Failed to open TCP socket: {}", err);
+            return;
+        }
+    };
+
+    info!("Listening for SMTP on {}", listener.local_addr().unwrap());
+
+    loop {
+        let (socket, peer_address) = match listener.accept().await {
+            Ok(l) => l,
+            Err(err) => {
+                error!("Failed to receive TCP connection: {}", err);
+                return;
+            }
+        };
+
+        info!("Connection received from {}", peer_address);
+
+        tokio::spawn(process_socket(socket, db_pool.clone(), celery_app.clone()));
+    }
+}
+
+async fn process_socket(
+    socket: tokio::net::TcpStream,
+    db_pool: crate::DBPool,
+    celery_app: std::sync::Arc<celery::Celery>
+) {
+    let (read, write) = socket.into_split();
+    if let Err(err) = session(read, write, |envelope| process_message(envelope, &db_pool, &celery_app)).await {
+        warn!("SMTP session failed: {}", err);
+    }
+}
+
+/// An email received over SMTP
+struct Envelope {
+    mail_from: String,
+    recipients: Vec<String>,
+    data: Vec<u8>,
+}
+
+/// Runs an SMTP conversation, passing each message received to `deliver`, which returns the
+/// response to send for it
+async fn session<R, W, F, Fut>(read: R, mut write: W, mut deliver: F) -> std::io::Result<()>
+where
+    R: tokio::io::AsyncRead + Unpin,
+    W: tokio::io::AsyncWrite + Unpin,
+    F: FnMut(Envelope) -> Fut,
+    Fut: std::future::Future<Output = &'static str>,
+{
+    let mut read = tokio::io::BufReader::new(read);
+
+    write.write_all(b"220 Kosmos ESMTP\r\n").await?;
+
+    let mut mail_from: Option<String> = None;
+    let mut recipients = vec![];
+    let mut line = vec![];
+
+    loop {
+        match read_line(&mut read, &mut line).await {
+            Ok(true) => {}
+            Ok(false) => return Ok(()),
+            Err(err) if err.kind() == std::io::ErrorKind::InvalidData => {
+                write.write_all(b"500 5.5.2 Line too long\r\n").await?;
+                return Err(err);
+            }
+            Err(err) if err.kind() == std::io::ErrorKind::TimedOut => {
+                write.write_all(b"421 4.4.2 Idle timeout, closing connection\r\n").await?;
+                return Err(err);
+            }
+            Err(err) => return Err(err)
+        }
+
+        let line = String::from_utf8_lossy(&line);
+        let command = line.trim_end();
+        let verb = command.split(' ').next().unwrap_or_default().to_ascii_uppercase();
+
+        let response: std::borrow::Cow<'static, str> = match verb.as_str() {
+            "HELO" => "250 Kosmos\r\n".into(),
+            "EHLO" => format!("250-Kosmos\r\n250-SIZE {}\r\n250 8BITMIME\r\n", MAX_MESSAGE_SIZE).into(),
+            "MAIL" => {
+                mail_from = Some(parse_path(&command[4..]));
+                recipients.clear();
+                "250 2.1.0 OK\r\n".into()
+            }
+            "RCPT" => if mail_from.is_some() {
+                recipients.push(parse_path(&command[4..]));
+                "250 2.1.5 OK\r\n".into()
+            } else {
+                "503 5.5.1 MAIL first\r\n".into()
+            },
+            "DATA" => if !recipients.is_empty() {
+                write.write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n").await?;
+
+                let data = match read_data(&mut read).await {
+                    Ok(Some(d)) => d,
+                    Ok(None) => {
+                        write.write_all(b"552 5.3.4 Message too big\r\n").await?;
+                        mail_from = None;
+                        recipients.clear();
+                        continue;
+                    }
+                    Err(err) if err.kind() == std::io::ErrorKind::InvalidData => {
+                        write.write_all(b"500 5.5.2 Line too long\r\n").await?;
+                        return Err(err);
+                    }
+                    Err(err) if err.kind() == std::io::ErrorKind::TimedOut => {
+                        write.write_all(b"421 4.4.2 Idle timeout, closing connection\r\n").await?;
+                        return Err(err);
+                    }
+                    Err(err) => return Err(err)
+                };
+
+                deliver(Envelope {
+                    mail_from: mail_from.take().unwrap_or_default(),
+                    recipients: std::mem::take(&mut recipients),
+                    data,
+                }).await.into()
+            } else {
+                "503 5.5.1 RCPT first\r\n".into()
+            },
+            "RSET" => {
+                mail_from = None;
+                recipients.clear();
+                "250 2.0.0 OK\r\n".into()
+            }
+            "NOOP" => "250 2.0.0 OK\r\n".into(),
+            "QUIT" => {
+                write.write_all(b"221 2.0.0 Bye\r\n").await?;
+                return Ok(());
+            }
+            _ => "502 5.5.2 Command not recognised\r\n".into()
+        };
+
+        write.write_all(response.as_bytes()).await?;
+    }
+}
+
+/// Reads a line of at most [`MAX_LINE_LENGTH`] bytes into `line`, returning false at the end of
+/// the stream. Longer lines are an `InvalidData` error, and waiting over [`IDLE_TIMEOUT`] a `TimedOut` one.
+async fn read_line<R: tokio::io::AsyncBufRead + Unpin>(read: &mut R, line: &mut Vec<u8>) -> std::io::Result<bool> {
+    use tokio::io::AsyncReadExt;
+
+    line.clear();
+    let length = tokio::time::timeout(IDLE_TIMEOUT, read.take(MAX_LINE_LENGTH).read_until(b'\n', line)).await
+        .map_err(|_| std::io::Error::new(std::io::ErrorKind::TimedOut, "client idle"))??;
+    if length == 0 {
+        return Ok(false);
+    }
+    if !line.ends_with(b"\n") && line.len() as u64 == MAX_LINE_LENGTH {
+        return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "line too long"));
+    }
+
+    Ok(true)
+}
+
+/// Extracts the address from the argument of a MAIL or RCPT command, such as `FROM:<a@b.c>`
+fn parse_path(argument: &str) -> String {
+    argument.trim().trim_start_matches(|c: char| c != '<')
+        .trim_start_matches('<').split('>').next().unwrap_or_default().to_string()
+}
+
+/// The secret token in the local part of an address such as `mt+token@example.com`
+fn recipient_token(address: &str) -> Option<&str> {
+    let (local, _) = address.rsplit_once('@')?;
+    local.split_once('+').map(|(_, token)| token)
+}
+
+/// Reads the body of a DATA command, returning None if it was too large
+async fn read_data<R: tokio::io::AsyncBufRead + Unpin>(read: &mut R) -> std::io::Result<Option<Vec<u8>>> {
+    let mut data = vec![];
+    let mut line = vec![];
+    let mut too_large = false;
+
+    loop {
+        if !read_line(read, &mut line).await? {
+            return Err(std::io::ErrorKind::UnexpectedEof.into());
+        }
+
+        if line == b".\r\n" || line == b".\n" {
+            break;
+        }
+
+        if too_large {
+            continue;
+        }
+
+        // Undo dot-stuffing
+        let line = if line.starts_with(b"..") { &line[1..] } else { &line[..] };
+        data.extend_from_slice(line);
+
+        if data.len() > MAX_MESSAGE_SIZE {
+            too_large = true;
+            data.clear();
+        }
+    }
+
+    Ok(if too_large { None } else { Some(data) })
+}
+
+async fn process_message(
+    envelope: Envelope, db_pool: &crate::DBPool, celery_app: &celery::Celery,
+) -> &'static str {
+    let message = match mail_parser::MessageParser::default().parse(&envelope.data) {
+        Some(m) => m,
+        None => {
+            return "554 5.6.0 Invalid message\r\n";
+        }
+    };
+
+    let sender = message.from()
+        .and_then(|f| f.first())
+        .and_then(|a| a.address())
+        .map(|a| a.to_string())
+        .unwrap_or(envelope.mail_from)
+        .to_ascii_lowercase();
+
+    let imei = match message.subject() {
+        Some(s) => s.trim().to_string(),
+        None => {
+            return "554 5.6.0 Missing IMEI in subject\r\n";
+        }
+    };
+
+    let attachment = match message.attachments().find(|a| {
+        a.attachment_name().is_some_and(|n| n.to_ascii_lowercase().ends_with(".sbd"))
+    }) {
+        Some(a) => a.contents().to_vec(),
+        None => {
+            return "554 5.6.0 Missing SBD attachment\r\n";
+        }
+    };
+
+    let mut db_conn = match db_pool.get().await {
+        Ok(c) => c,
+        Err(err) => {
+            error!("Failed to get DB connection: {}", err);
+            return "451 4.3.0 Temporary failure\r\n";
+        }
+    };
+
+    // Addresses are unique regardless of case, so at most one target matches
+    let target = match crate::schema::targets::dsl::targets.filter(
+        lower(crate::schema::targets::dsl::email_address).eq(&sender)
+    ).get_result::<crate::models::Target>(&mut db_conn).await
+        .optional() {
+        Ok(Some(t)) => t,
+        Ok(None) => {
+            warn!("Rejecting MT email from unknown sender {}", sender);
+            return "550 5.7.1 Sender not authorised\r\n";
+        }
+        Err(err) => {
+            error!("Failed to get target: {}", err);
+            return "451 4.3.0 Temporary failure\r\n";
+        }
+    };
+
+    // The sender address is easily forged, so the message must also be sent to the target's token
+    let authorised = target.email_token.as_ref().is_some_and(|token| {
+        envelope.recipients.iter()
+            .filter_map(|r| recipient_token(r))
+            .any(|t| constant_time_eq::constant_time_eq(t.as_bytes(), token.as_bytes()))
+    });
+    if !authorised {
+        warn!("Rejecting MT email for target {} without a valid token", target.id);
+        return "550 5.7.1 Sender not authorised\r\n";
+    }
+
+    match crate::mt::submit_mt(&mut db_conn, celery_app, &target, crate::types::MTMessage {
+        imei,
+        payload: BASE64_STANDARD.encode(attachment),
+        priority: None,
+        client_reference: None,
+        metadata: None,
+        not_before: None,
+        expires_at: None,
+    }, None).await {
+        Ok(id) => {
+            info!("Accepted MT email {} for target {}", id, target.id);
+            "250 2.0.0 OK\r\n"
+        }
+        Err(crate::mt::SubmitError::Internal) => "451 4.3.0 Temporary failure\r\n",
+        Err(crate::mt::SubmitError::InvalidIMEILength) | Err(crate::mt::SubmitError::InvalidIMEI) =>
+            "554 5.1.1 Invalid IMEI\r\n",
+        Err(crate::mt::SubmitError::Forbidden) => "550 5.7.1 Not authorised to send to this IMEI\r\n",
+        Err(err) => {
+            warn!("Rejected MT email for target {}: {:?}", target.id, err);
+            "554 5.6.0 Invalid message\r\n"
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    /// Runs a session over `input`, accepting every message, and returns the responses sent
+    async fn converse(input: &[u8]) -> (std::io::Result<()>, String, Vec<super::Envelope>) {
+        let mut output = vec![];
+        let mut envelopes = vec![];
+        let result = super::session(input, &mut output, |envelope| {
+            envelopes.push(envelope);
+            std::future::ready("250 2.0.0 OK\r\n")
+        }).await;
+        (result, String::from_utf8(output).unwrap(), envelopes)
+    }
+
+    #[tokio::test]
+    async fn session_delivers_message() {
+        let (result, output, envelopes) = converse(
+            b"EHLO client\r\n\
+              MAIL FROM:<Ops@Example.com> SIZE=100\r\n\
+              RCPT TO:<mt+secret-token@kosmos.example.com>\r\n\
+              DATA\r\n\
+              Subject: 300234010753370\r\n\
+              \r\n\
+              ..leading dot\r\n\
+              .\r\n\
+              QUIT\r\n"
+        ).await;
+
+        assert!(result.is_ok());
+        assert_eq!(output, "220 Kosmos ESMTP\r\n\
+            250-Kosmos\r\n250-SIZE 1048576\r\n250 8BITMIME\r\n\
+            250 2.1.0 OK\r\n\
+            250 2.1.5 OK\r\n\
+            354 End data with <CR><LF>.<CR><LF>\r\n\
+            250 2.0.0 OK\r\n\
+            221 2.0.0 Bye\r\n");
+        assert_eq!(envelopes.len(), 1);
+        assert_eq!(envelopes[0].mail_from, "Ops@Example.com");
+        assert_eq!(envelopes[0].recipients, vec!["mt+secret-token@kosmos.example.com"]);
+        assert_eq!(envelopes[0].data, b"Subject: 300234010753370\r\n\r\n.leading dot\r\n");
+    }
+
+    #[tokio::test]
+    async fn session_enforces_command_order() {
+        let (result, output, envelopes) = converse(
+            b"HELO client\r\nRCPT TO:<a@b.c>\r\nMAIL FROM:<a@b.c>\r\nDATA\r\nRSET\r\nNOOP\r\nVRFY a\r\n"
+        ).await;
+
+        assert!(result.is_ok());
+        assert_eq!(output, "220 Kosmos ESMTP\r\n\
+            250 Kosmos\r\n\
+            503 5.5.1 MAIL first\r\n\
+            250 2.1.0 OK\r\n\
+            503 5.5.1 RCPT first\r\n\
+            250 2.0.0 OK\r\n\
+            250 2.0.0 OK\r\n\
+            502 5.5.2 Command not recognised\r\n");
+        assert!(envelopes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn session_rejects_long_command() {
+        let mut input = b"HELO ".to_vec();
+        input.extend(std::iter::repeat_n(b'a', 2000));
+        input.extend_from_slice(b"\r\nQUIT\r\n");
+
+        let (result, output, _) = converse(&input).await;
+
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        assert_eq!(output, "220 Kosmos ESMTP\r\n500 5.5.2 Line too long\r\n");
+    }
+
+    #[tokio::test]
+    async fn session_rejects_long_data_line() {
+        let mut input = b"MAIL FROM:<a@b.c>\r\nRCPT TO:<d@e.f>\r\nDATA\r\n".to_vec();
+        input.extend(std::iter::repeat_n(b'a', 2000));
+        input.extend_from_slice(b"\r\n.\r\n");
+
+        let (result, output, envelopes) = converse(&input).await;
+
+        assert_eq!(result.unwrap_err().kind(), std::io::ErrorKind::InvalidData);
+        assert!(output.ends_with("354 End data with <CR><LF>.<CR><LF>\r\n500 5.5.2 Line too long\r\n"));
+        assert!(envelopes.is_empty());
+    }
+
+    #[tokio::test]
+    async fn session_rejects_large_message() {
+        let mut input = b"MAIL FROM:<a@b.c>\r\nRCPT TO:<d@e.f>\r\nDATA\r\n".to_vec();
+        for _ in 0..(super::MAX_MESSAGE_SIZE / 500 + 1) {
+            input.extend(std::iter::repeat_n(b'a', 498));
+            input.extend_from_slice(b"\r\n");
+        }
+        input.extend_from_slice(b".\r\nRCPT TO:<d@e.f>\r\n");
+
+        let (result, output, envelopes) = converse(&input).await;
+
+        assert!(result.is_ok());
+        assert!(output.ends_with("354 End data with <CR><LF>.<CR><LF>\r\n\
+            552 5.3.4 Message too big\r\n\
+            503 5.5.1 MAIL first\r\n"));
+        assert!(envelopes.is_empty());
+    }
+
+    #[test]
+    fn parse_path() {
+        assert_eq!(super::parse_path("FROM:<a@b.c>"), "a@b.c");
+        assert_eq!(super::parse_path(" TO: <a@b.c> NOTIFY=NEVER"), "a@b.c");
+        assert_eq!(super::parse_path("FROM:<>"), "");
+    }
+
+    #[test]
+    fn recipient_token() {
+        assert_eq!(super::recipient_token("mt+secret@kosmos.example.com"), Some("secret"));
+        assert_eq!(super::recipient_token("mt+sec+ret@kosmos.example.com"), Some("sec+ret"));
+        assert_eq!(super::recipient_token("mt@kosmos.example.com"), None);
+        assert_eq!(super::recipient_token("mt+secret"), None);
+    }
+}
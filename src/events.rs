@@ -0,0 +1,353 @@
+use diesel::{ExpressionMethods, QueryDsl};
+use diesel_async::RunQueryDsl;
+use sha2::Digest;
+
+/// How long to remember which channels an event was delivered over
+const DELIVERY_RETENTION: chrono::Duration = chrono::Duration::days(7);
+
+/// Something a target is told about, from which each version of the webhook format is generated
+pub(crate) struct Event {
+    pub id: uuid::Uuid,
+    /// The CloudEvents source, see [`device_source`] and [`batch_source`]
+    pub source: String,
+    pub time: chrono::DateTime<chrono::Utc>,
+    pub device: Option<crate::types::EventDevice>,
+    pub message: crate::types::WebhookMessage,
+}
+
+impl Event {
+    /// `key` identifies the event, so that it keeps the same ID across delivery attempts
+    pub(crate) fn new(
+        key: &str, source: String, time: chrono::DateTime<chrono::Utc>,
+        device: Option<crate::types::EventDevice>, message: crate::types::WebhookMessage,
+    ) -> Self {
+        let hash = sha2::Sha256::digest(key.as_bytes());
+        let mut id = [0; 16];
+        id.copy_from_slice(&hash[..16]);
+
+        Event {
+            id: uuid::Builder::from_custom_bytes(id).into_uuid(),
+            source,
+            time,
+            device,
+            message,
+        }
+    }
+
+    /// Serializes the event for a target, returning the webhook request and the MQTT message
+    pub(crate) fn encode(self, target: &crate::models::Target) -> (Webhook, Vec<u8>) {
+        let (event_type, subject) = match &self.message {
+            crate::types::WebhookMessage::MOMessage(m) => ("kosmos.mo_message", Some(m.id)),
+            crate::types::WebhookMessage::MTMessageStatus(m) => ("kosmos.mt_message_status", Some(m.id)),
+            crate::types::WebhookMessage::MTBatchComplete(_) => ("kosmos.mt_batch_complete", None),
+        };
+        let (id, source, time) = (self.id, self.source.clone(), self.time);
+        let data = self.into_version(target.webhook_version);
+        let body = serde_json::to_vec(&data).unwrap();
+
+        let webhook = match target.cloud_events {
+            Some(mode) => cloud_event(mode, crate::types::CloudEvent {
+                specversion: "1.0".to_string(),
+                id,
+                source,
+                event_type: event_type.to_string(),
+                subject: subject.map(|s| s.to_string()),
+                time,
+                datacontenttype: "application/json".to_string(),
+                data: &data,
+            }, body.clone()),
+            None => Webhook {
+                content_type: "application/json",
+                headers: vec![],
+                body: body.clone(),
+            }
+        };
+
+        (webhook, body)
+    }
+
+    /// The event in the given webhook format version. Unknown versions are sent as version 1.
+    fn into_version(self, version: i16) -> Versioned {
+        match version {
+            2 => Versioned::V2(crate::types::WebhookEnvelope {
+                event_id: self.id,
+                event_time: self.time,
+                schema_version: 2,
+                device: self.device,
+                event: self.message.into(),
+            }),
+            _ => Versioned::V1(self.message),
+        }
+    }
+}
+
+/// An event in one version of the webhook format
+#[derive(serde::Serialize)]
+#[serde(untagged)]
+enum Versioned {
+    V1(crate::types::WebhookMessage),
+    V2(crate::types::WebhookEnvelope),
+}
+
+/// Wraps an event as a CloudEvent; `body` is the serialized data, sent as is in binary mode
+fn cloud_event(mode: crate::models::CloudEventsMode, event: crate::types::CloudEvent<&Versioned>, body: Vec<u8>) -> Webhook {
+    match mode {
+        crate::models::CloudEventsMode::Structured => Webhook {
+            content_type: "application/cloudevents+json",
+            headers: vec![],
+            body: serde_json::to_vec(&event).unwrap(),
+        },
+        crate::models::CloudEventsMode::Binary => {
+            let mut headers = vec![
+                ("ce-specversion", event.specversion),
+                ("ce-id", event.id.to_string()),
+                ("ce-source", event.source),
+                ("ce-type", event.event_type),
+                ("ce-time", event.time.to_rfc3339_opts(chrono::SecondsFormat::Millis, true)),
+            ];
+            if let Some(subject) = event.subject {
+                headers.push(("ce-subject", subject));
+            }
+
+            Webhook {
+                content_type: "application/json",
+                headers,
+                body,
+            }
+        }
+    }
+}
+
+/// An event serialized for a target's webhook endpoint
+pub(crate) struct Webhook {
+    pub content_type: &'static str,
+    /// Headers to send in addition to the signature headers
+    pub headers: Vec<(&'static str, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Webhook {
+    /// What the webhook's signatures cover, see [`crate::signing::webhook_signed_content`]
+    pub(crate) fn signed_content(&self) -> Vec<u8> {
+        crate::signing::webhook_signed_content(self.headers.iter().map(|(n, v)| (*n, v.as_str())), &self.body)
+    }
+}
+
+/// The CloudEvents source of events about a device
+pub(crate) fn device_source(imei: &str) -> String {
+    format!("/devices/{}", imei)
+}
+
+/// The CloudEvents source of events about a batch
+pub(crate) fn batch_source(batch_id: uuid::Uuid) -> String {
+    format!("/batches/{}", batch_id)
+}
+
+/// Describes a device in version 2 events
+pub(crate) async fn device(
+    db_conn: &mut crate::DBConn, device: crate::models::Device,
+) -> diesel::result::QueryResult<crate::types::EventDevice> {
+    let tags = crate::devices::tags(db_conn, device.id).await?;
+
+    Ok(crate::types::EventDevice {
+        imei: device.imei,
+        metadata: device.metadata,
+        tags,
+    })
+}
+
+/// Returns the channels an event has already been delivered over, so that retries skip them
+pub(crate) async fn delivered_channels(
+    db_conn: &mut crate::DBConn, event_id: uuid::Uuid,
+) -> diesel::result::QueryResult<Vec<crate::models::DeliveryChannel>> {
+    crate::schema::event_deliveries::dsl::event_deliveries
+        .filter(crate::schema::event_deliveries::dsl::event.eq(event_id))
+        .select(crate::schema::event_deliveries::dsl::channel)
+        .load(db_conn).await
+}
+
+pub(crate) async fn record_delivery(
+    db_conn: &mut crate::DBConn, event_id: uuid::Uuid, channel: crate::models::DeliveryChannel,
+) -> diesel::result::QueryResult<()> {
+    diesel::insert_into(crate::schema::event_deliveries::dsl::event_deliveries)
+        .values(crate::models::EventDelivery {
+            event: event_id,
+            channel,
+            delivered: chrono::Utc::now().naive_utc(),
+        })
+        .on_conflict_do_nothing()
+        .execute(db_conn).await?;
+    Ok(())
+}
+
+/// Forgets deliveries of events old enough that they're no longer being retried
+pub(crate) async fn prune_deliveries(db_conn: &mut crate::DBConn) -> diesel::result::QueryResult<usize> {
+    diesel::delete(crate::schema::event_deliveries::dsl::event_deliveries)
+        .filter(crate::schema::event_deliveries::dsl::delivered.lt((chrono::Utc::now() - DELIVERY_RETENTION).naive_utc()))
+        .execute(db_conn).await
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    const MESSAGE_ID: uuid::Uuid = uuid::uuid!("5f6c2a2e-8c4a-4f4e-9d6b-3c1f0f8a7b21");
+
+    fn target(webhook_version: i16, cloud_events: Option<crate::models::CloudEventsMode>) -> crate::models::Target {
+        crate::models::Target {
+            id: uuid::Uuid::new_v4(),
+            endpoint: Some("https://example.com/webhook".to_string()),
+            mqtt_broker: None,
+            mqtt_username: None,
+            mqtt_password: None,
+            email_address: None,
+            mt_signature_version: 1,
+            webhook_signing: crate::models::WebhookSigning::Hmac,
+            webhook_version,
+            cloud_events,
+            email_token: None,
+        }
+    }
+
+    fn event() -> super::Event {
+        super::Event::new(
+            "mt_message_status:test", super::device_source("300234010753370"),
+            chrono::Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap(),
+            Some(crate::types::EventDevice {
+                imei: "300234010753370".to_string(),
+                metadata: None,
+                tags: Default::default(),
+            }),
+            crate::types::WebhookMessage::MTMessageStatus(crate::types::MTMessageStatus {
+                id: MESSAGE_ID,
+                status: crate::types::MessageStatus::Delivered,
+                idempotency_key: None,
+                client_reference: None,
+                metadata: None,
+                batch: None,
+            }),
+        )
+    }
+
+    fn batch_event() -> super::Event {
+        let batch_id = uuid::Uuid::new_v4();
+        super::Event::new(
+            &format!("mt_batch_complete:{}", batch_id), super::batch_source(batch_id),
+            chrono::Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap(), None,
+            crate::types::WebhookMessage::MTBatchComplete(crate::types::MTBatchStatus {
+                id: batch_id,
+                client_reference: None,
+                group: None,
+                created: chrono::Utc.with_ymd_and_hms(2026, 10, 18, 11, 0, 0).unwrap(),
+                completed: Some(chrono::Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap()),
+                total: 0,
+                counts: vec![],
+            }),
+        )
+    }
+
+    fn v1_body() -> serde_json::Value {
+        serde_json::json!({"type": "mt_message_status", "id": MESSAGE_ID, "status": "delivered"})
+    }
+
+    fn header<'a>(webhook: &'a super::Webhook, name: &str) -> Option<&'a str> {
+        webhook.headers.iter().find(|(n, _)| *n == name).map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn sources() {
+        assert_eq!(super::device_source("300234010753370"), "/devices/300234010753370");
+        assert_eq!(
+            super::batch_source(uuid::uuid!("9a8b7c6d-5e4f-4a3b-8c2d-1e0f9a8b7c6d")),
+            "/batches/9a8b7c6d-5e4f-4a3b-8c2d-1e0f9a8b7c6d"
+        );
+    }
+
+    #[test]
+    fn ids_are_stable() {
+        assert_eq!(event().id, event().id);
+        assert_ne!(event().id, batch_event().id);
+    }
+
+    #[test]
+    fn encode_plain() {
+        let (webhook, mqtt) = event().encode(&target(1, None));
+        assert_eq!(webhook.content_type, "application/json");
+        assert!(webhook.headers.is_empty());
+        assert_eq!(webhook.body, mqtt);
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&webhook.body).unwrap(), v1_body());
+        assert_eq!(webhook.signed_content(), webhook.body);
+
+        let (webhook, mqtt) = event().encode(&target(2, None));
+        assert_eq!(webhook.body, mqtt);
+        let body = serde_json::from_slice::<serde_json::Value>(&webhook.body).unwrap();
+        assert_eq!(body["event_id"], serde_json::json!(event().id));
+        assert_eq!(body["schema_version"], 2);
+        assert_eq!(body["device"]["imei"], "300234010753370");
+        assert_eq!(body["type"], "mt_message_status");
+    }
+
+    #[test]
+    fn encode_structured() {
+        let event_id = event().id;
+        let (webhook, mqtt) = event().encode(&target(1, Some(crate::models::CloudEventsMode::Structured)));
+        assert_eq!(webhook.content_type, "application/cloudevents+json");
+        assert!(webhook.headers.is_empty());
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&webhook.body).unwrap(), serde_json::json!({
+            "specversion": "1.0",
+            "id": event_id,
+            "source": "/devices/300234010753370",
+            "type": "kosmos.mt_message_status",
+            "subject": MESSAGE_ID.to_string(),
+            "time": "2026-10-18T12:00:00Z",
+            "datacontenttype": "application/json",
+            "data": v1_body(),
+        }));
+        // MQTT messages are never CloudEvents
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&mqtt).unwrap(), v1_body());
+
+        // The data follows the target's webhook format version
+        let (webhook, mqtt) = event().encode(&target(2, Some(crate::models::CloudEventsMode::Structured)));
+        let body = serde_json::from_slice::<crate::types::CloudEvent<crate::types::WebhookEnvelope>>(&webhook.body).unwrap();
+        assert_eq!(body.id, event_id);
+        assert_eq!(body.data.event_id, event_id);
+        assert_eq!(body.data.schema_version, 2);
+        assert_eq!(
+            serde_json::to_value(&body.data).unwrap(),
+            serde_json::from_slice::<serde_json::Value>(&mqtt).unwrap()
+        );
+    }
+
+    #[test]
+    fn encode_binary() {
+        let event_id = event().id;
+        let (webhook, mqtt) = event().encode(&target(1, Some(crate::models::CloudEventsMode::Binary)));
+        assert_eq!(webhook.content_type, "application/json");
+        assert_eq!(webhook.body, mqtt);
+        assert_eq!(serde_json::from_slice::<serde_json::Value>(&webhook.body).unwrap(), v1_body());
+        assert_eq!(header(&webhook, "ce-specversion"), Some("1.0"));
+        assert_eq!(header(&webhook, "ce-id"), Some(event_id.to_string().as_str()));
+        assert_eq!(header(&webhook, "ce-source"), Some("/devices/300234010753370"));
+        assert_eq!(header(&webhook, "ce-type"), Some("kosmos.mt_message_status"));
+        assert_eq!(header(&webhook, "ce-time"), Some("2026-10-18T12:00:00.000Z"));
+        assert_eq!(header(&webhook, "ce-subject"), Some(MESSAGE_ID.to_string().as_str()));
+
+        // The attributes are signed along with the body
+        let mut content = format!(
+            "ce-id:{}\nce-source:/devices/300234010753370\nce-specversion:1.0\nce-subject:{}\n\
+            ce-time:2026-10-18T12:00:00.000Z\nce-type:kosmos.mt_message_status\n\n",
+            event_id, MESSAGE_ID
+        ).into_bytes();
+        content.extend_from_slice(&webhook.body);
+        assert_eq!(webhook.signed_content(), content);
+
+        let (webhook, _) = event().encode(&target(2, Some(crate::models::CloudEventsMode::Binary)));
+        let body = serde_json::from_slice::<crate::types::WebhookEnvelope>(&webhook.body).unwrap();
+        assert_eq!(body.event_id, event_id);
+
+        let (webhook, _) = batch_event().encode(&target(1, Some(crate::models::CloudEventsMode::Binary)));
+        assert_eq!(header(&webhook, "ce-type"), Some("kosmos.mt_batch_complete"));
+        assert!(header(&webhook, "ce-source").unwrap().starts_with("/batches/"));
+        assert_eq!(header(&webhook, "ce-subject"), None);
+    }
+}
\ No newline at end of file
@@ -1,12 +1,215 @@
 use base64::prelude::*;
 use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
 use diesel_async::RunQueryDsl;
-use hmac::Mac;
 use rocket::data::ToByteUnit;
+use sha2::Digest;
+
+struct RockBLOCKConfig {
+    public_key: Option<openssl::pkey::PKey<openssl::pkey::Public>>,
+}
+
+struct AdminConfig {
+    token: Option<String>,
+}
+
+/// An error response, sent as an RFC 9457 problem details object. `code` is stable, and safe to
+/// match on; `detail` is for humans.
+#[derive(Debug, Clone)]
+struct ApiError {
+    status: rocket::http::Status,
+    code: &'static str,
+    detail: String,
+    field: Option<String>,
+}
+
+impl ApiError {
+    fn new(status: rocket::http::Status, code: &'static str, detail: impl Into<String>) -> Self {
+        ApiError {
+            status,
+            code,
+            detail: detail.into(),
+            field: None,
+        }
+    }
+
+    /// The request field the error relates to
+    fn field(mut self, field: impl Into<String>) -> Self {
+        self.field = Some(field.into());
+        self
+    }
+
+    fn internal() -> Self {
+        Self::new(rocket::http::Status::InternalServerError, "internal_error", "An internal error occurred, please try again later")
+    }
+
+    fn not_found(detail: impl Into<String>) -> Self {
+        Self::new(rocket::http::Status::NotFound, "not_found", detail)
+    }
+
+    fn unauthorized(code: &'static str, detail: impl Into<String>) -> Self {
+        Self::new(rocket::http::Status::Unauthorized, code, detail)
+    }
+
+    fn bad_request(code: &'static str, detail: impl Into<String>) -> Self {
+        Self::new(rocket::http::Status::BadRequest, code, detail)
+    }
+}
+
+impl<'r> rocket::response::Responder<'r, 'static> for ApiError {
+    fn respond_to(self, _request: &'r rocket::request::Request<'_>) -> rocket::response::Result<'static> {
+        let body = serde_json::to_string(&crate::types::Problem {
+            problem_type: format!("urn:kosmos:error:{}", self.code),
+            title: self.status.reason().unwrap_or("Error").to_string(),
+            status: self.status.code,
+            code: self.code.to_string(),
+            detail: Some(self.detail),
+            field: self.field,
+        }).unwrap();
+
+        rocket::response::Response::build()
+            .status(self.status)
+            .header(rocket::http::ContentType::new("application", "problem+json"))
+            .sized_body(body.len(), std::io::Cursor::new(body))
+            .ok()
+    }
+}
+
+/// Why a request guard failed, for the catcher to report, as guard errors aren't otherwise
+/// available to it
+struct GuardError(Option<ApiError>);
+
+fn guard_error<S>(request: &rocket::request::Request<'_>, err: ApiError) -> rocket::request::Outcome<S, ()> {
+    let status = err.status;
+    request.local_cache(|| GuardError(Some(err)));
+    rocket::request::Outcome::Error((status, ()))
+}
+
+#[rocket::catch(default)]
+fn default_catcher(status: rocket::http::Status, request: &rocket::request::Request<'_>) -> ApiError {
+    if let Some(err) = &request.local_cache(|| GuardError(None)).0 {
+        return err.clone();
+    }
+
+    match status.code {
+        400 => ApiError::bad_request("bad_request", "The request couldn't be parsed"),
+        404 => ApiError::not_found("No such resource, or the wrong Content-Type was given"),
+        405 => ApiError::new(status, "method_not_allowed", "This method isn't supported here"),
+        413 => ApiError::new(status, "body_too_large", "The request body is too large"),
+        422 => ApiError::new(status, "invalid_body", "The request body isn't valid JSON of the right shape"),
+        500 => ApiError::internal(),
+        _ => ApiError::new(status, "error", status.reason().unwrap_or("Error")),
+    }
+}
+
+/// Reports an unparsable request body, giving the field at fault where serde can tell
+fn json_error(err: serde_json::Error) -> ApiError {
+    let message = err.to_string();
+    let field = message.strip_prefix("missing field `")
+        .and_then(|m| m.split_once('`'))
+        .map(|(field, _)| field.to_string());
+    let err = ApiError::bad_request("invalid_json", message);
+    match field {
+        Some(f) => err.field(f),
+        None => err
+    }
+}
+
+fn parse_json<'a, T: serde::Deserialize<'a>>(body: &'a [u8]) -> Result<T, ApiError> {
+    serde_json::from_slice(body).map_err(json_error)
+}
+
+/// Unwraps a body parsed by Rocket's JSON guard
+fn json_body<T>(data: Result<rocket::serde::json::Json<T>, rocket::serde::json::Error<'_>>) -> Result<T, ApiError> {
+    match data {
+        Ok(d) => Ok(d.into_inner()),
+        Err(rocket::serde::json::Error::Io(_)) =>
+            Err(ApiError::bad_request("bad_request", "The request body couldn't be read")),
+        Err(rocket::serde::json::Error::Parse(_, err)) => Err(json_error(err)),
+    }
+}
+
+fn invalid_param(name: &str) -> ApiError {
+    ApiError::bad_request("invalid_parameter", format!("Invalid value for `{}`", name)).field(name)
+}
+
+/// A request authenticated with the admin token
+struct AdminAuth;
+
+#[rocket::async_trait]
+impl<'r> rocket::request::FromRequest<'r> for AdminAuth {
+    type Error = ();
+
+    async fn from_request(request: &'r rocket::request::Request<'_>) -> rocket::request::Outcome<Self, Self::Error> {
+        // The admin API is disabled entirely if no token is configured
+        let token = match request.rocket().state::<AdminConfig>().and_then(|c| c.token.as_ref()) {
+            Some(t) => t,
+            None => return guard_error(request, ApiError::not_found("No such resource"))
+        };
+
+        match request.headers().get_one("Authorization").and_then(|h| h.strip_prefix("Bearer ")) {
+            Some(t) if constant_time_eq::constant_time_eq(t.as_bytes(), token.as_bytes()) =>
+                rocket::request::Outcome::Success(AdminAuth),
+            _ => guard_error(request, ApiError::unauthorized("invalid_admin_token", "Missing or invalid admin token")
+                .field("Authorization"))
+        }
+    }
+}
 
 struct Auth {
     id: uuid::Uuid,
-    signature: Vec<u8>
+    signature: Vec<u8>,
+    version: SignatureVersion,
+}
+
+enum SignatureVersion {
+    V1,
+    V2 {
+        method: String,
+        path: String,
+        timestamp: i64,
+        nonce: String,
+    },
+}
+
+fn invalid_header(name: &'static str, detail: &str) -> ApiError {
+    ApiError::unauthorized("invalid_auth_header", detail).field(name)
+}
+
+fn parse_auth(request: &rocket::request::Request<'_>) -> Result<Auth, ApiError> {
+    let id = request.headers().get_one("Kosmos-Target-ID")
+        .and_then(|id| uuid::Uuid::parse_str(id).ok())
+        .ok_or_else(|| invalid_header("Kosmos-Target-ID", "Kosmos-Target-ID must be given, as a UUID"))?;
+    let signature = request.headers().get_one("Kosmos-MAC")
+        .and_then(|mac| BASE64_STANDARD.decode(mac).ok())
+        .ok_or_else(|| invalid_header("Kosmos-MAC", "Kosmos-MAC must be given, Base64 encoded"))?;
+
+    let version = match request.headers().get_one("Kosmos-Signature-Version") {
+        None | Some("1") => SignatureVersion::V1,
+        Some("2") => {
+            let timestamp = request.headers().get_one("Kosmos-Timestamp")
+                .and_then(|t| t.parse::<i64>().ok())
+                .ok_or_else(|| invalid_header("Kosmos-Timestamp", "Kosmos-Timestamp must be given, as a Unix timestamp"))?;
+            let nonce = request.headers().get_one("Kosmos-Nonce").unwrap_or_default();
+            if nonce.is_empty() || nonce.len() > 128 || !nonce.chars().all(|c| c.is_ascii_graphic()) {
+                return Err(invalid_header(
+                    "Kosmos-Nonce", "Kosmos-Nonce must be given, as up to 128 printable ASCII characters"
+                ));
+            }
+            SignatureVersion::V2 {
+                method: request.method().as_str().to_string(),
+                path: request.uri().to_string(),
+                timestamp,
+                nonce: nonce.to_string(),
+            }
+        }
+        Some(_) => return Err(invalid_header("Kosmos-Signature-Version", "Kosmos-Signature-Version must be 1 or 2"))
+    };
+
+    Ok(Auth {
+        id,
+        signature,
+        version,
+    })
 }
 
 #[rocket::async_trait]
@@ -14,124 +217,1143 @@ impl<'r> rocket::request::FromRequest<'r> for Auth {
     type Error = ();
 
     async fn from_request(request: &'r rocket::request::Request<'_>) -> rocket::request::Outcome<Self, Self::Error> {
-        let id = request.headers().get_one("Kosmos-Target-ID");
-        let token = request.headers().get_one("Kosmos-MAC");
-        match (id, token) {
-            (Some(id), Some(token)) => match BASE64_STANDARD.decode(token) {
-                Ok(t) => match uuid::Uuid::parse_str(id) {
-                    Ok(i) => rocket::request::Outcome::Success(Auth {
-                        id: i,
-                        signature: t
-                    }),
-                    Err(_) => rocket::request::Outcome::Error((rocket::http::Status::Unauthorized, ()))
-                },
-                Err(_) => rocket::request::Outcome::Error((rocket::http::Status::Unauthorized, ()))
-            },
-            _ => rocket::request::Outcome::Error((rocket::http::Status::Unauthorized, ()))
+        match parse_auth(request) {
+            Ok(a) => rocket::request::Outcome::Success(a),
+            Err(err) => guard_error(request, err)
         }
     }
 }
 
-#[rocket::get("/submit_mt")]
-fn submit_mt_get() -> rocket::http::Status {
-    rocket::http::Status::MethodNotAllowed
-}
+struct IdempotencyKeyHeader(Option<String>);
 
-#[rocket::post("/submit_mt", data = "<data>", format = "application/json")]
-async fn submit_mt(
-    db: &rocket::State<crate::DBPool>, celery_app: &rocket::State<std::sync::Arc<celery::Celery>>,
-    auth: Auth, data: rocket::data::Data<'_>
-) -> Result<String, rocket::http::Status> {
-    let mut db_conn = match db.get().await {
-        Ok(c) => c,
-        Err(err) => {
-            error!("Failed to get DB connection: {}", err);
-            return Err(rocket::http::Status::InternalServerError);
+#[rocket::async_trait]
+impl<'r> rocket::request::FromRequest<'r> for IdempotencyKeyHeader {
+    type Error = ();
+
+    async fn from_request(request: &'r rocket::request::Request<'_>) -> rocket::request::Outcome<Self, Self::Error> {
+        match request.headers().get_one("Idempotency-Key") {
+            None => rocket::request::Outcome::Success(IdempotencyKeyHeader(None)),
+            Some(k) if !k.is_empty() && k.len() <= 255 && k.chars().all(|c| c.is_ascii_graphic()) =>
+                rocket::request::Outcome::Success(IdempotencyKeyHeader(Some(k.to_string()))),
+            Some(_) => guard_error(request, ApiError::bad_request(
+                "invalid_idempotency_key", "Idempotency keys must be up to 255 printable ASCII characters"
+            ).field("Idempotency-Key"))
         }
-    };
+    }
+}
+
+/// Records a nonce as used, returning false if it has been seen before
+async fn record_nonce(db_conn: &mut crate::DBConn, target_id: uuid::Uuid, nonce: &str) -> diesel::result::QueryResult<bool> {
+    let now = chrono::Utc::now().naive_utc();
+
+    // Nonces older than the skew window can't be replayed anyway, as their timestamp will be rejected
+    diesel::delete(crate::schema::request_nonces::dsl::request_nonces)
+        .filter(crate::schema::request_nonces::dsl::seen.lt(
+            now - chrono::Duration::seconds(2 * crate::signing::MAX_REQUEST_SKEW)
+        ))
+        .execute(db_conn).await?;
+
+    let inserted = diesel::insert_into(crate::schema::request_nonces::dsl::request_nonces)
+        .values((
+            crate::schema::request_nonces::dsl::target.eq(target_id),
+            crate::schema::request_nonces::dsl::nonce.eq(nonce),
+            crate::schema::request_nonces::dsl::seen.eq(now),
+        ))
+        .on_conflict_do_nothing()
+        .execute(db_conn).await?;
+
+    Ok(inserted != 0)
+}
 
+async fn authenticate(db_conn: &mut crate::DBConn, auth: &Auth, body: &[u8]) -> Result<crate::models::Target, ApiError> {
     let target = match crate::schema::targets::dsl::targets.filter(
         crate::schema::targets::dsl::id.eq(&auth.id)
-    ).get_result::<crate::models::Target>(&mut db_conn).await
+    ).get_result::<crate::models::Target>(db_conn).await
         .optional() {
         Ok(Some(t)) => t,
         Ok(None) => {
-            return Err(rocket::http::Status::Unauthorized);
+            return Err(ApiError::unauthorized("unknown_target", "No target exists with this ID").field("Kosmos-Target-ID"));
         }
         Err(err) => {
             error!("Failed to get target: {}", err);
-            return Err(rocket::http::Status::InternalServerError);
+            return Err(ApiError::internal());
         }
     };
 
-    let mut mac = crate::HmacSha256::new_from_slice(&target.hmac_key).unwrap();
+    match &auth.version {
+        SignatureVersion::V1 => {
+            if target.mt_signature_version > 1 {
+                return Err(ApiError::unauthorized(
+                    "signature_version_not_allowed", "This target requires version 2 signatures"
+                ).field("Kosmos-Signature-Version"));
+            }
+            // A version 1 signature of an empty body is the same for every request, so one captured
+            // from any request could be replayed against any other, reads included
+            if body.is_empty() {
+                return Err(ApiError::unauthorized(
+                    "signature_version_not_allowed", "Requests without a body require version 2 signatures"
+                ).field("Kosmos-Signature-Version"));
+            }
+        }
+        SignatureVersion::V2 { timestamp, .. } => {
+            if (chrono::Utc::now().timestamp() - timestamp).abs() > crate::signing::MAX_REQUEST_SKEW {
+                return Err(ApiError::unauthorized("timestamp_out_of_range", format!(
+                    "Kosmos-Timestamp must be within {} seconds of the current time", crate::signing::MAX_REQUEST_SKEW
+                )).field("Kosmos-Timestamp"));
+            }
+        }
+    }
 
-    let body = data.open(4.kibibytes()).into_bytes().await
-        .map_err(|_| rocket::http::Status::InternalServerError)?;
+    let keys = match crate::keys::active_keys(target.id, db_conn).await {
+        Ok(k) => k,
+        Err(err) => {
+            error!("Failed to get target keys: {}", err);
+            return Err(ApiError::internal());
+        }
+    };
+
+    // Any active key is accepted, so that clients can switch keys at their own pace during a rotation
+    if !keys.iter().any(|key| {
+        let mac_result = match &auth.version {
+            SignatureVersion::V1 => crate::signing::request_mac_v1(&key.key, body),
+            SignatureVersion::V2 { method, path, timestamp, nonce } =>
+                crate::signing::request_mac_v2(&key.key, method, path, *timestamp, nonce, body)
+        };
+        constant_time_eq::constant_time_eq(&mac_result, &auth.signature)
+    }) {
+        return Err(ApiError::unauthorized(
+            "invalid_signature", "Kosmos-MAC doesn't match the request for any of the target's active keys"
+        ).field("Kosmos-MAC"));
+    }
+
+    if let SignatureVersion::V2 { nonce, .. } = &auth.version {
+        match record_nonce(db_conn, target.id, nonce).await {
+            Ok(true) => {}
+            Ok(false) => {
+                warn!("Replayed request for target {}", target.id);
+                return Err(ApiError::unauthorized("nonce_reused", "This nonce has already been used").field("Kosmos-Nonce"));
+            }
+            Err(err) => {
+                error!("Failed to record nonce: {}", err);
+                return Err(ApiError::internal());
+            }
+        }
+    }
+
+    Ok(target)
+}
+
+async fn get_db_conn(db: &crate::DBPool) -> Result<crate::DBConn, ApiError> {
+    db.get().await.map_err(|err| {
+        error!("Failed to get DB connection: {}", err);
+        ApiError::internal()
+    })
+}
+
+async fn read_body(data: rocket::data::Data<'_>, limit: rocket::data::ByteUnit) -> Result<Vec<u8>, ApiError> {
+    let body = data.open(limit).into_bytes().await
+        .map_err(|_| ApiError::bad_request("bad_request", "The request body couldn't be read"))?;
     if !body.is_complete() {
-        return Err(rocket::http::Status::PayloadTooLarge);
+        return Err(ApiError::new(
+            rocket::http::Status::PayloadTooLarge, "body_too_large", format!("The request body may be at most {}", limit)
+        ));
     }
-    mac.update(&body);
-    let mac_result = mac.finalize().into_bytes();
+    Ok(body.into_inner())
+}
+
+fn submit_error(err: crate::mt::SubmitError) -> ApiError {
+    match err {
+        crate::mt::SubmitError::InvalidIMEILength =>
+            ApiError::bad_request("invalid_imei", "IMEIs must be 15 digits long").field("imei"),
+        crate::mt::SubmitError::InvalidIMEI =>
+            ApiError::bad_request("invalid_imei", "IMEIs may only contain digits").field("imei"),
+        crate::mt::SubmitError::InvalidPayload =>
+            ApiError::bad_request("invalid_payload", "A Base64 encoded payload must be given").field("payload"),
+        crate::mt::SubmitError::PayloadTooLarge(max_size) => ApiError::bad_request(
+            "payload_too_large", format!("Payloads to this device may be at most {} bytes", max_size)
+        ).field("payload"),
+        crate::mt::SubmitError::InvalidPriority =>
+            ApiError::bad_request("invalid_priority", "Priorities must be between 1 and 5").field("priority"),
+        crate::mt::SubmitError::InvalidClientReference => ApiError::bad_request(
+            "invalid_client_reference",
+            format!("Client references must be between 1 and {} bytes", crate::mt::MAX_CLIENT_REFERENCE_LENGTH)
+        ).field("client_reference"),
+        crate::mt::SubmitError::InvalidMetadata =>
+            ApiError::bad_request("invalid_metadata", "Metadata must be a JSON object").field("metadata"),
+        crate::mt::SubmitError::InvalidSchedule => ApiError::bad_request(
+            "invalid_expiry", "expires_at must be in the future, and after not_before"
+        ).field("expires_at"),
+        crate::mt::SubmitError::Forbidden => ApiError::new(
+            rocket::http::Status::Forbidden, "forbidden_imei", "The target may not send to this device"
+        ).field("imei"),
+        crate::mt::SubmitError::IdempotencyConflict => ApiError::new(
+            rocket::http::Status::Conflict, "idempotency_conflict",
+            "This idempotency key was already used for a different request"
+        ).field("Idempotency-Key"),
+        crate::mt::SubmitError::Internal => ApiError::internal(),
+    }
+}
+
+#[rocket::get("/submit_mt")]
+fn submit_mt_get() -> rocket::http::Status {
+    rocket::http::Status::MethodNotAllowed
+}
+
+#[rocket::post("/submit_mt", data = "<data>", format = "application/json")]
+async fn submit_mt(
+    db: &rocket::State<crate::DBPool>, celery_app: &rocket::State<std::sync::Arc<celery::Celery>>,
+    auth: Auth, idempotency_key: IdempotencyKeyHeader, data: rocket::data::Data<'_>
+) -> Result<String, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+    let body = read_body(data, 4.kibibytes()).await?;
+
+    let target = authenticate(&mut db_conn, &auth, &body).await?;
+
+    let request: crate::types::MTMessage = parse_json(&body)?;
+
+    let idempotency_key = idempotency_key.0.map(|key| crate::mt::IdempotencyKey {
+        key,
+        request_hash: sha2::Sha256::digest(&body).to_vec(),
+    });
 
-    if !constant_time_eq::constant_time_eq(&mac_result, &auth.signature) {
-        return Err(rocket::http::Status::Unauthorized);
+    match crate::mt::submit_mt(&mut db_conn, celery_app, &target, request, idempotency_key).await {
+        Ok(id) => Ok(id.to_string()),
+        Err(err) => Err(submit_error(err)),
     }
+}
+
+/// Default and maximum number of messages returned from a listing
+const DEFAULT_PAGE_SIZE: u32 = 50;
+const MAX_PAGE_SIZE: u32 = 500;
 
-    let request: crate::types::MTMessage = serde_json::from_slice(&body)
-        .map_err(|_| rocket::http::Status::BadRequest)?;
+fn parse_time(value: &str) -> Option<chrono::NaiveDateTime> {
+    chrono::DateTime::parse_from_rfc3339(value).ok().map(|t| t.naive_utc())
+}
+
+fn parse_processing_status(value: &str) -> Option<crate::models::ProcessingStatus> {
+    match value {
+        "received" => Some(crate::models::ProcessingStatus::Received),
+        "done" => Some(crate::models::ProcessingStatus::Done),
+        "failed" => Some(crate::models::ProcessingStatus::Failed),
+        "scheduled" => Some(crate::models::ProcessingStatus::Scheduled),
+        "sending" => Some(crate::models::ProcessingStatus::Sending),
+        _ => None
+    }
+}
 
-    if request.imei.len() != 15 {
-        return Err(rocket::http::Status::Unauthorized);
+fn parse_message_status(value: &str) -> Option<crate::models::MessageStatus> {
+    match value {
+        "delivered" => Some(crate::models::MessageStatus::Delivered),
+        "invalid_imei" => Some(crate::models::MessageStatus::InvalidImei),
+        "payload_size_exceeded" => Some(crate::models::MessageStatus::PayloadSizeExceeded),
+        "message_queue_full" => Some(crate::models::MessageStatus::MessageQueueFull),
+        "resources_unavailable" => Some(crate::models::MessageStatus::ResourcesUnavailable),
+        "cancelled" => Some(crate::models::MessageStatus::Cancelled),
+        "expired" => Some(crate::models::MessageStatus::Expired),
+        "rejected" => Some(crate::models::MessageStatus::Rejected),
+        _ => None
     }
-    if !request.imei.chars().all(|c| c.is_ascii_digit()) {
-        return Err(rocket::http::Status::BadRequest);
+}
+
+fn parse_session_status(value: &str) -> Option<crate::models::SessionStatus> {
+    match value {
+        "normal" => Some(crate::models::SessionStatus::Successful),
+        "too_large" => Some(crate::models::SessionStatus::SuccessfulTooLarge),
+        "unacceptable_location" => Some(crate::models::SessionStatus::SuccessfulUnacceptableLocation),
+        "timeout" => Some(crate::models::SessionStatus::Timeout),
+        "mo_too_large" => Some(crate::models::SessionStatus::TooLarge),
+        "rf_link_lost" => Some(crate::models::SessionStatus::RfLinkLost),
+        "protocol_anomaly" => Some(crate::models::SessionStatus::ProtocolAnomaly),
+        "imei_blocked" => Some(crate::models::SessionStatus::ImeiBlocked),
+        _ => None
     }
+}
 
-    let msg_data = BASE64_STANDARD.decode(request.payload)
-        .map_err(|_| rocket::http::Status::BadRequest)?;
+#[rocket::get("/mt/<message_id>")]
+async fn get_mt(
+    db: &rocket::State<crate::DBPool>, auth: Auth, message_id: uuid::Uuid,
+) -> Result<rocket::serde::json::Json<crate::types::MTMessageInfo>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+    let target = authenticate(&mut db_conn, &auth, &[]).await?;
 
-    let priority = if let Some(p) = request.priority {
-        if p < 1 || p > 5 {
-            return Err(rocket::http::Status::BadRequest);
+    match crate::mt::get_message(&mut db_conn, &target, message_id).await {
+        Ok(Some(m)) => Ok(rocket::serde::json::Json(crate::mt::message_info(m))),
+        Ok(None) => Err(ApiError::not_found("No such MT message")),
+        Err(err) => {
+            error!("Failed to get MT message: {}", err);
+            Err(ApiError::internal())
         }
-        p as i16
-    } else {
-        0
+    }
+}
+
+#[rocket::delete("/mt/<message_id>?<flush>")]
+async fn cancel_mt(
+    db: &rocket::State<crate::DBPool>, celery_app: &rocket::State<std::sync::Arc<celery::Celery>>,
+    auth: Auth, message_id: uuid::Uuid, flush: Option<bool>,
+) -> Result<rocket::http::Status, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+    let target = authenticate(&mut db_conn, &auth, &[]).await?;
+
+    match crate::mt::cancel_mt(&mut db_conn, celery_app, &target, message_id, flush.unwrap_or(false)).await {
+        Ok(crate::mt::CancelOutcome::Cancelled) => Ok(rocket::http::Status::NoContent),
+        Ok(crate::mt::CancelOutcome::FlushRequested) => Ok(rocket::http::Status::Accepted),
+        Err(crate::mt::CancelError::NotFound) => Err(ApiError::not_found("No such MT message")),
+        Err(crate::mt::CancelError::NotCancellable) => Err(ApiError::new(
+            rocket::http::Status::Conflict, "not_cancellable",
+            "The message is being sent, has already been accepted by the gateway, or has failed; use flush=true to flush the device's queue once accepted"
+        )),
+        Err(crate::mt::CancelError::Internal) => Err(ApiError::internal()),
+    }
+}
+
+#[rocket::get("/mt?<imei>&<group>&<processing_status>&<message_status>&<batch>&<since>&<until>&<cursor>&<limit>")]
+#[allow(clippy::too_many_arguments)]
+async fn list_mt(
+    db: &rocket::State<crate::DBPool>, auth: Auth, imei: Option<String>, group: Option<uuid::Uuid>,
+    processing_status: Option<String>,
+    message_status: Option<String>, batch: Option<uuid::Uuid>, since: Option<String>, until: Option<String>,
+    cursor: Option<String>, limit: Option<u32>,
+) -> Result<rocket::serde::json::Json<crate::types::MTMessageList>, ApiError> {
+    let filter = crate::mt::MessageFilter {
+        imei,
+        group,
+        processing_status: processing_status.map(|s| parse_processing_status(&s)
+            .ok_or_else(|| invalid_param("processing_status"))).transpose()?,
+        message_status: message_status.map(|s| parse_message_status(&s)
+            .ok_or_else(|| invalid_param("message_status"))).transpose()?,
+        batch,
+        since: since.map(|t| parse_time(&t)
+            .ok_or_else(|| invalid_param("since"))).transpose()?,
+        until: until.map(|t| parse_time(&t)
+            .ok_or_else(|| invalid_param("until"))).transpose()?,
+        cursor: cursor.map(|c| crate::mt::Cursor::decode(&c)
+            .ok_or_else(|| invalid_param("cursor"))).transpose()?,
+        limit: limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE) as i64,
     };
 
-    let mt_message = crate::models::MTMessage {
-        id: uuid::Uuid::new_v4(),
-        imei: request.imei,
-        data: msg_data,
-        priority,
-        message_status: None,
-        processing_status: crate::models::ProcessingStatus::Received,
-        received: chrono::Utc::now().naive_utc(),
-        target: target.id
+    let mut db_conn = get_db_conn(db).await?;
+    let target = authenticate(&mut db_conn, &auth, &[]).await?;
+
+    let (messages, next_cursor) = crate::mt::list_messages(&mut db_conn, &target, filter).await
+        .map_err(|err| {
+            error!("Failed to list MT messages: {}", err);
+            ApiError::internal()
+        })?
+        .ok_or_else(|| ApiError::not_found("No such group"))?;
+
+    Ok(rocket::serde::json::Json(crate::types::MTMessageList {
+        messages: messages.into_iter().map(crate::mt::message_info).collect(),
+        next_cursor: next_cursor.map(|c| c.encode()),
+    }))
+}
+
+#[rocket::get("/mo/<message_id>")]
+async fn get_mo(
+    db: &rocket::State<crate::DBPool>, auth: Auth, message_id: uuid::Uuid,
+) -> Result<rocket::serde::json::Json<crate::types::MOMessageInfo>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+    let target = authenticate(&mut db_conn, &auth, &[]).await?;
+
+    match crate::mo::get_message(&mut db_conn, &target, message_id).await {
+        Ok(Some(m)) => Ok(rocket::serde::json::Json(crate::mo::message_info(m))),
+        Ok(None) => Err(ApiError::not_found("No such MO message")),
+        Err(err) => {
+            error!("Failed to get MO message: {}", err);
+            Err(ApiError::internal())
+        }
+    }
+}
+
+#[rocket::get("/mo/<message_id>/payload")]
+async fn get_mo_payload(
+    db: &rocket::State<crate::DBPool>, auth: Auth, message_id: uuid::Uuid,
+) -> Result<(rocket::http::ContentType, Vec<u8>), ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+    let target = authenticate(&mut db_conn, &auth, &[]).await?;
+
+    match crate::mo::get_message(&mut db_conn, &target, message_id).await {
+        Ok(Some(crate::models::MOMessage { data: Some(data), .. })) => Ok((rocket::http::ContentType::Binary, data)),
+        Ok(_) => Err(ApiError::not_found("No such MO message, or it has no payload")),
+        Err(err) => {
+            error!("Failed to get MO message: {}", err);
+            Err(ApiError::internal())
+        }
+    }
+}
+
+#[rocket::get("/mo?<imei>&<group>&<session_status>&<processing_status>&<since>&<until>&<cursor>&<limit>")]
+#[allow(clippy::too_many_arguments)]
+async fn list_mo(
+    db: &rocket::State<crate::DBPool>, auth: Auth, imei: Option<String>, group: Option<uuid::Uuid>,
+    session_status: Option<String>,
+    processing_status: Option<String>, since: Option<String>, until: Option<String>, cursor: Option<String>,
+    limit: Option<u32>,
+) -> Result<rocket::serde::json::Json<crate::types::MOMessageList>, ApiError> {
+    let filter = crate::mo::MessageFilter {
+        imei,
+        group,
+        session_status: session_status.map(|s| parse_session_status(&s)
+            .ok_or_else(|| invalid_param("session_status"))).transpose()?,
+        processing_status: processing_status.map(|s| parse_processing_status(&s)
+            .ok_or_else(|| invalid_param("processing_status"))).transpose()?,
+        since: since.map(|t| parse_time(&t)
+            .ok_or_else(|| invalid_param("since"))).transpose()?,
+        until: until.map(|t| parse_time(&t)
+            .ok_or_else(|| invalid_param("until"))).transpose()?,
+        cursor: cursor.map(|c| crate::mt::Cursor::decode(&c)
+            .ok_or_else(|| invalid_param("cursor"))).transpose()?,
+        limit: limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE) as i64,
+    };
+
+    let mut db_conn = get_db_conn(db).await?;
+    let target = authenticate(&mut db_conn, &auth, &[]).await?;
+
+    let (messages, next_cursor) = crate::mo::list_messages(&mut db_conn, &target, filter).await
+        .map_err(|err| {
+            error!("Failed to list MO messages: {}", err);
+            ApiError::internal()
+        })?
+        .ok_or_else(|| ApiError::not_found("No such group"))?;
+
+    Ok(rocket::serde::json::Json(crate::types::MOMessageList {
+        messages: messages.into_iter().map(crate::mo::message_info).collect(),
+        next_cursor: next_cursor.map(|c| c.encode()),
+    }))
+}
+
+fn batch_error(err: crate::batches::BatchError) -> ApiError {
+    match err {
+        crate::batches::BatchError::NotFound => ApiError::not_found("No such batch"),
+        crate::batches::BatchError::Empty => ApiError::bad_request("empty_batch", "The batch has no devices"),
+        crate::batches::BatchError::TooLarge => ApiError::new(
+            rocket::http::Status::PayloadTooLarge, "batch_too_large",
+            format!("Batches may have at most {} devices", crate::batches::MAX_BATCH_SIZE)
+        ),
+        crate::batches::BatchError::DuplicateIMEI(i) => ApiError::bad_request(
+            "duplicate_imei", format!("Destination {}: the device is already in the batch", i)
+        ).field("imei"),
+        crate::batches::BatchError::UnknownGroup =>
+            ApiError::bad_request("unknown_group", "No such device group").field("group"),
+        // Destinations are numbered across imeis, the group's devices, then messages
+        crate::batches::BatchError::Invalid(i, err) => {
+            let mut err = submit_error(err);
+            err.detail = format!("Destination {}: {}", i, err.detail);
+            err
+        }
+        crate::batches::BatchError::IdempotencyConflict => ApiError::new(
+            rocket::http::Status::Conflict, "idempotency_conflict",
+            "This idempotency key was already used for a different request"
+        ).field("Idempotency-Key"),
+        crate::batches::BatchError::Internal => ApiError::internal(),
+    }
+}
+
+#[rocket::post("/batches", data = "<data>", format = "application/json")]
+async fn create_batch(
+    db: &rocket::State<crate::DBPool>, auth: Auth, idempotency_key: IdempotencyKeyHeader, data: rocket::data::Data<'_>
+) -> Result<rocket::serde::json::Json<crate::types::MTBatchStatus>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+    let body = read_body(data, 1.mebibytes()).await?;
+    let target = authenticate(&mut db_conn, &auth, &body).await?;
+
+    let request: crate::types::NewMTBatch = parse_json(&body)?;
+
+    let idempotency_key = idempotency_key.0.map(|key| crate::mt::IdempotencyKey {
+        key,
+        request_hash: sha2::Sha256::digest(&body).to_vec(),
+    });
+
+    let batch = crate::batches::create(&mut db_conn, &target, request, idempotency_key).await
+        .map_err(batch_error)?;
+
+    let status = crate::batches::batch_status(&mut db_conn, batch).await
+        .map_err(|err| {
+            error!("Failed to get batch status: {}", err);
+            ApiError::internal()
+        })?;
+
+    Ok(rocket::serde::json::Json(status))
+}
+
+#[rocket::get("/batches/<batch_id>")]
+async fn get_batch(
+    db: &rocket::State<crate::DBPool>, auth: Auth, batch_id: uuid::Uuid,
+) -> Result<rocket::serde::json::Json<crate::types::MTBatchStatus>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+    let target = authenticate(&mut db_conn, &auth, &[]).await?;
+
+    let batch = crate::batches::get(&mut db_conn, &target, batch_id).await
+        .map_err(batch_error)?;
+
+    let status = crate::batches::batch_status(&mut db_conn, batch).await
+        .map_err(|err| {
+            error!("Failed to get batch status: {}", err);
+            ApiError::internal()
+        })?;
+
+    Ok(rocket::serde::json::Json(status))
+}
+
+fn schedule_error(err: crate::schedules::ScheduleError) -> ApiError {
+    match err {
+        crate::schedules::ScheduleError::NotFound => ApiError::not_found("No such schedule"),
+        crate::schedules::ScheduleError::InvalidCron =>
+            ApiError::bad_request("invalid_cron", "cron must be a five field cron expression").field("cron"),
+        crate::schedules::ScheduleError::InvalidDestination =>
+            ApiError::bad_request("invalid_destination", "Exactly one of imei or group must be given"),
+        crate::schedules::ScheduleError::UnknownGroup =>
+            ApiError::bad_request("unknown_group", "No such device group").field("group"),
+        crate::schedules::ScheduleError::Invalid(err) => submit_error(err),
+        crate::schedules::ScheduleError::Internal => ApiError::internal(),
+    }
+}
+
+#[rocket::post("/schedules", data = "<data>", format = "application/json")]
+async fn create_schedule(
+    db: &rocket::State<crate::DBPool>, auth: Auth, data: rocket::data::Data<'_>
+) -> Result<rocket::serde::json::Json<crate::types::MTSchedule>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+    let body = read_body(data, 4.kibibytes()).await?;
+    let target = authenticate(&mut db_conn, &auth, &body).await?;
+
+    let request: crate::types::NewMTSchedule = parse_json(&body)?;
+
+    let schedule = crate::schedules::create(&mut db_conn, &target, request).await
+        .map_err(schedule_error)?;
+
+    Ok(rocket::serde::json::Json(crate::schedules::schedule_info(schedule)))
+}
+
+#[rocket::get("/schedules")]
+async fn list_schedules(
+    db: &rocket::State<crate::DBPool>, auth: Auth,
+) -> Result<rocket::serde::json::Json<Vec<crate::types::MTSchedule>>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+    let target = authenticate(&mut db_conn, &auth, &[]).await?;
+
+    let schedules = crate::schedules::list(&mut db_conn, &target).await
+        .map_err(schedule_error)?;
+
+    Ok(rocket::serde::json::Json(schedules.into_iter().map(crate::schedules::schedule_info).collect()))
+}
+
+#[rocket::get("/schedules/<schedule_id>")]
+async fn get_schedule(
+    db: &rocket::State<crate::DBPool>, auth: Auth, schedule_id: uuid::Uuid,
+) -> Result<rocket::serde::json::Json<crate::types::MTSchedule>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+    let target = authenticate(&mut db_conn, &auth, &[]).await?;
+
+    let schedule = crate::schedules::get(&mut db_conn, &target, schedule_id).await
+        .map_err(schedule_error)?;
+
+    Ok(rocket::serde::json::Json(crate::schedules::schedule_info(schedule)))
+}
+
+#[rocket::post("/schedules/<schedule_id>/pause")]
+async fn pause_schedule(
+    db: &rocket::State<crate::DBPool>, auth: Auth, schedule_id: uuid::Uuid,
+) -> Result<rocket::serde::json::Json<crate::types::MTSchedule>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+    let target = authenticate(&mut db_conn, &auth, &[]).await?;
+
+    let schedule = crate::schedules::set_paused(&mut db_conn, &target, schedule_id, true).await
+        .map_err(schedule_error)?;
+
+    Ok(rocket::serde::json::Json(crate::schedules::schedule_info(schedule)))
+}
+
+#[rocket::post("/schedules/<schedule_id>/resume")]
+async fn resume_schedule(
+    db: &rocket::State<crate::DBPool>, auth: Auth, schedule_id: uuid::Uuid,
+) -> Result<rocket::serde::json::Json<crate::types::MTSchedule>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+    let target = authenticate(&mut db_conn, &auth, &[]).await?;
+
+    let schedule = crate::schedules::set_paused(&mut db_conn, &target, schedule_id, false).await
+        .map_err(schedule_error)?;
+
+    Ok(rocket::serde::json::Json(crate::schedules::schedule_info(schedule)))
+}
+
+#[rocket::delete("/schedules/<schedule_id>")]
+async fn delete_schedule(
+    db: &rocket::State<crate::DBPool>, auth: Auth, schedule_id: uuid::Uuid,
+) -> Result<rocket::http::Status, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+    let target = authenticate(&mut db_conn, &auth, &[]).await?;
+
+    crate::schedules::delete(&mut db_conn, &target, schedule_id).await
+        .map_err(schedule_error)?;
+
+    Ok(rocket::http::Status::NoContent)
+}
+
+#[rocket::get("/schedules/<schedule_id>/runs?<cursor>&<limit>")]
+async fn list_schedule_runs(
+    db: &rocket::State<crate::DBPool>, auth: Auth, schedule_id: uuid::Uuid, cursor: Option<String>,
+    limit: Option<u32>,
+) -> Result<rocket::serde::json::Json<crate::types::MTScheduleRunList>, ApiError> {
+    let cursor = cursor.map(|c| crate::mt::Cursor::decode(&c)
+        .ok_or_else(|| invalid_param("cursor"))).transpose()?;
+    let limit = limit.unwrap_or(DEFAULT_PAGE_SIZE).clamp(1, MAX_PAGE_SIZE) as i64;
+
+    let mut db_conn = get_db_conn(db).await?;
+    let target = authenticate(&mut db_conn, &auth, &[]).await?;
+
+    let (runs, next_cursor) = crate::schedules::runs(&mut db_conn, &target, schedule_id, cursor, limit).await
+        .map_err(schedule_error)?;
+
+    Ok(rocket::serde::json::Json(crate::types::MTScheduleRunList {
+        runs: runs.into_iter().map(|r| crate::types::MTScheduleRun {
+            run_time: r.run_time.and_utc(),
+            message: r.message,
+            batch: r.batch,
+            error: r.error,
+        }).collect(),
+        next_cursor: next_cursor.map(|c| c.encode()),
+    }))
+}
+
+fn device_error(err: crate::devices::DeviceError) -> ApiError {
+    match err {
+        crate::devices::DeviceError::NotFound => ApiError::not_found("No such device or group"),
+        crate::devices::DeviceError::InvalidTags => ApiError::bad_request("invalid_tags", format!(
+            "At most {} tags may be set, with keys of 1 to {} bytes and values of up to {} bytes",
+            crate::devices::MAX_TAGS, crate::devices::MAX_TAG_KEY_LENGTH, crate::devices::MAX_TAG_VALUE_LENGTH
+        )),
+        crate::devices::DeviceError::InvalidName => ApiError::bad_request("invalid_name", format!(
+            "Group names must be between 1 and {} bytes", crate::devices::MAX_GROUP_NAME_LENGTH
+        )).field("name"),
+        crate::devices::DeviceError::NameTaken => ApiError::new(
+            rocket::http::Status::Conflict, "name_taken", "A group with this name already exists"
+        ).field("name"),
+        crate::devices::DeviceError::Internal => ApiError::internal(),
+    }
+}
+
+#[rocket::get("/devices")]
+async fn list_devices(
+    db: &rocket::State<crate::DBPool>, auth: Auth,
+) -> Result<rocket::serde::json::Json<Vec<crate::types::Device>>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+    let target = authenticate(&mut db_conn, &auth, &[]).await?;
+
+    let devices = crate::devices::list_devices(&mut db_conn, &target).await
+        .map_err(device_error)?;
+    let devices = crate::devices::device_info(&mut db_conn, devices).await
+        .map_err(device_error)?;
+
+    Ok(rocket::serde::json::Json(devices))
+}
+
+#[rocket::get("/devices/<imei>")]
+async fn get_device(
+    db: &rocket::State<crate::DBPool>, auth: Auth, imei: &str,
+) -> Result<rocket::serde::json::Json<crate::types::Device>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+    let target = authenticate(&mut db_conn, &auth, &[]).await?;
+
+    let device = crate::devices::get_device(&mut db_conn, &target, imei).await
+        .map_err(device_error)?;
+    let mut devices = crate::devices::device_info(&mut db_conn, vec![device]).await
+        .map_err(device_error)?;
+
+    Ok(rocket::serde::json::Json(devices.remove(0)))
+}
+
+#[rocket::put("/devices/<imei>/tags", data = "<data>", format = "application/json")]
+async fn set_device_tags(
+    db: &rocket::State<crate::DBPool>, auth: Auth, imei: &str, data: rocket::data::Data<'_>
+) -> Result<rocket::serde::json::Json<crate::types::Device>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+    let body = read_body(data, 16.kibibytes()).await?;
+    let target = authenticate(&mut db_conn, &auth, &body).await?;
+
+    let tags: std::collections::BTreeMap<String, String> = parse_json(&body)?;
+
+    let device = crate::devices::set_tags(&mut db_conn, &target, imei, tags).await
+        .map_err(device_error)?;
+    let mut devices = crate::devices::device_info(&mut db_conn, vec![device]).await
+        .map_err(device_error)?;
+
+    Ok(rocket::serde::json::Json(devices.remove(0)))
+}
+
+#[rocket::post("/groups", data = "<data>", format = "application/json")]
+async fn create_group(
+    db: &rocket::State<crate::DBPool>, auth: Auth, data: rocket::data::Data<'_>
+) -> Result<rocket::serde::json::Json<crate::types::DeviceGroup>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+    let body = read_body(data, 4.kibibytes()).await?;
+    let target = authenticate(&mut db_conn, &auth, &body).await?;
+
+    let request: crate::types::NewDeviceGroup = parse_json(&body)?;
+
+    let group = crate::devices::create_group(&mut db_conn, &target, request).await
+        .map_err(device_error)?;
+
+    Ok(rocket::serde::json::Json(crate::devices::group_info(&mut db_conn, group).await.map_err(device_error)?))
+}
+
+#[rocket::get("/groups")]
+async fn list_groups(
+    db: &rocket::State<crate::DBPool>, auth: Auth,
+) -> Result<rocket::serde::json::Json<Vec<crate::types::DeviceGroup>>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+    let target = authenticate(&mut db_conn, &auth, &[]).await?;
+
+    let groups = crate::devices::list_groups(&mut db_conn, &target).await
+        .map_err(device_error)?;
+
+    let mut infos = Vec::with_capacity(groups.len());
+    for group in groups {
+        infos.push(crate::devices::group_info(&mut db_conn, group).await.map_err(device_error)?);
+    }
+
+    Ok(rocket::serde::json::Json(infos))
+}
+
+#[rocket::get("/groups/<group_id>")]
+async fn get_group(
+    db: &rocket::State<crate::DBPool>, auth: Auth, group_id: uuid::Uuid,
+) -> Result<rocket::serde::json::Json<crate::types::DeviceGroup>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+    let target = authenticate(&mut db_conn, &auth, &[]).await?;
+
+    let group = crate::devices::get_group(&mut db_conn, &target, group_id).await
+        .map_err(device_error)?;
+
+    Ok(rocket::serde::json::Json(crate::devices::group_info(&mut db_conn, group).await.map_err(device_error)?))
+}
+
+#[rocket::delete("/groups/<group_id>")]
+async fn delete_group(
+    db: &rocket::State<crate::DBPool>, auth: Auth, group_id: uuid::Uuid,
+) -> Result<rocket::http::Status, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+    let target = authenticate(&mut db_conn, &auth, &[]).await?;
+
+    crate::devices::delete_group(&mut db_conn, &target, group_id).await
+        .map_err(device_error)?;
+
+    Ok(rocket::http::Status::NoContent)
+}
+
+#[rocket::put("/groups/<group_id>/devices/<imei>")]
+async fn add_group_device(
+    db: &rocket::State<crate::DBPool>, auth: Auth, group_id: uuid::Uuid, imei: &str,
+) -> Result<rocket::http::Status, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+    let target = authenticate(&mut db_conn, &auth, &[]).await?;
+
+    crate::devices::set_member(&mut db_conn, &target, group_id, imei, true).await
+        .map_err(device_error)?;
+
+    Ok(rocket::http::Status::NoContent)
+}
+
+#[rocket::delete("/groups/<group_id>/devices/<imei>")]
+async fn remove_group_device(
+    db: &rocket::State<crate::DBPool>, auth: Auth, group_id: uuid::Uuid, imei: &str,
+) -> Result<rocket::http::Status, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+    let target = authenticate(&mut db_conn, &auth, &[]).await?;
+
+    crate::devices::set_member(&mut db_conn, &target, group_id, imei, false).await
+        .map_err(device_error)?;
+
+    Ok(rocket::http::Status::NoContent)
+}
+
+fn admin_error(err: crate::admin::AdminError) -> ApiError {
+    match err {
+        crate::admin::AdminError::NotFound => ApiError::not_found("No such target or device"),
+        crate::admin::AdminError::InvalidIMEI =>
+            ApiError::bad_request("invalid_imei", "IMEIs must be 15 digits with a valid check digit").field("imei"),
+        crate::admin::AdminError::InvalidEndpoint =>
+            ApiError::bad_request("invalid_endpoint", "Endpoints must be http or https URLs").field("endpoint"),
+        crate::admin::AdminError::InvalidMQTTBroker =>
+            ApiError::bad_request("invalid_mqtt_broker", "Invalid MQTT broker URL").field("mqtt_broker"),
+        crate::admin::AdminError::InvalidEmailAddress =>
+            ApiError::bad_request("invalid_email_address", "Invalid email address").field("email_address"),
+        crate::admin::AdminError::InvalidEmailToken => ApiError::bad_request(
+            "invalid_email_token", "Email tokens must be at least 16 letters, digits, - or _"
+        ).field("email_token"),
+        crate::admin::AdminError::InvalidSignatureVersion => ApiError::bad_request(
+            "invalid_signature_version", "Signature versions must be 1 or 2"
+        ).field("mt_signature_version"),
+        crate::admin::AdminError::InvalidWebhookVersion => ApiError::bad_request(
+            "invalid_webhook_version", "Webhook versions must be 1 or 2"
+        ).field("webhook_version"),
+        crate::admin::AdminError::InvalidMetadata =>
+            ApiError::bad_request("invalid_metadata", "Metadata must be a JSON object").field("metadata"),
+        crate::admin::AdminError::UnknownTarget =>
+            ApiError::bad_request("unknown_target", "No such target").field("target"),
+        crate::admin::AdminError::UnknownProvider =>
+            ApiError::bad_request("unknown_provider", "No such MT provider").field("mt_provider"),
+        crate::admin::AdminError::IMEITaken => ApiError::new(
+            rocket::http::Status::Conflict, "imei_taken", "A device with this IMEI already exists"
+        ).field("imei"),
+        crate::admin::AdminError::EmailAddressTaken => ApiError::new(
+            rocket::http::Status::Conflict, "email_address_taken", "Another target already has this email address"
+        ).field("email_address"),
+        crate::admin::AdminError::InUse => ApiError::new(
+            rocket::http::Status::Conflict, "in_use", "Still referenced by devices or messages"
+        ),
+        crate::admin::AdminError::Internal => ApiError::internal(),
+    }
+}
+
+#[rocket::post("/admin/targets", data = "<data>", format = "application/json")]
+async fn admin_create_target(
+    db: &rocket::State<crate::DBPool>, _auth: AdminAuth, data: Result<rocket::serde::json::Json<crate::types::TargetConfig>, rocket::serde::json::Error<'_>>,
+) -> Result<rocket::serde::json::Json<crate::types::NewTarget>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+
+    let target = crate::admin::create_target(&mut db_conn, json_body(data)?).await
+        .map_err(admin_error)?;
+
+    Ok(rocket::serde::json::Json(target))
+}
+
+#[rocket::get("/admin/targets")]
+async fn admin_list_targets(
+    db: &rocket::State<crate::DBPool>, _auth: AdminAuth,
+) -> Result<rocket::serde::json::Json<Vec<crate::types::Target>>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+
+    let targets = crate::admin::list_targets(&mut db_conn).await
+        .map_err(admin_error)?;
+
+    Ok(rocket::serde::json::Json(targets))
+}
+
+#[rocket::get("/admin/targets/<target_id>")]
+async fn admin_get_target(
+    db: &rocket::State<crate::DBPool>, _auth: AdminAuth, target_id: uuid::Uuid,
+) -> Result<rocket::serde::json::Json<crate::types::Target>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+
+    let target = crate::admin::get_target(&mut db_conn, target_id).await
+        .map_err(admin_error)?;
+
+    Ok(rocket::serde::json::Json(target))
+}
+
+#[rocket::put("/admin/targets/<target_id>", data = "<data>", format = "application/json")]
+async fn admin_update_target(
+    db: &rocket::State<crate::DBPool>, _auth: AdminAuth, target_id: uuid::Uuid,
+    data: Result<rocket::serde::json::Json<crate::types::TargetConfig>, rocket::serde::json::Error<'_>>,
+) -> Result<rocket::serde::json::Json<crate::types::Target>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+
+    let target = crate::admin::update_target(&mut db_conn, target_id, json_body(data)?).await
+        .map_err(admin_error)?;
+
+    Ok(rocket::serde::json::Json(target))
+}
+
+#[rocket::delete("/admin/targets/<target_id>")]
+async fn admin_delete_target(
+    db: &rocket::State<crate::DBPool>, _auth: AdminAuth, target_id: uuid::Uuid,
+) -> Result<rocket::http::Status, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+
+    crate::admin::delete_target(&mut db_conn, target_id).await
+        .map_err(admin_error)?;
+
+    Ok(rocket::http::Status::NoContent)
+}
+
+#[rocket::post("/admin/devices", data = "<data>", format = "application/json")]
+async fn admin_create_device(
+    db: &rocket::State<crate::DBPool>, _auth: AdminAuth, data: Result<rocket::serde::json::Json<crate::types::DeviceConfig>, rocket::serde::json::Error<'_>>,
+) -> Result<rocket::serde::json::Json<crate::types::AdminDevice>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+
+    let device = crate::admin::create_device(&mut db_conn, json_body(data)?).await
+        .map_err(admin_error)?;
+
+    Ok(rocket::serde::json::Json(device))
+}
+
+#[rocket::get("/admin/devices?<target>")]
+async fn admin_list_devices(
+    db: &rocket::State<crate::DBPool>, _auth: AdminAuth, target: Option<uuid::Uuid>,
+) -> Result<rocket::serde::json::Json<Vec<crate::types::AdminDevice>>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+
+    let devices = crate::admin::list_devices(&mut db_conn, target).await
+        .map_err(admin_error)?;
+
+    Ok(rocket::serde::json::Json(devices))
+}
+
+#[rocket::get("/admin/devices/<device_id>")]
+async fn admin_get_device(
+    db: &rocket::State<crate::DBPool>, _auth: AdminAuth, device_id: uuid::Uuid,
+) -> Result<rocket::serde::json::Json<crate::types::AdminDevice>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+
+    let device = crate::admin::get_device(&mut db_conn, device_id).await
+        .map_err(admin_error)?;
+
+    Ok(rocket::serde::json::Json(device))
+}
+
+#[rocket::put("/admin/devices/<device_id>", data = "<data>", format = "application/json")]
+async fn admin_update_device(
+    db: &rocket::State<crate::DBPool>, _auth: AdminAuth, device_id: uuid::Uuid,
+    data: Result<rocket::serde::json::Json<crate::types::DeviceConfig>, rocket::serde::json::Error<'_>>,
+) -> Result<rocket::serde::json::Json<crate::types::AdminDevice>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+
+    let device = crate::admin::update_device(&mut db_conn, device_id, json_body(data)?).await
+        .map_err(admin_error)?;
+
+    Ok(rocket::serde::json::Json(device))
+}
+
+#[rocket::delete("/admin/devices/<device_id>")]
+async fn admin_delete_device(
+    db: &rocket::State<crate::DBPool>, _auth: AdminAuth, device_id: uuid::Uuid,
+) -> Result<rocket::http::Status, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+
+    crate::admin::delete_device(&mut db_conn, device_id).await
+        .map_err(admin_error)?;
+
+    Ok(rocket::http::Status::NoContent)
+}
+
+#[rocket::get("/keys")]
+async fn list_keys(
+    db: &rocket::State<crate::DBPool>, auth: Auth,
+) -> Result<rocket::serde::json::Json<Vec<crate::types::TargetKey>>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+    let target = authenticate(&mut db_conn, &auth, &[]).await?;
+
+    let keys = crate::keys::active_keys(target.id, &mut db_conn).await
+        .map_err(|err| {
+            error!("Failed to get target keys: {}", err);
+            ApiError::internal()
+        })?;
+
+    Ok(rocket::serde::json::Json(keys.into_iter().map(|k| crate::types::TargetKey {
+        id: k.id,
+        created: k.created.and_utc(),
+        expires: k.expires.map(|e| e.and_utc()),
+    }).collect()))
+}
+
+#[rocket::post("/keys/rotate", data = "<data>", format = "application/json")]
+async fn rotate_keys(
+    db: &rocket::State<crate::DBPool>, auth: Auth, data: rocket::data::Data<'_>
+) -> Result<rocket::serde::json::Json<crate::types::NewTargetKey>, ApiError> {
+    // The response contains a secret, so a replayable signature can't be accepted
+    if !matches!(auth.version, SignatureVersion::V2 { .. }) {
+        return Err(ApiError::unauthorized(
+            "signature_version_not_allowed", "Managing keys requires version 2 signatures"
+        ).field("Kosmos-Signature-Version"));
+    }
+
+    let mut db_conn = get_db_conn(db).await?;
+    let body = read_body(data, 4.kibibytes()).await?;
+    let target = authenticate(&mut db_conn, &auth, &body).await?;
+
+    let request: crate::types::RotateKeys = parse_json(&body)?;
+    let grace_period = request.grace_period.map_or(crate::keys::DEFAULT_GRACE_PERIOD, |g| g as i64);
+
+    let key = crate::keys::rotate(target.id, grace_period, &mut db_conn).await
+        .map_err(|err| {
+            error!("Failed to rotate target keys: {}", err);
+            ApiError::internal()
+        })?;
+
+    crate::audit::record(
+        &mut db_conn, Some(target.id), "key_rotated",
+        format!("Key {} created, previous keys expire in {} seconds", key.id, grace_period)
+    ).await;
+
+    Ok(rocket::serde::json::Json(crate::types::NewTargetKey {
+        id: key.id,
+        key: BASE64_STANDARD.encode(&key.key),
+        created: key.created.and_utc(),
+    }))
+}
+
+#[rocket::delete("/keys/<key_id>")]
+async fn revoke_key(
+    db: &rocket::State<crate::DBPool>, auth: Auth, key_id: uuid::Uuid,
+) -> Result<rocket::http::Status, ApiError> {
+    if !matches!(auth.version, SignatureVersion::V2 { .. }) {
+        return Err(ApiError::unauthorized(
+            "signature_version_not_allowed", "Managing keys requires version 2 signatures"
+        ).field("Kosmos-Signature-Version"));
+    }
+
+    let mut db_conn = get_db_conn(db).await?;
+    let target = authenticate(&mut db_conn, &auth, &[]).await?;
+
+    let keys = crate::keys::active_keys(target.id, &mut db_conn).await
+        .map_err(|err| {
+            error!("Failed to get target keys: {}", err);
+            ApiError::internal()
+        })?;
+    if !keys.iter().any(|k| k.id == key_id) {
+        return Err(ApiError::not_found("No such active key"));
+    }
+    if keys.len() == 1 {
+        // Revoking the last key would lock the target out
+        return Err(ApiError::new(
+            rocket::http::Status::Conflict, "last_key", "The target's only active key can't be revoked"
+        ));
+    }
+
+    diesel::update(crate::schema::target_keys::dsl::target_keys)
+        .filter(crate::schema::target_keys::dsl::id.eq(key_id))
+        .set(crate::schema::target_keys::dsl::expires.eq(chrono::Utc::now().naive_utc()))
+        .execute(&mut db_conn).await
+        .map_err(|err| {
+            error!("Failed to revoke key: {}", err);
+            ApiError::internal()
+        })?;
+
+    crate::audit::record(&mut db_conn, Some(target.id), "key_revoked", format!("Key {} revoked", key_id)).await;
+
+    Ok(rocket::http::Status::NoContent)
+}
+
+#[rocket::get("/.well-known/jwks.json")]
+async fn jwks(
+    db: &rocket::State<crate::DBPool>,
+) -> Result<rocket::serde::json::Json<crate::types::JWKSet>, ApiError> {
+    let mut db_conn = get_db_conn(db).await?;
+
+    let keys = crate::jwks::published_keys(&mut db_conn).await
+        .map_err(|err| {
+            error!("Failed to get signing keys: {}", err);
+            ApiError::internal()
+        })?;
+
+    Ok(rocket::serde::json::Json(crate::types::JWKSet {
+        keys: keys.iter().map(crate::jwks::to_jwk).collect()
+    }))
+}
+
+#[rocket::get("/openapi.json")]
+fn openapi() -> rocket::serde::json::Json<serde_json::Value> {
+    rocket::serde::json::Json(crate::openapi::document())
+}
+
+#[rocket::get("/schemas/<name>")]
+fn json_schema(name: &str) -> Option<rocket::serde::json::Json<schemars::schema::RootSchema>> {
+    let name = name.strip_suffix(".json")?;
+    crate::openapi::json_schema(name).map(rocket::serde::json::Json)
+}
+
+#[rocket::post("/rockblock/mo", data = "<data>")]
+async fn rockblock_mo(
+    db: &rocket::State<crate::DBPool>, celery_app: &rocket::State<std::sync::Arc<celery::Celery>>,
+    config: &rocket::State<RockBLOCKConfig>, data: rocket::form::Form<crate::rockblock::MOMessage>
+) -> rocket::http::Status {
+    let public_key = match &config.public_key {
+        Some(k) => k,
+        None => return rocket::http::Status::NotFound
     };
 
-    if let Err(err) = diesel::insert_into(crate::schema::mt_messages::dsl::mt_messages)
-        .values(&mt_message)
-        .execute(&mut db_conn).await {
-        error!("Failed to insert message: {}", err);
-        return Err(rocket::http::Status::InternalServerError);
+    if let Err(err) = data.verify(public_key) {
+        warn!("Rejecting RockBLOCK message: {}", err);
+        return rocket::http::Status::Unauthorized;
     }
 
-    if let Err(err) = celery_app.send_task(crate::worker::deliver_mt::new(mt_message.id)).await {
-        error!("Failed to send task: {}", err);
-        return Err(rocket::http::Status::InternalServerError);
+    let message = match data.into_inner().into_model() {
+        Ok(m) => m,
+        Err(err) => {
+            warn!("Rejecting RockBLOCK message: {}", err);
+            return rocket::http::Status::BadRequest;
+        }
+    };
+
+    if crate::mo::save_message(message, db, celery_app).await {
+        rocket::http::Status::Ok
+    } else {
+        rocket::http::Status::InternalServerError
     }
+}
 
-    Ok(mt_message.id.to_string())
+/// Every route the API server mounts, which are all described by the OpenAPI document
+pub(crate) fn routes() -> Vec<rocket::Route> {
+    rocket::routes![
+        submit_mt,
+        submit_mt_get,
+        get_mt,
+        cancel_mt,
+        list_mt,
+        get_mo,
+        get_mo_payload,
+        list_mo,
+        create_batch,
+        get_batch,
+        create_schedule,
+        list_schedules,
+        get_schedule,
+        pause_schedule,
+        resume_schedule,
+        delete_schedule,
+        list_schedule_runs,
+        list_devices,
+        get_device,
+        set_device_tags,
+        create_group,
+        list_groups,
+        get_group,
+        delete_group,
+        add_group_device,
+        remove_group_device,
+        rockblock_mo,
+        list_keys,
+        rotate_keys,
+        revoke_key,
+        jwks,
+        openapi,
+        json_schema,
+        admin_create_target,
+        admin_list_targets,
+        admin_get_target,
+        admin_update_target,
+        admin_delete_target,
+        admin_create_device,
+        admin_list_devices,
+        admin_get_device,
+        admin_update_device,
+        admin_delete_device,
+    ]
 }
 
-pub async fn run(listen_addr: std::net::SocketAddr, amqp_addr: String, db_pool: crate::DBPool) {
+pub async fn run(
+    listen_addr: std::net::SocketAddr, amqp_addr: String, db_pool: crate::DBPool,
+    rockblock_public_key: Option<std::path::PathBuf>, admin_token: Option<String>,
+) {
     let figment = rocket::Config::figment()
         .merge(("address", listen_addr.ip()))
         .merge(("port", listen_addr.port()))
         .merge(("ident", format!("Kosmos {}", env!("CARGO_PKG_VERSION"))));
 
+    let rockblock_config = RockBLOCKConfig {
+        public_key: match rockblock_public_key {
+            Some(path) => match crate::rockblock::load_public_key(&path) {
+                Ok(k) => Some(k),
+                Err(err) => {
+                    error!("Failed to load RockBLOCK public key: {}", err);
+                    return;
+                }
+            },
+            None => None
+        }
+    };
+
     let celery_app = match celery::app!(
         broker = AMQP { amqp_addr },
         tasks = [],
@@ -146,11 +1368,13 @@ pub async fn run(listen_addr: std::net::SocketAddr, amqp_addr: String, db_pool:
     };
 
     rocket::custom(figment)
-        .mount("/", rocket::routes![
-            submit_mt,
-            submit_mt_get,
-        ])
+        .mount("/", routes())
+        .register("/", rocket::catchers![default_catcher])
         .manage(celery_app)
         .manage(db_pool)
+        .manage(rockblock_config)
+        .manage(AdminConfig {
+            token: admin_token,
+        })
         .launch().await.unwrap();
 }
\ No newline at end of file
@@ -0,0 +1,99 @@
+use base64::prelude::*;
+use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl};
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use diesel_async::scoped_futures::ScopedFutureExt;
+
+/// How long a key stays published after a newer key starts being used to sign, so that
+/// receivers can still verify retried webhooks
+pub const RETIRED_KEY_GRACE_PERIOD: i64 = 7 * 24 * 60 * 60;
+
+/// How long a new key is published before it starts being used to sign, so that receivers
+/// caching the key set will pick it up first
+pub const DEFAULT_PUBLISH_DELAY: i64 = 24 * 60 * 60;
+
+fn generate_key(now: chrono::NaiveDateTime, activates: chrono::NaiveDateTime) -> Result<crate::models::SigningKey, String> {
+    let pkey = openssl::pkey::PKey::generate_ed25519()
+        .map_err(|e| format!("failed to generate key: {}", e))?;
+
+    Ok(crate::models::SigningKey {
+        id: uuid::Uuid::new_v4(),
+        private_key: pkey.raw_private_key().map_err(|e| format!("failed to encode key: {}", e))?,
+        public_key: pkey.raw_public_key().map_err(|e| format!("failed to encode key: {}", e))?,
+        created: now,
+        activates,
+        expires: None,
+    })
+}
+
+/// Returns the key that should be used to sign webhooks now
+pub(crate) async fn signing_key(db_conn: &mut crate::DBConn) -> diesel::result::QueryResult<crate::models::SigningKey> {
+    crate::schema::signing_keys::dsl::signing_keys
+        .filter(crate::schema::signing_keys::dsl::activates.le(chrono::Utc::now().naive_utc()))
+        .order_by(crate::schema::signing_keys::dsl::activates.desc())
+        .first::<crate::models::SigningKey>(db_conn).await
+}
+
+/// Returns all keys that receivers should accept signatures from, including those not yet in use
+pub(crate) async fn published_keys(db_conn: &mut crate::DBConn) -> diesel::result::QueryResult<Vec<crate::models::SigningKey>> {
+    crate::schema::signing_keys::dsl::signing_keys
+        .filter(
+            crate::schema::signing_keys::dsl::expires.is_null()
+                .or(crate::schema::signing_keys::dsl::expires.gt(chrono::Utc::now().naive_utc()))
+        )
+        .order_by(crate::schema::signing_keys::dsl::activates.desc())
+        .load::<crate::models::SigningKey>(db_conn).await
+}
+
+/// Creates a signing key to be used immediately if none exist. Only one key may be without an
+/// expiry, so workers starting at the same time won't both create one.
+pub(crate) async fn ensure_key(db_conn: &mut crate::DBConn) -> Result<(), String> {
+    let now = chrono::Utc::now().naive_utc();
+    let key = generate_key(now, now)?;
+    let inserted = diesel::insert_into(crate::schema::signing_keys::dsl::signing_keys)
+        .values(&key)
+        .on_conflict_do_nothing()
+        .execute(db_conn).await
+        .map_err(|e| format!("failed to save signing key: {}", e))?;
+
+    if inserted != 0 {
+        info!("Created webhook signing key {}", key.id);
+    }
+    Ok(())
+}
+
+/// Creates a new signing key to be used after the publish delay, and retires all current keys
+/// once the grace period after that has passed
+pub async fn rotate(db_conn: &mut crate::DBConn, publish_delay: i64) -> Result<crate::models::SigningKey, String> {
+    let now = chrono::Utc::now().naive_utc();
+    let activates = now + chrono::Duration::seconds(publish_delay);
+    let expires = activates + chrono::Duration::seconds(RETIRED_KEY_GRACE_PERIOD);
+    let key = generate_key(now, activates)?;
+
+    db_conn.transaction::<_, diesel::result::Error, _>(|conn| async move {
+        diesel::update(crate::schema::signing_keys::dsl::signing_keys)
+            .filter(
+                crate::schema::signing_keys::dsl::expires.is_null()
+                    .or(crate::schema::signing_keys::dsl::expires.gt(expires))
+            )
+            .set(crate::schema::signing_keys::dsl::expires.eq(expires))
+            .execute(conn).await?;
+
+        diesel::insert_into(crate::schema::signing_keys::dsl::signing_keys)
+            .values(&key)
+            .execute(conn).await?;
+
+        Ok(key)
+    }.scope_boxed()).await
+        .map_err(|e| format!("failed to save signing key: {}", e))
+}
+
+pub(crate) fn to_jwk(key: &crate::models::SigningKey) -> crate::types::JWK {
+    crate::types::JWK {
+        kty: "OKP".to_string(),
+        crv: "Ed25519".to_string(),
+        x: BASE64_URL_SAFE_NO_PAD.encode(&key.public_key),
+        kid: key.id,
+        key_use: "sig".to_string(),
+        alg: "EdDSA".to_string(),
+    }
+}
\ No newline at end of file
@@ -0,0 +1,110 @@
+use base64::prelude::*;
+use diesel::{BoolExpressionMethods, ExpressionMethods, QueryDsl};
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use diesel_async::scoped_futures::ScopedFutureExt;
+
+/// How long superseded keys remain valid for after a rotation, unless otherwise requested
+pub const DEFAULT_GRACE_PERIOD: i64 = 7 * 24 * 60 * 60;
+
+pub fn generate_key() -> Vec<u8> {
+    rand::random::<[u8; 32]>().to_vec()
+}
+
+/// Returns all of a target's keys that haven't expired, newest first
+pub(crate) async fn active_keys(target_id: uuid::Uuid, db_conn: &mut crate::DBConn) -> diesel::result::QueryResult<Vec<crate::models::TargetKey>> {
+    crate::schema::target_keys::dsl::target_keys
+        .filter(crate::schema::target_keys::dsl::target.eq(target_id))
+        .filter(
+            crate::schema::target_keys::dsl::expires.is_null()
+                .or(crate::schema::target_keys::dsl::expires.gt(chrono::Utc::now().naive_utc()))
+        )
+        .order_by(crate::schema::target_keys::dsl::created.desc())
+        .load::<crate::models::TargetKey>(db_conn).await
+}
+
+/// Returns the key that should be used to sign messages to a target
+pub(crate) async fn signing_key(target_id: uuid::Uuid, db_conn: &mut crate::DBConn) -> diesel::result::QueryResult<crate::models::TargetKey> {
+    active_keys(target_id, db_conn).await?
+        .into_iter().next()
+        .ok_or(diesel::result::Error::NotFound)
+}
+
+/// Adds a new key to a target, leaving its other keys valid
+pub(crate) async fn add(target_id: uuid::Uuid, db_conn: &mut crate::DBConn) -> diesel::result::QueryResult<crate::models::TargetKey> {
+    let new_key = crate::models::TargetKey {
+        id: uuid::Uuid::new_v4(),
+        target: target_id,
+        key: generate_key(),
+        created: chrono::Utc::now().naive_utc(),
+        expires: None,
+    };
+
+    diesel::insert_into(crate::schema::target_keys::dsl::target_keys)
+        .values(&new_key)
+        .execute(db_conn).await?;
+
+    Ok(new_key)
+}
+
+/// Adds a new key to a target, and sets all of its other keys to expire after the grace period
+pub(crate) async fn rotate(target_id: uuid::Uuid, grace_period: i64, db_conn: &mut crate::DBConn) -> diesel::result::QueryResult<crate::models::TargetKey> {
+    let now = chrono::Utc::now().naive_utc();
+    let expires = now + chrono::Duration::seconds(grace_period);
+
+    let new_key = crate::models::TargetKey {
+        id: uuid::Uuid::new_v4(),
+        target: target_id,
+        key: generate_key(),
+        created: now,
+        expires: None,
+    };
+
+    db_conn.transaction::<_, diesel::result::Error, _>(|conn| async move {
+        diesel::update(crate::schema::target_keys::dsl::target_keys)
+            .filter(crate::schema::target_keys::dsl::target.eq(target_id))
+            .filter(
+                crate::schema::target_keys::dsl::expires.is_null()
+                    .or(crate::schema::target_keys::dsl::expires.gt(expires))
+            )
+            .set(crate::schema::target_keys::dsl::expires.eq(expires))
+            .execute(conn).await?;
+
+        diesel::insert_into(crate::schema::target_keys::dsl::target_keys)
+            .values(&new_key)
+            .execute(conn).await?;
+
+        Ok(new_key)
+    }.scope_boxed()).await
+}
+
+/// The key used to sign webhooks to a particular target
+pub(crate) enum WebhookKey {
+    Hmac(crate::models::TargetKey),
+    Ed25519(crate::models::SigningKey),
+}
+
+impl WebhookKey {
+    pub(crate) async fn for_target(target: &crate::models::Target, db_conn: &mut crate::DBConn) -> diesel::result::QueryResult<Self> {
+        Ok(match target.webhook_signing {
+            crate::models::WebhookSigning::Hmac => Self::Hmac(signing_key(target.id, db_conn).await?),
+            crate::models::WebhookSigning::Ed25519 => Self::Ed25519(crate::jwks::signing_key(db_conn).await?),
+        })
+    }
+
+    pub(crate) fn signature_headers(
+        &self, body: &[u8], signed_content: &[u8]
+    ) -> Result<Vec<(&'static str, String)>, openssl::error::ErrorStack> {
+        let timestamp = chrono::Utc::now().timestamp();
+        Ok(match self {
+            Self::Hmac(key) => vec![
+                ("Kosmos-MAC", BASE64_STANDARD.encode(crate::signing::request_mac_v1(&key.key, body))),
+                ("Kosmos-Signature", crate::signing::webhook_signature_header(&key.id, &key.key, timestamp, signed_content)),
+            ],
+            Self::Ed25519(key) => vec![
+                ("Kosmos-Signature", crate::signing::webhook_ed25519_signature_header(
+                    &key.id, &key.private_key, timestamp, signed_content
+                )?),
+            ]
+        })
+    }
+}
\ No newline at end of file
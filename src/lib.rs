@@ -9,8 +9,26 @@ mod schema;
 mod models;
 pub mod mo;
 pub mod worker;
-mod types;
+pub mod types;
 pub mod http;
+mod mt;
+pub mod mqtt;
+pub mod email;
+mod rockblock;
+mod provider;
+mod scheduler;
+mod schedules;
+mod batches;
+mod devices;
+mod admin;
+pub mod cli;
+mod audit;
+mod events;
+pub mod signing;
+pub mod client;
+mod keys;
+pub mod jwks;
+pub mod openapi;
 
 pub const IRIDUM_MT_ADDR: &'static str = "directip.sbd.iridium.com:10800";
 pub const IRIDIUM_SOURCE_IP: std::net::IpAddr = std::net::IpAddr::V4(std::net::Ipv4Addr::new(12, 47, 179, 11));
@@ -41,6 +59,3 @@ pub fn run_migrations(db_url: &str) -> bool {
 
     true
 }
-
-
-
@@ -1,3 +1,4 @@
+use diesel::{BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl};
 use diesel_async::RunQueryDsl;
 use rocket::form::validate::Contains;
 
@@ -120,8 +121,21 @@ async fn _process_socket(
         data: message.payload,
         processing_status: crate::models::ProcessingStatus::Received,
         received: chrono::Utc::now().naive_utc(),
+        source: crate::models::MOSource::DirectIp,
+        requeued: None,
+        target: None,
     };
 
+    save_message(message_to_save, &db_pool, &celery_app).await
+}
+
+/// Stores a received MO message against the device's current owner, and queues it for processing.
+/// Repeats of a message already received are accepted, but not processed again.
+pub(crate) async fn save_message(
+    mut message_to_save: crate::models::MOMessage,
+    db_pool: &crate::DBPool,
+    celery_app: &celery::Celery,
+) -> bool {
     let mut db_conn = match db_pool.get().await {
         Ok(c) => c,
         Err(err) => {
@@ -129,11 +143,30 @@ async fn _process_socket(
             return false;
         }
     };
-    if let Err(err) = diesel::insert_into(crate::schema::mo_messages::dsl::mo_messages)
+    message_to_save.target = match crate::schema::devices::dsl::devices
+        .filter(crate::schema::devices::dsl::imei.eq(&message_to_save.imei))
+        .select(crate::schema::devices::dsl::target)
+        .get_result::<uuid::Uuid>(&mut db_conn).await
+        .optional() {
+        Ok(t) => t,
+        Err(err) => {
+            error!("Failed to get device owner: {}", err);
+            return false;
+        }
+    };
+    match diesel::insert_into(crate::schema::mo_messages::dsl::mo_messages)
         .values(&message_to_save)
+        .on_conflict_do_nothing()
         .execute(&mut db_conn).await {
-        error!("Failed to insert message: {}", err);
-        return false;
+        Ok(0) => {
+            info!("Ignoring repeated MO message {} from {}", message_to_save.mo_msn as u16, message_to_save.imei);
+            return true;
+        }
+        Ok(_) => {}
+        Err(err) => {
+            error!("Failed to insert message: {}", err);
+            return false;
+        }
     }
 
     if let Err(err) = celery_app.send_task(crate::worker::process_message::new(message_to_save.id)).await {
@@ -142,4 +175,120 @@ async fn _process_socket(
     }
 
     true
-}
\ No newline at end of file
+}
+
+pub(crate) fn message_info(message: crate::models::MOMessage) -> crate::types::MOMessageInfo {
+    crate::types::MOMessageInfo {
+        id: message.id,
+        header: crate::types::MOHeader {
+            imei: message.imei,
+            cdr_reference: message.cdr_reference as u32,
+            session_status: message.session_status.into(),
+            mo_msn: message.mo_msn as u16,
+            mt_msn: message.mt_msn as u16,
+            time_of_session: message.time_of_session.and_utc(),
+        },
+        location_information: match (message.longitude, message.latitude, message.cep_radius) {
+            (Some(longitude), Some(latitude), Some(cep_radius)) => Some(crate::types::MOLocationInformation {
+                longitude,
+                latitude,
+                cep_radius: cep_radius as u32
+            }),
+            _ => None
+        },
+        payload_size: message.data.map(|d| d.len() as u32),
+        processing_status: message.processing_status.into(),
+        source: message.source.into(),
+        received: message.received.and_utc(),
+    }
+}
+
+/// Gets an MO message, if it was received while its device belonged to the target
+pub(crate) async fn get_message(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target, message_id: uuid::Uuid,
+) -> diesel::result::QueryResult<Option<crate::models::MOMessage>> {
+    crate::schema::mo_messages::dsl::mo_messages
+        .filter(crate::schema::mo_messages::dsl::id.eq(message_id))
+        .filter(crate::schema::mo_messages::dsl::target.eq(&target.id))
+        .get_result::<crate::models::MOMessage>(db_conn).await
+        .optional()
+}
+
+pub struct MessageFilter {
+    pub imei: Option<String>,
+    pub group: Option<uuid::Uuid>,
+    pub session_status: Option<crate::models::SessionStatus>,
+    pub processing_status: Option<crate::models::ProcessingStatus>,
+    pub since: Option<chrono::NaiveDateTime>,
+    pub until: Option<chrono::NaiveDateTime>,
+    pub cursor: Option<crate::mt::Cursor>,
+    pub limit: i64,
+}
+
+/// Lists the MO messages received while their devices belonged to a target, newest first, with the
+/// next page's cursor; `None` if the group filtered on isn't the target's
+pub(crate) async fn list_messages(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target, filter: MessageFilter,
+) -> diesel::result::QueryResult<Option<(Vec<crate::models::MOMessage>, Option<crate::mt::Cursor>)>> {
+    let mut query = crate::schema::mo_messages::dsl::mo_messages
+        .filter(crate::schema::mo_messages::dsl::target.eq(&target.id))
+        .into_boxed();
+
+    if let Some(imei) = filter.imei {
+        query = query.filter(crate::schema::mo_messages::dsl::imei.eq(imei));
+    }
+    if let Some(group) = filter.group {
+        let owns_group = diesel::select(diesel::dsl::exists(
+            crate::schema::device_groups::dsl::device_groups
+                .filter(crate::schema::device_groups::dsl::id.eq(group))
+                .filter(crate::schema::device_groups::dsl::target.eq(&target.id))
+        )).get_result::<bool>(db_conn).await?;
+        if !owns_group {
+            return Ok(None);
+        }
+        query = query.filter(crate::schema::mo_messages::dsl::imei.eq_any(
+            crate::schema::devices::dsl::devices
+                .inner_join(crate::schema::device_group_members::table)
+                .filter(crate::schema::device_group_members::dsl::device_group.eq(group))
+                .select(crate::schema::devices::dsl::imei)
+        ));
+    }
+    if let Some(status) = filter.session_status {
+        query = query.filter(crate::schema::mo_messages::dsl::session_status.eq(status));
+    }
+    if let Some(status) = filter.processing_status {
+        query = query.filter(crate::schema::mo_messages::dsl::processing_status.eq(status));
+    }
+    if let Some(since) = filter.since {
+        query = query.filter(crate::schema::mo_messages::dsl::received.ge(since));
+    }
+    if let Some(until) = filter.until {
+        query = query.filter(crate::schema::mo_messages::dsl::received.lt(until));
+    }
+    if let Some(cursor) = filter.cursor {
+        query = query.filter(
+            crate::schema::mo_messages::dsl::received.lt(cursor.time).or(
+                crate::schema::mo_messages::dsl::received.eq(cursor.time)
+                    .and(crate::schema::mo_messages::dsl::id.lt(cursor.id))
+            )
+        );
+    }
+
+    // Fetch one extra row to find out if there's another page
+    let mut messages = query
+        .order_by((crate::schema::mo_messages::dsl::received.desc(), crate::schema::mo_messages::dsl::id.desc()))
+        .limit(filter.limit + 1)
+        .load::<crate::models::MOMessage>(db_conn).await?;
+
+    let next_cursor = if messages.len() as i64 > filter.limit {
+        messages.truncate(filter.limit as usize);
+        messages.last().map(|m| crate::mt::Cursor {
+            time: m.received,
+            id: m.id,
+        })
+    } else {
+        None
+    };
+
+    Ok(Some((messages, next_cursor)))
+}
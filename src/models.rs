@@ -34,6 +34,25 @@ pub enum MessageStatus {
     PayloadSizeExceeded,
     MessageQueueFull,
     ResourcesUnavailable,
+    Cancelled,
+    Expired,
+    Rejected,
+}
+
+/// The name stored in the database, which doesn't change between versions
+impl std::fmt::Display for MessageStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Self::Delivered => "delivered",
+            Self::InvalidImei => "invalid_imei",
+            Self::PayloadSizeExceeded => "payload_size_exceeded",
+            Self::MessageQueueFull => "message_queue_full",
+            Self::ResourcesUnavailable => "resources_unavailable",
+            Self::Cancelled => "cancelled",
+            Self::Expired => "expired",
+            Self::Rejected => "rejected",
+        })
+    }
 }
 
 #[derive(Debug, diesel_derive_enum::DbEnum, PartialEq)]
@@ -42,6 +61,15 @@ pub enum ProcessingStatus {
     Received,
     Done,
     Failed,
+    Scheduled,
+    Sending,
+}
+
+#[derive(Debug, diesel_derive_enum::DbEnum)]
+#[ExistingTypePath = "crate::schema::sql_types::MoSource"]
+pub enum MOSource {
+    DirectIp,
+    RockBlock,
 }
 
 #[derive(diesel::Queryable, diesel::Selectable, diesel::Insertable)]
@@ -61,6 +89,11 @@ pub struct MOMessage {
     pub data: Option<Vec<u8>>,
     pub processing_status: ProcessingStatus,
     pub received: chrono::NaiveDateTime,
+    pub source: MOSource,
+    /// When the message was last queued to be processed again, restarting its retry window
+    pub requeued: Option<chrono::NaiveDateTime>,
+    /// The target that owned the device when the message was received
+    pub target: Option<uuid::Uuid>,
 }
 
 #[derive(diesel::Queryable, diesel::Selectable, diesel::Insertable)]
@@ -75,6 +108,31 @@ pub struct MTMessage {
     pub processing_status: ProcessingStatus,
     pub received: chrono::NaiveDateTime,
     pub target: uuid::Uuid,
+    pub idempotency_key: Option<String>,
+    pub client_reference: Option<String>,
+    pub metadata: Option<serde_json::Value>,
+    pub attempts: i32,
+    pub last_attempt: Option<chrono::NaiveDateTime>,
+    pub completed: Option<chrono::NaiveDateTime>,
+    pub not_before: Option<chrono::NaiveDateTime>,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub batch: Option<uuid::Uuid>,
+    /// When the message was last queued to be sent again, restarting its retry window
+    pub requeued: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Debug, diesel_derive_enum::DbEnum, Clone, Copy)]
+#[ExistingTypePath = "crate::schema::sql_types::WebhookSigning"]
+pub enum WebhookSigning {
+    Hmac,
+    Ed25519,
+}
+
+#[derive(Debug, diesel_derive_enum::DbEnum, Clone, Copy)]
+#[ExistingTypePath = "crate::schema::sql_types::CloudEventsMode"]
+pub enum CloudEventsMode {
+    Structured,
+    Binary,
 }
 
 #[derive(diesel::Queryable, diesel::Selectable, diesel::Insertable)]
@@ -82,8 +140,27 @@ pub struct MTMessage {
 #[diesel(check_for_backend(diesel::pg::Pg))]
 pub struct Target {
     pub id: uuid::Uuid,
-    pub hmac_key: Vec<u8>,
-    pub endpoint: String,
+    pub endpoint: Option<String>,
+    pub mqtt_broker: Option<String>,
+    pub mqtt_username: Option<String>,
+    pub mqtt_password: Option<String>,
+    pub email_address: Option<String>,
+    pub mt_signature_version: i16,
+    pub webhook_signing: WebhookSigning,
+    pub webhook_version: i16,
+    pub cloud_events: Option<CloudEventsMode>,
+    pub email_token: Option<String>,
+}
+
+#[derive(diesel::Queryable, diesel::Selectable, diesel::Insertable)]
+#[diesel(table_name = crate::schema::target_keys)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct TargetKey {
+    pub id: uuid::Uuid,
+    pub target: uuid::Uuid,
+    pub key: Vec<u8>,
+    pub created: chrono::NaiveDateTime,
+    pub expires: Option<chrono::NaiveDateTime>,
 }
 
 #[derive(diesel::Queryable, diesel::Selectable, diesel::Insertable)]
@@ -92,5 +169,150 @@ pub struct Target {
 pub struct Device {
     pub id: uuid::Uuid,
     pub imei: String,
-    pub target: uuid::Uuid
-}
\ No newline at end of file
+    pub target: uuid::Uuid,
+    pub mt_provider: Option<uuid::Uuid>,
+    pub metadata: Option<serde_json::Value>,
+}
+
+#[derive(diesel::Queryable, diesel::Selectable, diesel::Insertable)]
+#[diesel(table_name = crate::schema::device_groups)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DeviceGroup {
+    pub id: uuid::Uuid,
+    pub target: uuid::Uuid,
+    pub name: String,
+    pub created: chrono::NaiveDateTime,
+}
+
+#[derive(diesel::Queryable, diesel::Selectable, diesel::Insertable)]
+#[diesel(table_name = crate::schema::device_tags)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DeviceTag {
+    pub device: uuid::Uuid,
+    pub key: String,
+    pub value: String,
+}
+
+#[derive(Debug, diesel_derive_enum::DbEnum)]
+#[ExistingTypePath = "crate::schema::sql_types::MtProviderKind"]
+pub enum MTProviderKind {
+    RockBlock,
+}
+
+#[derive(diesel::Queryable, diesel::Selectable, diesel::Insertable)]
+#[diesel(table_name = crate::schema::mt_providers)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct MTProvider {
+    pub id: uuid::Uuid,
+    pub kind: MTProviderKind,
+    pub endpoint: String,
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(diesel::Queryable, diesel::Selectable, diesel::Insertable)]
+#[diesel(table_name = crate::schema::mt_permissions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct MTPermission {
+    pub id: uuid::Uuid,
+    pub target: uuid::Uuid,
+    pub imei: String,
+}
+
+#[derive(diesel::Queryable, diesel::Selectable, diesel::Insertable)]
+#[diesel(table_name = crate::schema::audit_log)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct AuditLogEntry {
+    pub id: uuid::Uuid,
+    pub time: chrono::NaiveDateTime,
+    pub target: Option<uuid::Uuid>,
+    pub event: String,
+    pub detail: String,
+    /// The MO or MT message the entry is about, if any
+    pub message: Option<uuid::Uuid>,
+}
+
+#[derive(diesel::Queryable, diesel::Selectable, diesel::Insertable)]
+#[diesel(table_name = crate::schema::signing_keys)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct SigningKey {
+    pub id: uuid::Uuid,
+    pub private_key: Vec<u8>,
+    pub public_key: Vec<u8>,
+    pub created: chrono::NaiveDateTime,
+    pub activates: chrono::NaiveDateTime,
+    pub expires: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Debug, diesel_derive_enum::DbEnum, Clone, Copy, PartialEq)]
+#[ExistingTypePath = "crate::schema::sql_types::DeliveryChannel"]
+pub enum DeliveryChannel {
+    Webhook,
+    Mqtt,
+    Email,
+}
+
+#[derive(diesel::Queryable, diesel::Selectable, diesel::Insertable)]
+#[diesel(table_name = crate::schema::event_deliveries)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct EventDelivery {
+    pub event: uuid::Uuid,
+    pub channel: DeliveryChannel,
+    pub delivered: chrono::NaiveDateTime,
+}
+
+#[derive(diesel::Queryable, diesel::Selectable, diesel::Insertable)]
+#[diesel(table_name = crate::schema::idempotency_keys)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct IdempotencyKey {
+    pub target: uuid::Uuid,
+    pub key: String,
+    pub request_hash: Vec<u8>,
+    /// Either the message or the batch created by the request
+    pub message: Option<uuid::Uuid>,
+    pub created: chrono::NaiveDateTime,
+    pub batch: Option<uuid::Uuid>,
+}
+
+#[derive(diesel::Queryable, diesel::Selectable, diesel::Insertable)]
+#[diesel(table_name = crate::schema::mt_schedules)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct MTSchedule {
+    pub id: uuid::Uuid,
+    pub target: uuid::Uuid,
+    pub imei: Option<String>,
+    pub cron: String,
+    pub data: Vec<u8>,
+    pub priority: i16,
+    pub client_reference: Option<String>,
+    pub metadata: Option<serde_json::Value>,
+    pub paused: bool,
+    pub next_run: Option<chrono::NaiveDateTime>,
+    pub created: chrono::NaiveDateTime,
+    pub device_group: Option<uuid::Uuid>,
+}
+
+#[derive(diesel::Queryable, diesel::Selectable, diesel::Insertable)]
+#[diesel(table_name = crate::schema::mt_schedule_runs)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct MTScheduleRun {
+    pub id: uuid::Uuid,
+    pub schedule: uuid::Uuid,
+    pub run_time: chrono::NaiveDateTime,
+    pub message: Option<uuid::Uuid>,
+    pub error: Option<String>,
+    pub batch: Option<uuid::Uuid>,
+}
+
+#[derive(diesel::Queryable, diesel::Selectable, diesel::Insertable)]
+#[diesel(table_name = crate::schema::mt_batches)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct MTBatch {
+    pub id: uuid::Uuid,
+    pub target: uuid::Uuid,
+    pub client_reference: Option<String>,
+    pub notify: bool,
+    pub created: chrono::NaiveDateTime,
+    pub completed: Option<chrono::NaiveDateTime>,
+    pub device_group: Option<uuid::Uuid>,
+}
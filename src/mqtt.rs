@@ -0,0 +1,234 @@
+use diesel::{ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::RunQueryDsl;
+
+type RunningBridges = std::sync::Arc<std::sync::Mutex<std::collections::HashSet<uuid::Uuid>>>;
+
+fn mqtt_options(target: &crate::models::Target, client_id: &str) -> Option<rumqttc::MqttOptions> {
+    let broker = target.mqtt_broker.as_ref()?;
+
+    let mut url = match reqwest::Url::parse(broker) {
+        Ok(u) => u,
+        Err(err) => {
+            warn!("Invalid MQTT broker URL for target {}: {}", target.id, err);
+            return None;
+        }
+    };
+    url.query_pairs_mut().append_pair("client_id", client_id);
+
+    let mut options = match rumqttc::MqttOptions::parse_url(url.to_string()) {
+        Ok(o) => o,
+        Err(err) => {
+            warn!("Invalid MQTT broker URL for target {}: {}", target.id, err);
+            return None;
+        }
+    };
+    options.set_keep_alive(std::time::Duration::from_secs(30));
+    if let Some(username) = &target.mqtt_username {
+        options.set_credentials(username, target.mqtt_password.as_deref().unwrap_or_default());
+    }
+
+    Some(options)
+}
+
+pub(crate) async fn publish(target: &crate::models::Target, topic: &str, payload: Vec<u8>) -> bool {
+    let options = match mqtt_options(target, &format!("kosmos-{}", uuid::Uuid::new_v4())) {
+        Some(o) => o,
+        None => return false
+    };
+
+    let (client, mut event_loop) = rumqttc::AsyncClient::new(options, 10);
+    if let Err(err) = client.publish(topic, rumqttc::QoS::AtLeastOnce, false, payload).await {
+        warn!("Failed to queue MQTT publish for target {}: {}", target.id, err);
+        return false;
+    }
+
+    // Only consider the message delivered once the broker has acknowledged it
+    let res = tokio::time::timeout(std::time::Duration::from_secs(30), async {
+        let mut pkid = None;
+        loop {
+            match event_loop.poll().await {
+                Ok(rumqttc::Event::Outgoing(rumqttc::Outgoing::Publish(id))) => {
+                    pkid = Some(id);
+                }
+                Ok(rumqttc::Event::Incoming(rumqttc::Packet::PubAck(ack))) if Some(ack.pkid) == pkid => {
+                    return Ok(());
+                }
+                Ok(_) => {}
+                Err(err) => return Err(err)
+            }
+        }
+    }).await;
+
+    let _ = client.try_disconnect();
+
+    match res {
+        Ok(Ok(())) => true,
+        Ok(Err(err)) => {
+            warn!("Failed to publish to MQTT broker for target {}: {}", target.id, err);
+            false
+        }
+        Err(_) => {
+            warn!("Timed out waiting for MQTT broker acknowledgement for target {}", target.id);
+            false
+        }
+    }
+}
+
+pub async fn run_bridge(amqp_addr: String, db_pool: crate::DBPool) {
+    let celery_app = match celery::app!(
+        broker = AMQP { amqp_addr },
+        tasks = [],
+        task_routes = [],
+        acks_late = false,
+    ).await {
+        Ok(a) => a,
+        Err(err) => {
+            error!("Failed to setup celery: {}", err);
+            return;
+        }
+    };
+
+    let running: RunningBridges = Default::default();
+
+    info!("Kosmos MQTT bridge running");
+
+    loop {
+        match load_bridge_targets(&db_pool).await {
+            Ok(targets) => for target_id in targets {
+                if running.lock().unwrap().insert(target_id) {
+                    tokio::spawn(bridge_target(target_id, db_pool.clone(), celery_app.clone(), running.clone()));
+                }
+            },
+            Err(err) => {
+                error!("Failed to load MQTT targets: {}", err);
+            }
+        }
+
+        tokio::time::sleep(std::time::Duration::from_secs(60)).await;
+    }
+}
+
+async fn load_bridge_targets(db_pool: &crate::DBPool) -> Result<Vec<uuid::Uuid>, String> {
+    let mut db_conn = db_pool.get().await.map_err(|e| e.to_string())?;
+
+    crate::schema::targets::dsl::targets
+        .filter(crate::schema::targets::dsl::mqtt_broker.is_not_null())
+        .select(crate::schema::targets::dsl::id)
+        .load::<uuid::Uuid>(&mut db_conn).await
+        .map_err(|e| e.to_string())
+}
+
+async fn load_target(target_id: uuid::Uuid, db_pool: &crate::DBPool) -> Result<Option<crate::models::Target>, String> {
+    let mut db_conn = db_pool.get().await.map_err(|e| e.to_string())?;
+
+    crate::schema::targets::dsl::targets
+        .filter(crate::schema::targets::dsl::id.eq(target_id))
+        .get_result::<crate::models::Target>(&mut db_conn).await
+        .optional()
+        .map_err(|e| e.to_string())
+}
+
+async fn bridge_target(
+    target_id: uuid::Uuid, db_pool: crate::DBPool, celery_app: std::sync::Arc<celery::Celery>,
+    running: RunningBridges,
+) {
+    loop {
+        let target = match load_target(target_id, &db_pool).await {
+            Ok(Some(t)) => t,
+            Ok(None) => break,
+            Err(err) => {
+                error!("Failed to load MQTT target {}: {}", target_id, err);
+                tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+                continue;
+            }
+        };
+
+        let mut options = match mqtt_options(&target, &format!("kosmos-bridge-{}", target.id)) {
+            Some(o) => o,
+            None => break
+        };
+        options.set_clean_session(false);
+        options.set_manual_acks(true);
+
+        info!("Connecting MQTT bridge for target {}", target.id);
+        let (client, mut event_loop) = rumqttc::AsyncClient::new(options, 10);
+
+        loop {
+            match event_loop.poll().await {
+                Ok(rumqttc::Event::Incoming(rumqttc::Packet::ConnAck(_))) => {
+                    if let Err(err) = client.subscribe("kosmos/+/mt", rumqttc::QoS::AtLeastOnce).await {
+                        warn!("Failed to subscribe to MT topic for target {}: {}", target.id, err);
+                        break;
+                    }
+                }
+                Ok(rumqttc::Event::Incoming(rumqttc::Packet::Publish(publish))) => {
+                    if process_publish(&target, &publish, &db_pool, &celery_app).await {
+                        if let Err(err) = client.ack(&publish).await {
+                            warn!("Failed to acknowledge MQTT publish for target {}: {}", target.id, err);
+                        }
+                    }
+                }
+                Ok(_) => {}
+                Err(err) => {
+                    warn!("MQTT connection for target {} failed: {}", target.id, err);
+                    break;
+                }
+            }
+        }
+
+        tokio::time::sleep(std::time::Duration::from_secs(10)).await;
+    }
+
+    info!("MQTT bridge for target {} stopped", target_id);
+    running.lock().unwrap().remove(&target_id);
+}
+
+/// Returns false if the message should be redelivered by the broker
+async fn process_publish(
+    target: &crate::models::Target, publish: &rumqttc::Publish, db_pool: &crate::DBPool,
+    celery_app: &celery::Celery,
+) -> bool {
+    let imei = match publish.topic.split('/').collect::<Vec<_>>()[..] {
+        ["kosmos", imei, "mt"] => imei.to_string(),
+        _ => {
+            warn!("Unexpected MQTT topic {} for target {}", publish.topic, target.id);
+            return true;
+        }
+    };
+
+    let request: crate::types::MQTTMTMessage = match serde_json::from_slice(&publish.payload) {
+        Ok(r) => r,
+        Err(err) => {
+            warn!("Invalid MQTT MT message for target {}: {}", target.id, err);
+            return true;
+        }
+    };
+
+    let mut db_conn = match db_pool.get().await {
+        Ok(c) => c,
+        Err(err) => {
+            error!("Failed to get DB connection: {}", err);
+            return false;
+        }
+    };
+
+    match crate::mt::submit_mt(&mut db_conn, celery_app, target, crate::types::MTMessage {
+        imei,
+        payload: request.payload,
+        priority: request.priority,
+        client_reference: request.client_reference,
+        metadata: request.metadata,
+        not_before: request.not_before,
+        expires_at: request.expires_at,
+    }, None).await {
+        Ok(id) => {
+            info!("Accepted MQTT MT message {} for target {}", id, target.id);
+            true
+        }
+        Err(crate::mt::SubmitError::Internal) => false,
+        Err(err) => {
+            warn!("Rejected MQTT MT message for target {}: {:?}", target.id, err);
+            true
+        }
+    }
+}
@@ -0,0 +1,495 @@
+use base64::prelude::*;
+use diesel::{BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use diesel_async::scoped_futures::ScopedFutureExt;
+
+/// How long idempotency keys are remembered for
+pub const IDEMPOTENCY_KEY_RETENTION: i64 = 24 * 60 * 60;
+/// Maximum length of a client reference, in bytes
+pub const MAX_CLIENT_REFERENCE_LENGTH: usize = 255;
+
+#[derive(Debug)]
+pub enum SubmitError {
+    InvalidIMEILength,
+    InvalidIMEI,
+    InvalidPayload,
+    /// The payload is larger than the device's provider accepts, given in bytes
+    PayloadTooLarge(usize),
+    InvalidPriority,
+    InvalidClientReference,
+    InvalidMetadata,
+    InvalidSchedule,
+    Forbidden,
+    IdempotencyConflict,
+    Internal,
+}
+
+pub struct IdempotencyKey {
+    pub key: String,
+    /// Hash of the request body, to detect reuse of a key for a different request
+    pub request_hash: Vec<u8>,
+}
+
+/// Returns the request previously made with an idempotency key, if any
+pub(crate) async fn idempotent_request(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target, key: &IdempotencyKey,
+) -> Result<Option<crate::models::IdempotencyKey>, SubmitError> {
+    let cutoff = chrono::Utc::now().naive_utc() - chrono::Duration::seconds(IDEMPOTENCY_KEY_RETENTION);
+
+    if let Err(err) = diesel::delete(crate::schema::idempotency_keys::dsl::idempotency_keys)
+        .filter(crate::schema::idempotency_keys::dsl::target.eq(&target.id))
+        .filter(crate::schema::idempotency_keys::dsl::created.lt(cutoff))
+        .execute(db_conn).await {
+        error!("Failed to remove expired idempotency keys: {}", err);
+        return Err(SubmitError::Internal);
+    }
+
+    let existing = match crate::schema::idempotency_keys::dsl::idempotency_keys
+        .filter(crate::schema::idempotency_keys::dsl::target.eq(&target.id))
+        .filter(crate::schema::idempotency_keys::dsl::key.eq(&key.key))
+        .get_result::<crate::models::IdempotencyKey>(db_conn).await
+        .optional() {
+        Ok(e) => e,
+        Err(err) => {
+            error!("Failed to get idempotency key: {}", err);
+            return Err(SubmitError::Internal);
+        }
+    };
+
+    match existing {
+        Some(e) if e.request_hash == key.request_hash => Ok(Some(e)),
+        Some(_) => Err(SubmitError::IdempotencyConflict),
+        None => Ok(None)
+    }
+}
+
+/// Returns the message previously submitted with an idempotency key, if any
+async fn idempotent_message(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target, key: &IdempotencyKey,
+) -> Result<Option<uuid::Uuid>, SubmitError> {
+    match idempotent_request(db_conn, target, key).await? {
+        Some(crate::models::IdempotencyKey { message: Some(id), .. }) => Ok(Some(id)),
+        // The key was used to create a batch
+        Some(_) => Err(SubmitError::IdempotencyConflict),
+        None => Ok(None)
+    }
+}
+
+/// Checks if a target may send MT messages to an IMEI, either because it owns the device or
+/// because the IMEI is on its allow-list
+async fn may_send_to(db_conn: &mut crate::DBConn, target: &crate::models::Target, imei: &str) -> diesel::result::QueryResult<bool> {
+    let owns_device = diesel::select(diesel::dsl::exists(
+        crate::schema::devices::dsl::devices
+            .filter(crate::schema::devices::dsl::imei.eq(imei))
+            .filter(crate::schema::devices::dsl::target.eq(&target.id))
+    )).get_result::<bool>(db_conn).await?;
+    if owns_device {
+        return Ok(true);
+    }
+
+    diesel::select(diesel::dsl::exists(
+        crate::schema::mt_permissions::dsl::mt_permissions
+            .filter(crate::schema::mt_permissions::dsl::imei.eq(imei))
+            .filter(crate::schema::mt_permissions::dsl::target.eq(&target.id))
+    )).get_result::<bool>(db_conn).await
+}
+
+/// Checks an IMEI is valid, and that the target may send to it
+pub(crate) async fn check_destination(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target, imei: &str,
+) -> Result<(), SubmitError> {
+    if imei.len() != 15 {
+        return Err(SubmitError::InvalidIMEILength);
+    }
+    if !imei.chars().all(|c| c.is_ascii_digit()) {
+        return Err(SubmitError::InvalidIMEI);
+    }
+
+    match may_send_to(db_conn, target, imei).await {
+        Ok(true) => Ok(()),
+        Ok(false) => {
+            crate::audit::record(
+                db_conn, Some(target.id), "mt_forbidden",
+                format!("MT message to IMEI {} rejected, device not owned by target", imei)
+            ).await;
+            Err(SubmitError::Forbidden)
+        }
+        Err(err) => {
+            error!("Failed to check device ownership: {}", err);
+            Err(SubmitError::Internal)
+        }
+    }
+}
+
+/// Decodes a Base64 payload, checking it will fit in an MT message of at most `max_size` bytes
+pub(crate) fn decode_payload(payload: &str, max_size: usize) -> Result<Vec<u8>, SubmitError> {
+    let data = BASE64_STANDARD.decode(payload)
+        .map_err(|_| SubmitError::InvalidPayload)?;
+    if data.len() > max_size {
+        return Err(SubmitError::PayloadTooLarge(max_size));
+    }
+    Ok(data)
+}
+
+/// Converts an API priority into the stored form, where 0 means no priority
+pub(crate) fn parse_priority(priority: Option<u8>) -> Result<i16, SubmitError> {
+    match priority {
+        Some(p) if !(1..=5).contains(&p) => Err(SubmitError::InvalidPriority),
+        Some(p) => Ok(p as i16),
+        None => Ok(0)
+    }
+}
+
+pub(crate) fn check_reference(
+    client_reference: Option<&str>, metadata: Option<&serde_json::Value>,
+) -> Result<(), SubmitError> {
+    if let Some(r) = client_reference {
+        if r.is_empty() || r.len() > MAX_CLIENT_REFERENCE_LENGTH {
+            return Err(SubmitError::InvalidClientReference);
+        }
+    }
+    if let Some(m) = metadata {
+        if !m.is_object() {
+            return Err(SubmitError::InvalidMetadata);
+        }
+    }
+    Ok(())
+}
+
+/// Validates a submitted message, returning the message to be stored
+pub(crate) async fn prepare_message(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target, request: crate::types::MTMessage,
+) -> Result<crate::models::MTMessage, SubmitError> {
+    check_destination(db_conn, target, &request.imei).await?;
+
+    let max_size = crate::provider::max_payload_size(&request.imei, &target.id, db_conn).await
+        .map_err(|err| {
+            error!("Failed to get device provider: {}", err);
+            SubmitError::Internal
+        })?;
+    let msg_data = decode_payload(&request.payload, max_size)?;
+    let priority = parse_priority(request.priority)?;
+
+    check_reference(request.client_reference.as_deref(), request.metadata.as_ref())?;
+
+    let now = chrono::Utc::now();
+    if let Some(expires_at) = request.expires_at {
+        if expires_at <= now || request.not_before.is_some_and(|n| expires_at <= n) {
+            return Err(SubmitError::InvalidSchedule);
+        }
+    }
+    // Messages due now are sent straight away, rather than waiting for the scheduler
+    let scheduled = request.not_before.is_some_and(|n| n > now);
+
+    Ok(crate::models::MTMessage {
+        id: uuid::Uuid::new_v4(),
+        imei: request.imei,
+        data: msg_data,
+        priority,
+        message_status: None,
+        processing_status: if scheduled {
+            crate::models::ProcessingStatus::Scheduled
+        } else {
+            crate::models::ProcessingStatus::Received
+        },
+        received: now.naive_utc(),
+        target: target.id,
+        idempotency_key: None,
+        client_reference: request.client_reference,
+        metadata: request.metadata,
+        attempts: 0,
+        last_attempt: None,
+        completed: None,
+        not_before: request.not_before.map(|n| n.naive_utc()),
+        expires_at: request.expires_at.map(|e| e.naive_utc()),
+        batch: None,
+        requeued: None,
+    })
+}
+
+pub async fn submit_mt(
+    db_conn: &mut crate::DBConn, celery_app: &celery::Celery,
+    target: &crate::models::Target, request: crate::types::MTMessage,
+    idempotency_key: Option<IdempotencyKey>,
+) -> Result<uuid::Uuid, SubmitError> {
+    if let Some(key) = &idempotency_key {
+        if let Some(id) = idempotent_message(db_conn, target, key).await? {
+            return Ok(id);
+        }
+    }
+
+    let mut mt_message = prepare_message(db_conn, target, request).await?;
+    mt_message.idempotency_key = idempotency_key.as_ref().map(|k| k.key.clone());
+
+    let idempotency_key_row = idempotency_key.as_ref().map(|k| crate::models::IdempotencyKey {
+        target: target.id,
+        key: k.key.clone(),
+        request_hash: k.request_hash.clone(),
+        message: Some(mt_message.id),
+        created: mt_message.received,
+        batch: None,
+    });
+
+    let mt_message_ref = &mt_message;
+    let idempotency_key_row_ref = &idempotency_key_row;
+    if let Err(err) = db_conn.transaction::<_, diesel::result::Error, _>(|conn| async move {
+        diesel::insert_into(crate::schema::mt_messages::dsl::mt_messages)
+            .values(mt_message_ref)
+            .execute(conn).await?;
+
+        if let Some(k) = idempotency_key_row_ref {
+            diesel::insert_into(crate::schema::idempotency_keys::dsl::idempotency_keys)
+                .values(k)
+                .execute(conn).await?;
+        }
+
+        Ok(())
+    }.scope_boxed()).await {
+        return match (err, &idempotency_key) {
+            // A concurrent request with the same key got there first
+            (diesel::result::Error::DatabaseError(diesel::result::DatabaseErrorKind::UniqueViolation, _), Some(key)) =>
+                match idempotent_message(db_conn, target, key).await? {
+                    Some(id) => Ok(id),
+                    None => Err(SubmitError::Internal)
+                },
+            (err, _) => {
+                error!("Failed to insert message: {}", err);
+                Err(SubmitError::Internal)
+            }
+        };
+    }
+
+    if mt_message.processing_status == crate::models::ProcessingStatus::Received {
+        if let Err(err) = celery_app.send_task(crate::worker::deliver_mt::new(mt_message.id)).await {
+            error!("Failed to send task: {}", err);
+            return Err(SubmitError::Internal);
+        }
+    }
+
+    Ok(mt_message.id)
+}
+
+pub(crate) fn message_info(message: crate::models::MTMessage) -> crate::types::MTMessageInfo {
+    crate::types::MTMessageInfo {
+        id: message.id,
+        imei: message.imei,
+        priority: if message.priority == 0 { None } else { Some(message.priority as u8) },
+        processing_status: message.processing_status.into(),
+        message_status: message.message_status.map(Into::into),
+        received: message.received.and_utc(),
+        last_attempt: message.last_attempt.map(|t| t.and_utc()),
+        completed: message.completed.map(|t| t.and_utc()),
+        not_before: message.not_before.map(|t| t.and_utc()),
+        expires_at: message.expires_at.map(|t| t.and_utc()),
+        attempts: message.attempts as u32,
+        idempotency_key: message.idempotency_key,
+        client_reference: message.client_reference,
+        metadata: message.metadata,
+        batch: message.batch,
+    }
+}
+
+#[derive(Debug)]
+pub enum CancelOutcome {
+    /// The message hadn't been accepted by the gateway, and won't be delivered
+    Cancelled,
+    /// The message had already been accepted, and a flush of the device's queue at the gateway
+    /// has been requested
+    FlushRequested,
+}
+
+#[derive(Debug)]
+pub enum CancelError {
+    NotFound,
+    /// The message is being sent, has already been accepted by the gateway, or has failed
+    NotCancellable,
+    Internal,
+}
+
+pub(crate) async fn cancel_mt(
+    db_conn: &mut crate::DBConn, celery_app: &celery::Celery, target: &crate::models::Target,
+    message_id: uuid::Uuid, flush: bool,
+) -> Result<CancelOutcome, CancelError> {
+    if get_message(db_conn, target, message_id).await.map_err(|err| {
+        error!("Failed to get MT message: {}", err);
+        CancelError::Internal
+    })?.is_none() {
+        return Err(CancelError::NotFound);
+    }
+
+    // Only messages still waiting for delivery can be cancelled, checked in the update itself so
+    // as not to race with the worker claiming the message to send it
+    let cancelled = diesel::update(crate::schema::mt_messages::dsl::mt_messages)
+        .filter(crate::schema::mt_messages::dsl::id.eq(message_id))
+        .filter(crate::schema::mt_messages::dsl::processing_status.eq_any([
+            crate::models::ProcessingStatus::Received, crate::models::ProcessingStatus::Scheduled,
+        ]))
+        .set((
+            crate::schema::mt_messages::dsl::processing_status.eq(crate::models::ProcessingStatus::Done),
+            crate::schema::mt_messages::dsl::message_status.eq(crate::models::MessageStatus::Cancelled),
+            crate::schema::mt_messages::dsl::completed.eq(chrono::Utc::now().naive_utc()),
+        ))
+        .execute(db_conn).await
+        .map_err(|err| {
+            error!("Failed to cancel MT message: {}", err);
+            CancelError::Internal
+        })?;
+
+    if cancelled != 0 {
+        crate::audit::record_message(
+            db_conn, Some(target.id), Some(message_id), "mt_cancelled", format!("MT message {} cancelled", message_id)
+        ).await;
+
+        if let Err(err) = celery_app.send_task(crate::worker::send_mt_status::new(message_id)).await {
+            error!("Failed to send MT status task: {}", err);
+        }
+        return Ok(CancelOutcome::Cancelled);
+    }
+
+    let message = match get_message(db_conn, target, message_id).await {
+        Ok(Some(m)) => m,
+        Ok(None) => return Err(CancelError::NotFound),
+        Err(err) => {
+            error!("Failed to get MT message: {}", err);
+            return Err(CancelError::Internal);
+        }
+    };
+
+    if !flush || !matches!(message.message_status, Some(crate::models::MessageStatus::Delivered)) {
+        return Err(CancelError::NotCancellable);
+    }
+
+    if let Err(err) = celery_app.send_task(crate::worker::flush_mt_queue::new(message_id)).await {
+        error!("Failed to send task: {}", err);
+        return Err(CancelError::Internal);
+    }
+
+    Ok(CancelOutcome::FlushRequested)
+}
+
+pub(crate) async fn get_message(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target, message_id: uuid::Uuid,
+) -> diesel::result::QueryResult<Option<crate::models::MTMessage>> {
+    crate::schema::mt_messages::dsl::mt_messages
+        .filter(crate::schema::mt_messages::dsl::id.eq(message_id))
+        .filter(crate::schema::mt_messages::dsl::target.eq(&target.id))
+        .get_result::<crate::models::MTMessage>(db_conn).await
+        .optional()
+}
+
+/// Position in a listing, ordered newest first
+pub struct Cursor {
+    pub time: chrono::NaiveDateTime,
+    pub id: uuid::Uuid,
+}
+
+impl Cursor {
+    pub fn encode(&self) -> String {
+        BASE64_URL_SAFE_NO_PAD.encode(format!("{}_{}", self.time.and_utc().timestamp_micros(), self.id))
+    }
+
+    pub fn decode(cursor: &str) -> Option<Self> {
+        let cursor = String::from_utf8(BASE64_URL_SAFE_NO_PAD.decode(cursor).ok()?).ok()?;
+        let (time, id) = cursor.split_once('_')?;
+        Some(Cursor {
+            time: chrono::DateTime::from_timestamp_micros(time.parse().ok()?)?.naive_utc(),
+            id: uuid::Uuid::parse_str(id).ok()?,
+        })
+    }
+}
+
+pub struct MessageFilter {
+    pub imei: Option<String>,
+    pub group: Option<uuid::Uuid>,
+    pub processing_status: Option<crate::models::ProcessingStatus>,
+    pub message_status: Option<crate::models::MessageStatus>,
+    pub batch: Option<uuid::Uuid>,
+    pub since: Option<chrono::NaiveDateTime>,
+    pub until: Option<chrono::NaiveDateTime>,
+    pub cursor: Option<Cursor>,
+    pub limit: i64,
+}
+
+/// Lists a target's MT messages, newest first, returning the cursor for the next page if there is one.
+/// Returns `None` if filtering on a group the target doesn't have.
+pub(crate) async fn list_messages(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target, filter: MessageFilter,
+) -> diesel::result::QueryResult<Option<(Vec<crate::models::MTMessage>, Option<Cursor>)>> {
+    let mut query = crate::schema::mt_messages::dsl::mt_messages
+        .filter(crate::schema::mt_messages::dsl::target.eq(&target.id))
+        .into_boxed();
+
+    if let Some(imei) = filter.imei {
+        query = query.filter(crate::schema::mt_messages::dsl::imei.eq(imei));
+    }
+    if let Some(group) = filter.group {
+        let owns_group = diesel::select(diesel::dsl::exists(
+            crate::schema::device_groups::dsl::device_groups
+                .filter(crate::schema::device_groups::dsl::id.eq(group))
+                .filter(crate::schema::device_groups::dsl::target.eq(&target.id))
+        )).get_result::<bool>(db_conn).await?;
+        if !owns_group {
+            return Ok(None);
+        }
+        query = query.filter(crate::schema::mt_messages::dsl::imei.eq_any(
+            crate::schema::devices::dsl::devices
+                .inner_join(crate::schema::device_group_members::table)
+                .filter(crate::schema::device_group_members::dsl::device_group.eq(group))
+                .select(crate::schema::devices::dsl::imei)
+        ));
+    }
+    if let Some(status) = filter.processing_status {
+        query = query.filter(crate::schema::mt_messages::dsl::processing_status.eq(status));
+    }
+    if let Some(status) = filter.message_status {
+        query = query.filter(crate::schema::mt_messages::dsl::message_status.eq(status));
+    }
+    if let Some(batch) = filter.batch {
+        query = query.filter(crate::schema::mt_messages::dsl::batch.eq(batch));
+    }
+    if let Some(since) = filter.since {
+        query = query.filter(crate::schema::mt_messages::dsl::received.ge(since));
+    }
+    if let Some(until) = filter.until {
+        query = query.filter(crate::schema::mt_messages::dsl::received.lt(until));
+    }
+    if let Some(cursor) = filter.cursor {
+        query = query.filter(
+            crate::schema::mt_messages::dsl::received.lt(cursor.time).or(
+                crate::schema::mt_messages::dsl::received.eq(cursor.time)
+                    .and(crate::schema::mt_messages::dsl::id.lt(cursor.id))
+            )
+        );
+    }
+
+    // Fetch one extra row to find out if there's another page
+    let mut messages = query
+        .order_by((crate::schema::mt_messages::dsl::received.desc(), crate::schema::mt_messages::dsl::id.desc()))
+        .limit(filter.limit + 1)
+        .load::<crate::models::MTMessage>(db_conn).await?;
+
+    let next_cursor = if messages.len() as i64 > filter.limit {
+        messages.truncate(filter.limit as usize);
+        messages.last().map(|m| Cursor {
+            time: m.received,
+            id: m.id,
+        })
+    } else {
+        None
+    };
+
+    Ok(Some((messages, next_cursor)))
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::prelude::*;
+
+    #[test]
+    fn decode_payload_limit() {
+        let payload = BASE64_STANDARD.encode([0; 270]);
+        assert_eq!(super::decode_payload(&payload, 270).unwrap().len(), 270);
+        assert!(matches!(super::decode_payload(&payload, 269), Err(super::SubmitError::PayloadTooLarge(269))));
+        assert!(matches!(super::decode_payload("not base64!", 270), Err(super::SubmitError::InvalidPayload)));
+    }
+}
@@ -0,0 +1,646 @@
+use serde_json::json;
+
+/// Payloads published as standalone JSON Schemas, for validating them outside of the API
+pub const SCHEMAS: &[&str] = &["webhook_message", "webhook_envelope", "cloud_event", "cloud_event_envelope", "mt_message", "mqtt_mt_message", "new_mt_batch"];
+
+/// A standalone JSON Schema for one of [`SCHEMAS`]
+pub fn json_schema(name: &str) -> Option<schemars::schema::RootSchema> {
+    let gen = schemars::gen::SchemaSettings::draft07().into_generator();
+    Some(match name {
+        "webhook_message" => gen.into_root_schema_for::<crate::types::WebhookMessage>(),
+        "webhook_envelope" => gen.into_root_schema_for::<crate::types::WebhookEnvelope>(),
+        "cloud_event" => gen.into_root_schema_for::<crate::types::CloudEvent>(),
+        "cloud_event_envelope" => gen.into_root_schema_for::<crate::types::CloudEvent<crate::types::WebhookEnvelope>>(),
+        "mt_message" => gen.into_root_schema_for::<crate::types::MTMessage>(),
+        "mqtt_mt_message" => gen.into_root_schema_for::<crate::types::MQTTMTMessage>(),
+        "new_mt_batch" => gen.into_root_schema_for::<crate::types::NewMTBatch>(),
+        _ => return None
+    })
+}
+
+enum Security {
+    None,
+    Target,
+    Admin,
+}
+
+struct Generator {
+    gen: schemars::gen::SchemaGenerator,
+    paths: serde_json::Map<String, serde_json::Value>,
+}
+
+impl Generator {
+    fn schema<T: schemars::JsonSchema>(&mut self) -> serde_json::Value {
+        serde_json::to_value(self.gen.subschema_for::<T>()).unwrap()
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn add(
+        &mut self, method: &str, path: &str, summary: &str, security: Security,
+        mut parameters: Vec<serde_json::Value>, body: Option<serde_json::Value>,
+        mut responses: serde_json::Map<String, serde_json::Value>,
+    ) {
+        if let Security::Target = security {
+            parameters.extend(signature_v2_params());
+        }
+        let mut operation = json!({
+            "summary": summary,
+            "parameters": parameters,
+        });
+        if let Some(body) = body {
+            operation["requestBody"] = json!({
+                "required": true,
+                "content": {
+                    "application/json": {
+                        "schema": body
+                    }
+                }
+            });
+        }
+        match security {
+            Security::None => {}
+            Security::Target => {
+                operation["security"] = json!([{"target_id": [], "target_mac": []}]);
+                responses.insert("400".to_string(), json!({"description": "Invalid request"}));
+                responses.insert("401".to_string(), json!({"description": "Missing or invalid signature"}));
+            }
+            Security::Admin => {
+                operation["security"] = json!([{"admin_token": []}]);
+                responses.insert("401".to_string(), json!({"description": "Missing or invalid admin token"}));
+            }
+        }
+        responses.insert("500".to_string(), json!({"description": "Internal error"}));
+
+        // Every error is described by a problem details body
+        let problem = self.schema::<crate::types::Problem>();
+        for (code, response) in responses.iter_mut() {
+            if code.starts_with('4') || code.starts_with('5') {
+                response["content"] = json!({
+                    "application/problem+json": {
+                        "schema": problem
+                    }
+                });
+            }
+        }
+        operation["responses"] = serde_json::Value::Object(responses);
+
+        self.paths.entry(path.to_string())
+            .or_insert_with(|| json!({}))
+            .as_object_mut().unwrap()
+            .insert(method.to_string(), operation);
+    }
+}
+
+fn path_param(name: &str) -> serde_json::Value {
+    json!({
+        "name": name,
+        "in": "path",
+        "required": true,
+        "schema": {"type": "string"}
+    })
+}
+
+fn header_param(name: &str, description: &str, schema: serde_json::Value) -> serde_json::Value {
+    json!({
+        "name": name,
+        "in": "header",
+        "required": false,
+        "description": description,
+        "schema": schema
+    })
+}
+
+/// The headers added by version 2 request signatures
+fn signature_v2_params() -> Vec<serde_json::Value> {
+    vec![
+        header_param(
+            "Kosmos-Signature-Version",
+            "The request signing version, 1 if absent",
+            json!({"type": "string", "enum": ["1", "2"]}),
+        ),
+        header_param(
+            "Kosmos-Timestamp",
+            "The current time as a Unix timestamp in seconds, required with version 2 signatures",
+            json!({"type": "string", "pattern": "^-?[0-9]+$"}),
+        ),
+        header_param(
+            "Kosmos-Nonce",
+            "A value unique to this request, required with version 2 signatures",
+            json!({"type": "string", "minLength": 1, "maxLength": 128}),
+        ),
+    ]
+}
+
+fn query_param(name: &str, schema: serde_json::Value) -> serde_json::Value {
+    json!({
+        "name": name,
+        "in": "query",
+        "required": false,
+        "schema": schema
+    })
+}
+
+fn page_params() -> Vec<serde_json::Value> {
+    vec![
+        query_param("cursor", json!({"type": "string"})),
+        query_param("limit", json!({"type": "integer", "minimum": 1, "maximum": 500})),
+    ]
+}
+
+fn time_params() -> Vec<serde_json::Value> {
+    vec![
+        query_param("since", json!({"type": "string", "format": "date-time"})),
+        query_param("until", json!({"type": "string", "format": "date-time"})),
+    ]
+}
+
+fn json_response(description: &str, schema: serde_json::Value) -> serde_json::Value {
+    json!({
+        "description": description,
+        "content": {
+            "application/json": {
+                "schema": schema
+            }
+        }
+    })
+}
+
+fn responses<const N: usize>(
+    responses: [(u16, serde_json::Value); N],
+) -> serde_json::Map<String, serde_json::Value> {
+    responses.into_iter().map(|(code, response)| (code.to_string(), response)).collect()
+}
+
+fn empty(description: &str) -> serde_json::Value {
+    json!({"description": description})
+}
+
+/// The OpenAPI 3.1 description of the HTTP API and the webhooks it sends
+pub fn document() -> serde_json::Value {
+    let mut settings = schemars::gen::SchemaSettings::draft2019_09();
+    settings.definitions_path = "#/components/schemas/".to_string();
+    let mut g = Generator {
+        gen: settings.into_generator(),
+        paths: serde_json::Map::new(),
+    };
+    let uuid = json!({"type": "string", "format": "uuid"});
+
+    let idempotency_key = header_param(
+        "Idempotency-Key", "Makes retries of the request return the original result",
+        json!({"type": "string", "maxLength": 255}),
+    );
+
+    let mt_message = g.schema::<crate::types::MTMessage>();
+    g.add("post", "/submit_mt", "Submit an MT message", Security::Target, vec![idempotency_key.clone()], Some(mt_message), responses([
+        (200, json!({
+            "description": "The ID of the message",
+            "content": {"text/plain": {"schema": {"type": "string", "format": "uuid"}}}
+        })),
+        (403, empty("The target may not send to this device")),
+        (409, empty("The idempotency key was used with a different body")),
+    ]));
+
+    let mt_info = g.schema::<crate::types::MTMessageInfo>();
+    g.add("get", "/mt/{message_id}", "Get an MT message", Security::Target, vec![path_param("message_id")], None, responses([
+        (200, json_response("The message", mt_info)),
+        (404, empty("No such message")),
+    ]));
+    g.add("delete", "/mt/{message_id}", "Cancel an MT message", Security::Target, vec![
+        path_param("message_id"), query_param("flush", json!({"type": "boolean"})),
+    ], None, responses([
+        (202, empty("The device's MT queue will be flushed")),
+        (204, empty("The message was cancelled")),
+        (404, empty("No such message")),
+        (409, empty("The message can no longer be cancelled")),
+    ]));
+
+    let processing_status = g.schema::<crate::types::ProcessingStatus>();
+    let message_status = g.schema::<crate::types::MessageStatus>();
+    let session_status = g.schema::<crate::types::SessionStatus>();
+    let mt_list = g.schema::<crate::types::MTMessageList>();
+    let mut params = vec![
+        query_param("imei", json!({"type": "string"})),
+        query_param("group", uuid.clone()),
+        query_param("processing_status", processing_status.clone()),
+        query_param("message_status", message_status),
+        query_param("batch", uuid.clone()),
+    ];
+    params.extend(time_params());
+    params.extend(page_params());
+    g.add("get", "/mt", "List MT messages", Security::Target, params, None, responses([
+        (200, json_response("A page of messages, newest first", mt_list)),
+    ]));
+
+    let mo_info = g.schema::<crate::types::MOMessageInfo>();
+    g.add("get", "/mo/{message_id}", "Get an MO message", Security::Target, vec![path_param("message_id")], None, responses([
+        (200, json_response("The message", mo_info)),
+        (404, empty("No such message")),
+    ]));
+    g.add("get", "/mo/{message_id}/payload", "Get the payload of an MO message", Security::Target, vec![path_param("message_id")], None, responses([
+        (200, json!({
+            "description": "The raw payload",
+            "content": {"application/octet-stream": {"schema": {"type": "string", "format": "binary"}}}
+        })),
+        (404, empty("No such message, or it has no payload")),
+    ]));
+    let mo_list = g.schema::<crate::types::MOMessageList>();
+    let mut params = vec![
+        query_param("imei", json!({"type": "string"})),
+        query_param("group", uuid.clone()),
+        query_param("session_status", session_status),
+        query_param("processing_status", processing_status),
+    ];
+    params.extend(time_params());
+    params.extend(page_params());
+    g.add("get", "/mo", "List MO messages", Security::Target, params, None, responses([
+        (200, json_response("A page of messages, newest first", mo_list)),
+    ]));
+
+    let new_batch = g.schema::<crate::types::NewMTBatch>();
+    let batch = g.schema::<crate::types::MTBatchStatus>();
+    g.add("post", "/batches", "Submit a batch of MT messages", Security::Target, vec![idempotency_key], Some(new_batch), responses([
+        (200, json_response("The new batch", batch.clone())),
+        (403, empty("The target may not send to one of the devices")),
+        (409, empty("The idempotency key was used with a different body")),
+    ]));
+    g.add("get", "/batches/{batch_id}", "Get a batch", Security::Target, vec![path_param("batch_id")], None, responses([
+        (200, json_response("The batch", batch)),
+        (404, empty("No such batch")),
+    ]));
+
+    let new_schedule = g.schema::<crate::types::NewMTSchedule>();
+    let schedule = g.schema::<crate::types::MTSchedule>();
+    let schedules = g.schema::<Vec<crate::types::MTSchedule>>();
+    let runs = g.schema::<crate::types::MTScheduleRunList>();
+    g.add("post", "/schedules", "Create a recurring message", Security::Target, vec![], Some(new_schedule), responses([
+        (200, json_response("The new schedule", schedule.clone())),
+    ]));
+    g.add("get", "/schedules", "List recurring messages", Security::Target, vec![], None, responses([
+        (200, json_response("The target's schedules", schedules)),
+    ]));
+    g.add("get", "/schedules/{schedule_id}", "Get a recurring message", Security::Target, vec![path_param("schedule_id")], None, responses([
+        (200, json_response("The schedule", schedule.clone())),
+        (404, empty("No such schedule")),
+    ]));
+    g.add("post", "/schedules/{schedule_id}/pause", "Pause a recurring message", Security::Target, vec![path_param("schedule_id")], None, responses([
+        (200, json_response("The schedule", schedule.clone())),
+        (404, empty("No such schedule")),
+    ]));
+    g.add("post", "/schedules/{schedule_id}/resume", "Resume a recurring message", Security::Target, vec![path_param("schedule_id")], None, responses([
+        (200, json_response("The schedule", schedule)),
+        (404, empty("No such schedule")),
+    ]));
+    g.add("delete", "/schedules/{schedule_id}", "Delete a recurring message", Security::Target, vec![path_param("schedule_id")], None, responses([
+        (204, empty("The schedule was deleted")),
+        (404, empty("No such schedule")),
+    ]));
+    let mut params = vec![path_param("schedule_id")];
+    params.extend(page_params());
+    g.add("get", "/schedules/{schedule_id}/runs", "List the runs of a recurring message", Security::Target, params, None, responses([
+        (200, json_response("A page of runs, newest first", runs)),
+        (404, empty("No such schedule")),
+    ]));
+
+    let device = g.schema::<crate::types::Device>();
+    let devices = g.schema::<Vec<crate::types::Device>>();
+    let tags = g.schema::<std::collections::BTreeMap<String, String>>();
+    g.add("get", "/devices", "List devices", Security::Target, vec![], None, responses([
+        (200, json_response("The target's devices", devices)),
+    ]));
+    g.add("get", "/devices/{imei}", "Get a device", Security::Target, vec![path_param("imei")], None, responses([
+        (200, json_response("The device", device.clone())),
+        (404, empty("No such device")),
+    ]));
+    g.add("put", "/devices/{imei}/tags", "Replace a device's tags", Security::Target, vec![path_param("imei")], Some(tags), responses([
+        (200, json_response("The device", device)),
+        (404, empty("No such device")),
+    ]));
+
+    let new_group = g.schema::<crate::types::NewDeviceGroup>();
+    let group = g.schema::<crate::types::DeviceGroup>();
+    let groups = g.schema::<Vec<crate::types::DeviceGroup>>();
+    g.add("post", "/groups", "Create a device group", Security::Target, vec![], Some(new_group), responses([
+        (200, json_response("The new group", group.clone())),
+        (409, empty("A group with this name already exists")),
+    ]));
+    g.add("get", "/groups", "List device groups", Security::Target, vec![], None, responses([
+        (200, json_response("The target's groups", groups)),
+    ]));
+    g.add("get", "/groups/{group_id}", "Get a device group", Security::Target, vec![path_param("group_id")], None, responses([
+        (200, json_response("The group", group)),
+        (404, empty("No such group")),
+    ]));
+    g.add("delete", "/groups/{group_id}", "Delete a device group", Security::Target, vec![path_param("group_id")], None, responses([
+        (204, empty("The group was deleted")),
+        (404, empty("No such group")),
+    ]));
+    g.add("put", "/groups/{group_id}/devices/{imei}", "Add a device to a group", Security::Target, vec![
+        path_param("group_id"), path_param("imei"),
+    ], None, responses([
+        (204, empty("The device is in the group")),
+        (404, empty("No such group or device")),
+    ]));
+    g.add("delete", "/groups/{group_id}/devices/{imei}", "Remove a device from a group", Security::Target, vec![
+        path_param("group_id"), path_param("imei"),
+    ], None, responses([
+        (204, empty("The device isn't in the group")),
+        (404, empty("No such group or device")),
+    ]));
+
+    let keys = g.schema::<Vec<crate::types::TargetKey>>();
+    let rotate = g.schema::<crate::types::RotateKeys>();
+    let new_key = g.schema::<crate::types::NewTargetKey>();
+    let jwks = g.schema::<crate::types::JWKSet>();
+    g.add("get", "/keys", "List the target's keys", Security::Target, vec![], None, responses([
+        (200, json_response("The target's keys", keys)),
+    ]));
+    g.add("post", "/keys/rotate", "Rotate the target's keys, requires version 2 signatures", Security::Target, vec![], Some(rotate), responses([
+        (200, json_response("The new key", new_key.clone())),
+    ]));
+    g.add("delete", "/keys/{key_id}", "Revoke a key", Security::Target, vec![path_param("key_id")], None, responses([
+        (204, empty("The key was revoked")),
+        (404, empty("No such key")),
+        (409, empty("This is the target's only active key")),
+    ]));
+    g.add("get", "/.well-known/jwks.json", "Public keys used to sign Ed25519 webhooks", Security::None, vec![], None, responses([
+        (200, json_response("The public keys", jwks)),
+    ]));
+
+    g.add("get", "/openapi.json", "This document", Security::None, vec![], None, responses([
+        (200, json_response("The OpenAPI document", json!({"type": "object"}))),
+    ]));
+    let schema_names = SCHEMAS.iter().map(|name| format!("{}.json", name)).collect::<Vec<_>>();
+    g.add("get", "/schemas/{name}", "A standalone JSON Schema", Security::None, vec![json!({
+        "name": "name",
+        "in": "path",
+        "required": true,
+        "schema": {"type": "string", "enum": schema_names}
+    })], None, responses([
+        (200, json_response("The JSON Schema", json!({"type": "object"}))),
+        (404, empty("No such schema")),
+    ]));
+
+    g.add("post", "/rockblock/mo", "Receive an MO message from RockBLOCK", Security::None, vec![], None, responses([
+        (200, empty("The message was accepted")),
+        (400, empty("Invalid message")),
+        (401, empty("The message's JWT isn't validly signed by RockBLOCK, or doesn't match its fields")),
+        (404, empty("RockBLOCK delivery isn't configured")),
+    ]));
+    g.paths["/rockblock/mo"]["post"]["requestBody"] = json!({
+        "required": true,
+        "content": {
+            "application/x-www-form-urlencoded": {
+                "schema": {
+                    "type": "object",
+                    "required": ["imei", "momsn", "transmit_time", "JWT"],
+                    "properties": {
+                        "imei": {"type": "string"},
+                        "device_type": {"type": "string"},
+                        "serial": {"type": "string"},
+                        "momsn": {"type": "integer", "minimum": 0, "maximum": 65535},
+                        "transmit_time": {"type": "string"},
+                        "iridium_latitude": {"type": "string"},
+                        "iridium_longitude": {"type": "string"},
+                        "iridium_cep": {"type": "string"},
+                        "data": {"type": "string", "description": "Hex encoded payload"},
+                        "JWT": {"type": "string", "description": "RS256 JWT signed by RockBLOCK over the other fields"},
+                    }
+                }
+            }
+        }
+    });
+
+    let target_config = g.schema::<crate::types::TargetConfig>();
+    let new_target = g.schema::<crate::types::NewTarget>();
+    let target = g.schema::<crate::types::Target>();
+    let targets = g.schema::<Vec<crate::types::Target>>();
+    g.add("post", "/admin/targets", "Create a target", Security::Admin, vec![], Some(target_config.clone()), responses([
+        (200, json_response("The new target, and its first key", new_target)),
+        (400, empty("Invalid target configuration")),
+    ]));
+    g.add("get", "/admin/targets", "List targets", Security::Admin, vec![], None, responses([
+        (200, json_response("All targets", targets)),
+    ]));
+    g.add("get", "/admin/targets/{target_id}", "Get a target", Security::Admin, vec![path_param("target_id")], None, responses([
+        (200, json_response("The target", target.clone())),
+        (404, empty("No such target")),
+    ]));
+    g.add("put", "/admin/targets/{target_id}", "Replace a target's configuration", Security::Admin, vec![path_param("target_id")], Some(target_config), responses([
+        (200, json_response("The target", target)),
+        (404, empty("No such target")),
+        (400, empty("Invalid target configuration")),
+    ]));
+    g.add("delete", "/admin/targets/{target_id}", "Delete a target", Security::Admin, vec![path_param("target_id")], None, responses([
+        (204, empty("The target was deleted")),
+        (404, empty("No such target")),
+        (409, empty("The target still has devices or messages")),
+    ]));
+
+    let device_config = g.schema::<crate::types::DeviceConfig>();
+    let admin_device = g.schema::<crate::types::AdminDevice>();
+    let admin_devices = g.schema::<Vec<crate::types::AdminDevice>>();
+    g.add("post", "/admin/devices", "Create a device", Security::Admin, vec![], Some(device_config.clone()), responses([
+        (200, json_response("The new device", admin_device.clone())),
+        (409, empty("The IMEI is already registered")),
+        (400, empty("Invalid device configuration")),
+    ]));
+    g.add("get", "/admin/devices", "List devices", Security::Admin, vec![query_param("target", uuid)], None, responses([
+        (200, json_response("All devices, or those of one target", admin_devices)),
+    ]));
+    g.add("get", "/admin/devices/{device_id}", "Get a device", Security::Admin, vec![path_param("device_id")], None, responses([
+        (200, json_response("The device", admin_device.clone())),
+        (404, empty("No such device")),
+    ]));
+    g.add("put", "/admin/devices/{device_id}", "Replace a device's configuration", Security::Admin, vec![path_param("device_id")], Some(device_config), responses([
+        (200, json_response("The device", admin_device)),
+        (404, empty("No such device")),
+        (409, empty("The IMEI is already registered")),
+        (400, empty("Invalid device configuration")),
+    ]));
+    g.add("delete", "/admin/devices/{device_id}", "Delete a device", Security::Admin, vec![path_param("device_id")], None, responses([
+        (204, empty("The device was deleted")),
+        (404, empty("No such device")),
+        (409, empty("The device still has messages")),
+    ]));
+
+    let webhook = g.schema::<crate::types::WebhookMessage>();
+    let webhook_envelope = g.schema::<crate::types::WebhookEnvelope>();
+    let cloud_event = g.schema::<crate::types::CloudEvent>();
+    let cloud_event_envelope = g.schema::<crate::types::CloudEvent<crate::types::WebhookEnvelope>>();
+    let webhook_headers = vec![
+        json!({
+            "name": "Kosmos-MAC",
+            "in": "header",
+            "required": false,
+            "description": "Base64 encoded HMAC-SHA256 of the body, for HMAC signed targets",
+            "schema": {"type": "string"}
+        }),
+        json!({
+            "name": "Kosmos-Signature",
+            "in": "header",
+            "required": true,
+            "schema": {"type": "string"}
+        }),
+    ];
+
+    json!({
+        "openapi": "3.1.0",
+        "info": {
+            "title": "Kosmos",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "paths": g.paths,
+        "webhooks": {
+            "message": {
+                "post": {
+                    "summary": "MO messages, MT status updates, and completed batches",
+                    "parameters": webhook_headers.clone(),
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": webhook
+                            }
+                        }
+                    },
+                    "responses": {
+                        "2XX": {"description": "The webhook was accepted, any other response is retried"}
+                    }
+                }
+            },
+            "event": {
+                "post": {
+                    "summary": "Version 2 webhooks, for targets with webhook_version set to 2",
+                    "parameters": webhook_headers.clone(),
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/json": {
+                                "schema": webhook_envelope
+                            }
+                        }
+                    },
+                    "responses": {
+                        "2XX": {"description": "The webhook was accepted, any other response is retried"}
+                    }
+                }
+            },
+            "cloud_event": {
+                "post": {
+                    "summary": "Structured mode CloudEvents, for targets with cloud_events set to structured; the data is in the target's webhook version",
+                    "parameters": webhook_headers,
+                    "requestBody": {
+                        "required": true,
+                        "content": {
+                            "application/cloudevents+json": {
+                                "schema": {"oneOf": [cloud_event, cloud_event_envelope]}
+                            }
+                        }
+                    },
+                    "responses": {
+                        "2XX": {"description": "The webhook was accepted, any other response is retried"}
+                    }
+                }
+            }
+        },
+        "components": {
+            "schemas": g.gen.take_definitions(),
+            "securitySchemes": {
+                "target_id": {
+                    "type": "apiKey",
+                    "in": "header",
+                    "name": "Kosmos-Target-ID",
+                },
+                "target_mac": {
+                    "type": "apiKey",
+                    "in": "header",
+                    "name": "Kosmos-MAC",
+                    "description": "Base64 encoded HMAC-SHA256 of the request, see the request signing documentation",
+                },
+                "admin_token": {
+                    "type": "http",
+                    "scheme": "bearer",
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    /// Routes deliberately left out of the document
+    const UNDOCUMENTED: &[(&str, &str)] = &[
+        // Only exists to answer GETs with 405 Method Not Allowed
+        ("get", "/submit_mt"),
+    ];
+
+    /// The OpenAPI form of a Rocket path, and the names of its query parameters
+    fn openapi_path(route: &rocket::Route) -> (String, Vec<String>) {
+        let path = route.uri.path().split('/')
+            .map(|segment| match segment.strip_prefix('<').and_then(|s| s.strip_suffix('>')) {
+                Some(name) => format!("{{{}}}", name),
+                None => segment.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join("/");
+        let query = route.uri.query().unwrap_or_default().split('&')
+            .filter_map(|segment| segment.strip_prefix('<')?.strip_suffix('>'))
+            .map(|name| name.to_string())
+            .collect();
+        (path, query)
+    }
+
+    #[test]
+    fn documents_every_route() {
+        let document = super::document();
+        for route in crate::http::routes() {
+            let method = route.method.as_str().to_ascii_lowercase();
+            let (path, query) = openapi_path(&route);
+            if UNDOCUMENTED.contains(&(method.as_str(), path.as_str())) {
+                continue;
+            }
+
+            let operation = &document["paths"][&path][&method];
+            assert!(operation.is_object(), "{} {} isn't documented", method, path);
+            let parameters = operation["parameters"].as_array().unwrap();
+            for name in query {
+                assert!(
+                    parameters.iter().any(|p| p["in"] == "query" && p["name"] == name.as_str()),
+                    "{} {} doesn't document the {} query parameter", method, path, name
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn documents_only_mounted_routes() {
+        let routes = crate::http::routes().iter()
+            .map(|route| (route.method.as_str().to_ascii_lowercase(), openapi_path(route).0))
+            .collect::<Vec<_>>();
+        let document = super::document();
+        for (path, operations) in document["paths"].as_object().unwrap() {
+            for method in operations.as_object().unwrap().keys() {
+                assert!(
+                    routes.contains(&(method.clone(), path.clone())),
+                    "{} {} is documented but not mounted", method, path
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn documents_signature_v2_headers() {
+        let document = super::document();
+        let parameters = document["paths"]["/mt/{message_id}"]["delete"]["parameters"].as_array().unwrap();
+        for name in ["Kosmos-Signature-Version", "Kosmos-Timestamp", "Kosmos-Nonce"] {
+            assert!(parameters.iter().any(|p| p["in"] == "header" && p["name"] == name), "{} isn't documented", name);
+        }
+    }
+
+    #[test]
+    fn schemas_exist() {
+        for name in super::SCHEMAS {
+            assert!(super::json_schema(name).is_some(), "{} has no schema", name);
+        }
+    }
+}
\ No newline at end of file
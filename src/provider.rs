@@ -0,0 +1,389 @@
+use diesel::{ExpressionMethods, NullableExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::RunQueryDsl;
+
+#[derive(Debug)]
+pub enum DeliveryStatus {
+    Delivered,
+    InvalidIMEI,
+    PayloadSizeExceeded,
+    QueueFull,
+    ResourcesUnavailable,
+    /// The gateway response couldn't be used, and delivery should be tried again
+    Retry,
+    /// The gateway rejected the message for a reason that can't be represented as a message status
+    Rejected(String),
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// A failure talking to the gateway, likely to succeed if tried again
+    Expected(String),
+    Unexpected(String),
+}
+
+#[async_trait::async_trait]
+pub trait Provider: Send + Sync {
+    async fn deliver(&self, message: &crate::models::MTMessage) -> Result<DeliveryStatus, Error>;
+
+    /// Removes any messages queued at the gateway for a device
+    async fn flush(&self, _imei: &str) -> Result<(), Error> {
+        Err(Error::Unexpected("Provider doesn't support flushing the MT queue".to_string()))
+    }
+}
+
+/// Largest MT payload Iridium's DirectIP gateway accepts, in bytes
+pub const DIRECT_IP_MAX_PAYLOAD_SIZE: usize = 1890;
+/// Largest MT payload RockBLOCK accepts, in bytes
+pub const ROCKBLOCK_MAX_PAYLOAD_SIZE: usize = 270;
+
+fn max_payload_size_for(kind: Option<crate::models::MTProviderKind>) -> usize {
+    match kind {
+        Some(crate::models::MTProviderKind::RockBlock) => ROCKBLOCK_MAX_PAYLOAD_SIZE,
+        None => DIRECT_IP_MAX_PAYLOAD_SIZE,
+    }
+}
+
+/// Largest MT payload that can be delivered to an IMEI through its device's provider. The device
+/// is found as in [`for_message`].
+pub(crate) async fn max_payload_size(
+    imei: &str, target: &uuid::Uuid, db_conn: &mut crate::DBConn,
+) -> diesel::result::QueryResult<usize> {
+    let kind = crate::schema::devices::dsl::devices
+        .left_join(crate::schema::mt_providers::table)
+        .filter(crate::schema::devices::dsl::imei.eq(imei))
+        .order(crate::schema::devices::dsl::target.eq(target).desc())
+        .select(crate::schema::mt_providers::dsl::kind.nullable())
+        .first::<Option<crate::models::MTProviderKind>>(db_conn).await
+        .optional()?
+        .flatten();
+
+    Ok(max_payload_size_for(kind))
+}
+
+/// Largest MT payload that can be delivered to every device in a group
+pub(crate) async fn max_group_payload_size(
+    group_id: uuid::Uuid, db_conn: &mut crate::DBConn,
+) -> diesel::result::QueryResult<usize> {
+    let kinds = crate::schema::devices::dsl::devices
+        .inner_join(crate::schema::device_group_members::table)
+        .left_join(crate::schema::mt_providers::table)
+        .filter(crate::schema::device_group_members::dsl::device_group.eq(group_id))
+        .select(crate::schema::mt_providers::dsl::kind.nullable())
+        .distinct()
+        .load::<Option<crate::models::MTProviderKind>>(db_conn).await?;
+
+    Ok(kinds.into_iter().map(max_payload_size_for).min().unwrap_or(DIRECT_IP_MAX_PAYLOAD_SIZE))
+}
+
+/// Returns the provider for an MT message's device, defaulting to Iridium DirectIP. The device may
+/// belong to another target that lets the sender message it.
+pub(crate) async fn for_message(
+    message: &crate::models::MTMessage, http_client: &reqwest::Client, db_conn: &mut crate::DBConn,
+) -> diesel::result::QueryResult<Box<dyn Provider>> {
+    let provider_id = crate::schema::devices::dsl::devices
+        .filter(crate::schema::devices::dsl::imei.eq(&message.imei))
+        .order(crate::schema::devices::dsl::target.eq(&message.target).desc())
+        .select(crate::schema::devices::dsl::mt_provider)
+        .first::<Option<uuid::Uuid>>(db_conn).await
+        .optional()?
+        .flatten();
+
+    let provider = match provider_id {
+        Some(id) => crate::schema::mt_providers::dsl::mt_providers
+            .filter(crate::schema::mt_providers::dsl::id.eq(id))
+            .get_result::<crate::models::MTProvider>(db_conn).await?,
+        None => return Ok(Box::new(DirectIP {
+            addr: crate::IRIDUM_MT_ADDR.to_string()
+        }))
+    };
+
+    Ok(match provider.kind {
+        crate::models::MTProviderKind::RockBlock => Box::new(RockBLOCK {
+            client: http_client.clone(),
+            endpoint: provider.endpoint,
+            username: provider.username,
+            password: provider.password,
+        })
+    })
+}
+
+/// Iridium's own DirectIP gateway
+pub struct DirectIP {
+    pub addr: String,
+}
+
+impl DirectIP {
+    /// Sends a protocol message to the gateway, returning the confirmation if it was for the
+    /// same message, or None if it wasn't
+    async fn send(
+        &self, header: &crate::ie::MTHeader, elements: Vec<crate::ie::Element>,
+    ) -> Result<Option<crate::ie::MTConfirmation>, Error> {
+        let mut socket = tokio::net::TcpStream::connect(&self.addr).await
+            .map_err(|e| Error::Expected(format!("Failed to connect to Iridium gateway: {}", e)))?;
+
+        let protocol_message = crate::ie::ProtocolMessage {
+            elements
+        };
+
+        trace!("Sending message: {:02x?}", protocol_message);
+
+        protocol_message.write(&mut socket).await
+            .map_err(|e| Error::Expected(format!("Failed to write protocol message to socket: {}", e)))?;
+
+        let response_protocol_message = crate::ie::ProtocolMessage::read(&mut socket).await
+            .map_err(|e| Error::Expected(format!("Failed to read response protocol message from socket: {}", e)))?;
+        let response_message = crate::ie::ResponseMessage::from_pm(response_protocol_message)
+            .map_err(|e| Error::Unexpected(format!("Failed to decode response message: {}", e)))?;
+
+        trace!("Got response: {:02x?}", response_message);
+
+        if response_message.confirmation.client_message_id != header.client_message_id ||
+            response_message.confirmation.imei != header.imei {
+            warn!("Response not for sent message");
+            return Ok(None);
+        }
+
+        Ok(Some(response_message.confirmation))
+    }
+}
+
+#[async_trait::async_trait]
+impl Provider for DirectIP {
+    async fn deliver(&self, message: &crate::models::MTMessage) -> Result<DeliveryStatus, Error> {
+        let header = crate::ie::MTHeader {
+            client_message_id: rand::random(),
+            imei: message.imei.clone(),
+            flush_mt_queue: false,
+            send_ring_alert: false,
+            update_ssd_location: false,
+            high_priority_message: message.priority != 0,
+            assign_mtmsn: false,
+        };
+
+        let payload = crate::ie::MTPayload {
+            data: message.data.clone()
+        };
+
+        let mut elements = vec![header.to_element(), payload.to_element()];
+
+        if message.priority != 0 {
+            elements.push(crate::ie::MTPriority {
+                level: message.priority as u16,
+            }.to_element());
+        }
+
+        let confirmation = match self.send(&header, elements).await? {
+            Some(c) => c,
+            None => return Ok(DeliveryStatus::Retry)
+        };
+
+        Ok(match confirmation.message_status {
+            crate::ie::MessageStatus::Successful(_) => DeliveryStatus::Delivered,
+            crate::ie::MessageStatus::SuccessfulNoPayload => {
+                warn!("Expected payload to be acknowledged");
+                DeliveryStatus::Retry
+            }
+            crate::ie::MessageStatus::UnknownIMEI => DeliveryStatus::InvalidIMEI,
+            crate::ie::MessageStatus::TooLarge => DeliveryStatus::PayloadSizeExceeded,
+            crate::ie::MessageStatus::QueueFull => DeliveryStatus::QueueFull,
+            crate::ie::MessageStatus::ResourcesUnavailable => DeliveryStatus::ResourcesUnavailable,
+            o => DeliveryStatus::Rejected(format!("unexpected response status: {:?}", o))
+        })
+    }
+
+    async fn flush(&self, imei: &str) -> Result<(), Error> {
+        let header = crate::ie::MTHeader {
+            client_message_id: rand::random(),
+            imei: imei.to_string(),
+            flush_mt_queue: true,
+            send_ring_alert: false,
+            update_ssd_location: false,
+            high_priority_message: false,
+            assign_mtmsn: false,
+        };
+
+        let elements = vec![header.to_element()];
+        match self.send(&header, elements).await? {
+            Some(crate::ie::MTConfirmation { message_status: crate::ie::MessageStatus::SuccessfulNoPayload, .. }) => Ok(()),
+            Some(c) => Err(Error::Unexpected(format!("unexpected response status: {:?}", c.message_status))),
+            None => Err(Error::Expected("Response not for sent message".to_string()))
+        }
+    }
+}
+
+/// A reseller HTTP API in the style of RockBLOCK
+pub struct RockBLOCK {
+    pub client: reqwest::Client,
+    pub endpoint: String,
+    pub username: String,
+    pub password: String,
+}
+
+#[async_trait::async_trait]
+impl Provider for RockBLOCK {
+    async fn deliver(&self, message: &crate::models::MTMessage) -> Result<DeliveryStatus, Error> {
+        let data = hex::encode(&message.data);
+        let res = self.client.post(&self.endpoint)
+            .form(&[
+                ("imei", message.imei.as_str()),
+                ("username", self.username.as_str()),
+                ("password", self.password.as_str()),
+                ("data", data.as_str()),
+            ])
+            .send().await
+            .map_err(|e| Error::Expected(format!("Failed to send to reseller: {}", e)))?;
+
+        if !res.status().is_success() {
+            return Err(Error::Expected(format!("Received error response from reseller: {}", res.status())));
+        }
+
+        let body = res.text().await
+            .map_err(|e| Error::Expected(format!("Failed to read response from reseller: {}", e)))?;
+
+        trace!("Got response: {}", body);
+
+        let mut parts = body.trim().splitn(3, ',');
+        Ok(match (parts.next(), parts.next(), parts.next()) {
+            (Some("OK"), _, _) => DeliveryStatus::Delivered,
+            (Some("FAILED"), Some(code), description) => match code {
+                // Invalid login credentials, insufficient credit; retrying won't help until an
+                // operator fixes the account
+                "10" | "13" => DeliveryStatus::Rejected(format!(
+                    "reseller account error: {} {}", code, description.unwrap_or_default()
+                )),
+                // System error
+                "99" => {
+                    warn!("Failed to send MT message, reseller error: {}", description.unwrap_or_default());
+                    DeliveryStatus::ResourcesUnavailable
+                }
+                // No device with this IMEI on the account, device has no line rental
+                "11" | "12" => DeliveryStatus::InvalidIMEI,
+                "15" => DeliveryStatus::PayloadSizeExceeded,
+                _ => DeliveryStatus::Rejected(format!(
+                    "unexpected response status: {} {}", code, description.unwrap_or_default()
+                ))
+            },
+            _ => return Err(Error::Unexpected(format!("Failed to decode reseller response: {}", body)))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Provider;
+    use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
+
+    /// Accepts a single request, answering it with the given status and body, and returns the
+    /// endpoint along with a handle resolving to the request body
+    async fn mock_server(status: &'static str, body: &'static str) -> (String, tokio::task::JoinHandle<String>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint = format!("http://{}/rockblock/MT", listener.local_addr().unwrap());
+        let handle = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut socket = tokio::io::BufReader::new(socket);
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                socket.read_line(&mut line).await.unwrap();
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some((name, value)) = line.split_once(':') {
+                    if name.eq_ignore_ascii_case("content-length") {
+                        content_length = value.trim().parse().unwrap();
+                    }
+                }
+            }
+            let mut request = vec![0; content_length];
+            socket.read_exact(&mut request).await.unwrap();
+            socket.write_all(format!(
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", status, body.len(), body
+            ).as_bytes()).await.unwrap();
+            String::from_utf8(request).unwrap()
+        });
+        (endpoint, handle)
+    }
+
+    fn provider(endpoint: String) -> super::RockBLOCK {
+        super::RockBLOCK {
+            client: reqwest::Client::new(),
+            endpoint,
+            username: "user".to_string(),
+            password: "pass".to_string(),
+        }
+    }
+
+    fn message() -> crate::models::MTMessage {
+        crate::models::MTMessage {
+            id: uuid::Uuid::nil(),
+            imei: "300234010753370".to_string(),
+            priority: 0,
+            data: b"Hello".to_vec(),
+            message_status: None,
+            processing_status: crate::models::ProcessingStatus::Received,
+            received: chrono::NaiveDateTime::default(),
+            target: uuid::Uuid::nil(),
+            idempotency_key: None,
+            client_reference: None,
+            metadata: None,
+            attempts: 0,
+            last_attempt: None,
+            completed: None,
+            not_before: None,
+            expires_at: None,
+            batch: None,
+            requeued: None,
+        }
+    }
+
+    async fn deliver(status: &'static str, body: &'static str) -> (Result<super::DeliveryStatus, super::Error>, String) {
+        let (endpoint, request) = mock_server(status, body).await;
+        let res = provider(endpoint).deliver(&message()).await;
+        (res, request.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn deliver_ok() {
+        let (res, request) = deliver("200 OK", "OK,12345678").await;
+        assert!(matches!(res, Ok(super::DeliveryStatus::Delivered)));
+        assert_eq!(request, "imei=300234010753370&username=user&password=pass&data=48656c6c6f");
+    }
+
+    #[tokio::test]
+    async fn deliver_account_errors_are_permanent() {
+        for body in ["FAILED,10,Invalid login credentials", "FAILED,13,Insufficient credit"] {
+            let (res, _) = deliver("200 OK", body).await;
+            assert!(matches!(res, Ok(super::DeliveryStatus::Rejected(_))), "{}", body);
+        }
+    }
+
+    #[tokio::test]
+    async fn deliver_errors() {
+        let (res, _) = deliver("200 OK", "FAILED,99,System error").await;
+        assert!(matches!(res, Ok(super::DeliveryStatus::ResourcesUnavailable)));
+
+        let (res, _) = deliver("200 OK", "FAILED,11,No RockBLOCK with this IMEI found on your account").await;
+        assert!(matches!(res, Ok(super::DeliveryStatus::InvalidIMEI)));
+
+        let (res, _) = deliver("200 OK", "FAILED,15,Data too long").await;
+        assert!(matches!(res, Ok(super::DeliveryStatus::PayloadSizeExceeded)));
+
+        let (res, _) = deliver("200 OK", "FAILED,16,Something new").await;
+        assert!(matches!(res, Ok(super::DeliveryStatus::Rejected(_))));
+
+        let (res, _) = deliver("500 Internal Server Error", "").await;
+        assert!(matches!(res, Err(super::Error::Expected(_))));
+
+        let (res, _) = deliver("200 OK", "<html></html>").await;
+        assert!(matches!(res, Err(super::Error::Unexpected(_))));
+    }
+
+    #[test]
+    fn payload_size_per_provider() {
+        assert_eq!(super::max_payload_size_for(None), super::DIRECT_IP_MAX_PAYLOAD_SIZE);
+        assert_eq!(
+            super::max_payload_size_for(Some(crate::models::MTProviderKind::RockBlock)),
+            super::ROCKBLOCK_MAX_PAYLOAD_SIZE
+        );
+    }
+}
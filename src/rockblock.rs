@@ -0,0 +1,260 @@
+use base64::prelude::*;
+
+/// An MO message as pushed by RockBLOCK style resellers
+#[derive(rocket::FromForm)]
+pub struct MOMessage {
+    imei: String,
+    device_type: Option<String>,
+    serial: Option<String>,
+    momsn: u16,
+    transmit_time: String,
+    // Kept as sent, so that they can be compared against the signed claims
+    iridium_latitude: Option<String>,
+    iridium_longitude: Option<String>,
+    iridium_cep: Option<String>,
+    data: Option<String>,
+    #[field(name = "JWT")]
+    jwt: String,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    InvalidSignature,
+    InvalidMessage(String),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::InvalidSignature => f.write_str("invalid signature"),
+            Self::InvalidMessage(e) => f.write_fmt(format_args!("invalid message: {}", e)),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub fn load_public_key(path: &std::path::Path) -> Result<openssl::pkey::PKey<openssl::pkey::Public>, String> {
+    let pem = std::fs::read(path)
+        .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    openssl::pkey::PKey::public_key_from_pem(&pem)
+        .map_err(|e| format!("failed to parse {}: {}", path.display(), e))
+}
+
+fn claim_matches(claims: &serde_json::Value, name: &str, value: Option<&str>) -> bool {
+    match (claims.get(name), value) {
+        (Some(serde_json::Value::String(s)), value) => s == value.unwrap_or_default(),
+        // Numbers may be formatted differently in the claims and the form
+        (Some(serde_json::Value::Number(n)), Some(value)) => value.parse::<f64>().ok() == n.as_f64(),
+        (None | Some(serde_json::Value::Null), value) => value.unwrap_or_default().is_empty(),
+        _ => false
+    }
+}
+
+fn parse_number(value: Option<&str>, name: &str) -> Result<Option<f32>, Error> {
+    match value {
+        Some(v) if !v.is_empty() => v.parse().map(Some)
+            .map_err(|e| Error::InvalidMessage(format!("invalid {}: {}", name, e))),
+        _ => Ok(None)
+    }
+}
+
+impl MOMessage {
+    /// Checks the JWT attached to the message was signed by the provider, and that every field of
+    /// the message matches its claims
+    pub fn verify(&self, public_key: &openssl::pkey::PKeyRef<openssl::pkey::Public>) -> Result<(), Error> {
+        let parts = self.jwt.split('.').collect::<Vec<_>>();
+        if parts.len() != 3 {
+            return Err(Error::InvalidSignature);
+        }
+
+        let header: serde_json::Value = BASE64_URL_SAFE_NO_PAD.decode(parts[0]).ok()
+            .and_then(|h| serde_json::from_slice(&h).ok())
+            .ok_or(Error::InvalidSignature)?;
+        if header.get("alg").and_then(|a| a.as_str()) != Some("RS256") {
+            return Err(Error::InvalidSignature);
+        }
+
+        let signature = BASE64_URL_SAFE_NO_PAD.decode(parts[2])
+            .map_err(|_| Error::InvalidSignature)?;
+        let mut verifier = openssl::sign::Verifier::new(openssl::hash::MessageDigest::sha256(), public_key)
+            .map_err(|_| Error::InvalidSignature)?;
+        verifier.update(parts[0].as_bytes()).map_err(|_| Error::InvalidSignature)?;
+        verifier.update(b".").map_err(|_| Error::InvalidSignature)?;
+        verifier.update(parts[1].as_bytes()).map_err(|_| Error::InvalidSignature)?;
+        if !verifier.verify(&signature).unwrap_or(false) {
+            return Err(Error::InvalidSignature);
+        }
+
+        let claims: serde_json::Value = BASE64_URL_SAFE_NO_PAD.decode(parts[1]).ok()
+            .and_then(|c| serde_json::from_slice(&c).ok())
+            .ok_or(Error::InvalidSignature)?;
+        let momsn = self.momsn.to_string();
+        let fields = [
+            ("imei", Some(self.imei.as_str())),
+            ("device_type", self.device_type.as_deref()),
+            ("serial", self.serial.as_deref()),
+            ("momsn", Some(momsn.as_str())),
+            ("transmit_time", Some(self.transmit_time.as_str())),
+            ("iridium_latitude", self.iridium_latitude.as_deref()),
+            ("iridium_longitude", self.iridium_longitude.as_deref()),
+            ("iridium_cep", self.iridium_cep.as_deref()),
+            ("data", self.data.as_deref()),
+        ];
+        if !fields.iter().all(|(name, value)| claim_matches(&claims, name, *value)) {
+            return Err(Error::InvalidSignature);
+        }
+
+        Ok(())
+    }
+
+    pub fn into_model(self) -> Result<crate::models::MOMessage, Error> {
+        if self.imei.len() != 15 || !self.imei.chars().all(|c| c.is_ascii_digit()) {
+            return Err(Error::InvalidMessage("invalid IMEI".to_string()));
+        }
+
+        let time_of_session = chrono::NaiveDateTime::parse_from_str(&self.transmit_time, "%y-%m-%d %H:%M:%S")
+            .map_err(|e| Error::InvalidMessage(format!("invalid transmit time: {}", e)))?;
+
+        let data = match self.data {
+            Some(d) if !d.is_empty() => Some(
+                hex::decode(d).map_err(|e| Error::InvalidMessage(format!("invalid data: {}", e)))?
+            ),
+            _ => None
+        };
+
+        let (latitude, longitude, cep_radius) = match (
+            parse_number(self.iridium_latitude.as_deref(), "latitude")?,
+            parse_number(self.iridium_longitude.as_deref(), "longitude")?,
+            parse_number(self.iridium_cep.as_deref(), "CEP radius")?,
+        ) {
+            (Some(latitude), Some(longitude), Some(cep)) => (Some(latitude), Some(longitude), Some(cep.round() as i32)),
+            _ => (None, None, None)
+        };
+
+        Ok(crate::models::MOMessage {
+            id: uuid::Uuid::new_v4(),
+            // Not provided by the reseller
+            cdr_reference: 0,
+            imei: self.imei,
+            session_status: crate::models::SessionStatus::Successful,
+            mo_msn: self.momsn as i16,
+            mt_msn: 0,
+            time_of_session,
+            latitude,
+            longitude,
+            cep_radius,
+            data,
+            processing_status: crate::models::ProcessingStatus::Received,
+            received: chrono::Utc::now().naive_utc(),
+            source: crate::models::MOSource::RockBlock,
+            requeued: None,
+            target: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::prelude::*;
+
+    fn key() -> openssl::pkey::PKey<openssl::pkey::Private> {
+        openssl::pkey::PKey::from_rsa(openssl::rsa::Rsa::generate(2048).unwrap()).unwrap()
+    }
+
+    fn public(key: &openssl::pkey::PKeyRef<openssl::pkey::Private>) -> openssl::pkey::PKey<openssl::pkey::Public> {
+        openssl::pkey::PKey::public_key_from_pem(&key.public_key_to_pem().unwrap()).unwrap()
+    }
+
+    fn sign(key: &openssl::pkey::PKeyRef<openssl::pkey::Private>, alg: &str, claims: &serde_json::Value) -> String {
+        let header = BASE64_URL_SAFE_NO_PAD.encode(serde_json::json!({"alg": alg, "typ": "JWT"}).to_string());
+        let claims = BASE64_URL_SAFE_NO_PAD.encode(claims.to_string());
+        let mut signer = openssl::sign::Signer::new(openssl::hash::MessageDigest::sha256(), key).unwrap();
+        signer.update(format!("{}.{}", header, claims).as_bytes()).unwrap();
+        format!("{}.{}.{}", header, claims, BASE64_URL_SAFE_NO_PAD.encode(signer.sign_to_vec().unwrap()))
+    }
+
+    fn claims() -> serde_json::Value {
+        serde_json::json!({
+            "imei": "300234010753370",
+            "device_type": "ROCKBLOCK",
+            "serial": 12345,
+            "momsn": 12,
+            "transmit_time": "21-10-31 10:41:50",
+            "iridium_latitude": 52.3867,
+            "iridium_longitude": 0.2938,
+            "iridium_cep": 9.0,
+            "data": "48656c6c6f",
+            "iat": 1635676910,
+        })
+    }
+
+    fn message(jwt: String) -> super::MOMessage {
+        super::MOMessage {
+            imei: "300234010753370".to_string(),
+            device_type: Some("ROCKBLOCK".to_string()),
+            serial: Some("12345".to_string()),
+            momsn: 12,
+            transmit_time: "21-10-31 10:41:50".to_string(),
+            iridium_latitude: Some("52.3867".to_string()),
+            iridium_longitude: Some("0.2938".to_string()),
+            iridium_cep: Some("9".to_string()),
+            data: Some("48656c6c6f".to_string()),
+            jwt,
+        }
+    }
+
+    #[test]
+    fn verify_valid() {
+        let key = key();
+        let message = message(sign(&key, "RS256", &claims()));
+        assert!(message.verify(&public(&key)).is_ok());
+
+        let model = message.into_model().unwrap();
+        assert_eq!(model.imei, "300234010753370");
+        assert_eq!(model.mo_msn, 12);
+        assert_eq!(model.time_of_session.to_string(), "2021-10-31 10:41:50");
+        assert_eq!(model.latitude, Some(52.3867));
+        assert_eq!(model.longitude, Some(0.2938));
+        assert_eq!(model.cep_radius, Some(9));
+        assert_eq!(model.data.as_deref(), Some(&b"Hello"[..]));
+    }
+
+    #[test]
+    fn verify_rejects_changed_fields() {
+        let key = key();
+        let jwt = sign(&key, "RS256", &claims());
+
+        let mut changed = message(jwt.clone());
+        changed.iridium_latitude = Some("51.5".to_string());
+        assert!(changed.verify(&public(&key)).is_err());
+
+        let mut changed = message(jwt.clone());
+        changed.momsn = 13;
+        assert!(changed.verify(&public(&key)).is_err());
+
+        let mut changed = message(jwt.clone());
+        changed.serial = None;
+        assert!(changed.verify(&public(&key)).is_err());
+
+        let mut changed = message(jwt);
+        changed.data = Some("00".to_string());
+        assert!(changed.verify(&public(&key)).is_err());
+    }
+
+    #[test]
+    fn verify_rejects_unsigned_fields() {
+        let key = key();
+        let mut claims = claims();
+        claims.as_object_mut().unwrap().remove("iridium_cep");
+        assert!(message(sign(&key, "RS256", &claims)).verify(&public(&key)).is_err());
+    }
+
+    #[test]
+    fn verify_rejects_bad_signatures() {
+        let key = key();
+        assert!(message(sign(&key, "HS256", &claims())).verify(&public(&key)).is_err());
+        assert!(message(sign(&super::tests::key(), "RS256", &claims())).verify(&public(&key)).is_err());
+        assert!(message("not a jwt".to_string()).verify(&public(&key)).is_err());
+    }
+}
@@ -0,0 +1,277 @@
+use base64::prelude::*;
+use diesel::{BoolExpressionMethods, ExpressionMethods, NullableExpressionMethods, QueryDsl};
+use diesel_async::{AsyncConnection, RunQueryDsl};
+use diesel_async::scoped_futures::ScopedFutureExt;
+
+/// How often to check for scheduled messages that have fallen due
+const INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+/// Maximum number of messages to dispatch in one pass
+const BATCH_SIZE: i64 = 100;
+
+/// Dispatches scheduled MT messages once due, and generates messages from recurring schedules.
+/// Each message or schedule run is claimed by a single worker's scheduler.
+pub(crate) async fn run(db_pool: crate::DBPool, celery_app: std::sync::Arc<celery::Celery>) {
+    loop {
+        // A full batch means there may be more waiting
+        let mut more = false;
+
+        match dispatch_due(&db_pool, &celery_app).await {
+            Ok(n) => more |= n as i64 == BATCH_SIZE,
+            Err(err) => {
+                error!("Failed to dispatch scheduled MT messages: {}", err);
+            }
+        }
+
+        if let Err(err) = requeue_stale(&db_pool, &celery_app).await {
+            error!("Failed to requeue stale MT messages: {}", err);
+        }
+
+        match run_schedules(&db_pool, &celery_app).await {
+            Ok(n) => more |= n as i64 == BATCH_SIZE,
+            Err(err) => {
+                error!("Failed to run MT schedules: {}", err);
+            }
+        }
+
+        if let Err(err) = complete_batches(&db_pool, &celery_app).await {
+            error!("Failed to check MT batch completion: {}", err);
+        }
+
+        if let Err(err) = prune_event_deliveries(&db_pool).await {
+            error!("Failed to prune event deliveries: {}", err);
+        }
+
+        if !more {
+            tokio::time::sleep(INTERVAL).await;
+        }
+    }
+}
+
+async fn dispatch_due(db_pool: &crate::DBPool, celery_app: &celery::Celery) -> Result<usize, String> {
+    let mut db_conn = db_pool.get().await.map_err(|e| e.to_string())?;
+
+    // Messages without a time set, such as those in batches, are dispatched on the next pass
+    let due = crate::schema::mt_messages::dsl::mt_messages
+        .filter(crate::schema::mt_messages::dsl::processing_status.eq(crate::models::ProcessingStatus::Scheduled))
+        .filter(
+            crate::schema::mt_messages::dsl::not_before.le(chrono::Utc::now().naive_utc())
+                .or(crate::schema::mt_messages::dsl::not_before.is_null())
+        )
+        .select(crate::schema::mt_messages::dsl::id)
+        .limit(BATCH_SIZE)
+        .load::<uuid::Uuid>(&mut db_conn).await
+        .map_err(|e| e.to_string())?;
+
+    let mut dispatched = 0;
+    for message_id in due {
+        // The claim is only committed once the task has been sent, so a crash in between leaves
+        // the message scheduled, to be picked up again on the next pass
+        let result = db_conn.transaction::<_, diesel::result::Error, _>(|conn| async move {
+            // Another scheduler may have claimed it in the meantime
+            let claimed = diesel::update(crate::schema::mt_messages::dsl::mt_messages)
+                .filter(crate::schema::mt_messages::dsl::id.eq(message_id))
+                .filter(crate::schema::mt_messages::dsl::processing_status.eq(crate::models::ProcessingStatus::Scheduled))
+                .set(crate::schema::mt_messages::dsl::processing_status.eq(crate::models::ProcessingStatus::Received))
+                .execute(conn).await?;
+            if claimed == 0 {
+                return Ok(false);
+            }
+
+            info!("Dispatching scheduled MT message {}", message_id);
+
+            if let Err(err) = celery_app.send_task(crate::worker::deliver_mt::new(message_id)).await {
+                error!("Failed to send task: {}", err);
+                return Err(diesel::result::Error::RollbackTransaction);
+            }
+            Ok(true)
+        }.scope_boxed()).await;
+
+        match result {
+            Ok(true) => dispatched += 1,
+            Ok(false) | Err(diesel::result::Error::RollbackTransaction) => {}
+            Err(err) => return Err(err.to_string()),
+        }
+    }
+
+    Ok(dispatched)
+}
+
+/// Queues delivery again for messages left being sent by a worker that stopped part way through
+async fn requeue_stale(db_pool: &crate::DBPool, celery_app: &celery::Celery) -> Result<(), String> {
+    let mut db_conn = db_pool.get().await.map_err(|e| e.to_string())?;
+
+    let stale = crate::schema::mt_messages::dsl::mt_messages
+        .filter(crate::schema::mt_messages::dsl::processing_status.eq(crate::models::ProcessingStatus::Sending))
+        .filter(crate::schema::mt_messages::dsl::last_attempt.lt(
+            chrono::Utc::now().naive_utc() - chrono::Duration::seconds(crate::worker::MT_CLAIM_TIMEOUT)
+        ))
+        .select(crate::schema::mt_messages::dsl::id)
+        .limit(BATCH_SIZE)
+        .load::<uuid::Uuid>(&mut db_conn).await
+        .map_err(|e| e.to_string())?;
+
+    for message_id in stale {
+        warn!("MT message {} was left being sent, queueing it again", message_id);
+
+        if let Err(err) = celery_app.send_task(crate::worker::deliver_mt::new(message_id)).await {
+            error!("Failed to send task: {}", err);
+        }
+    }
+
+    Ok(())
+}
+
+async fn run_schedules(db_pool: &crate::DBPool, celery_app: &celery::Celery) -> Result<usize, String> {
+    let mut db_conn = db_pool.get().await.map_err(|e| e.to_string())?;
+    let now = chrono::Utc::now();
+
+    let due = crate::schema::mt_schedules::dsl::mt_schedules
+        .filter(crate::schema::mt_schedules::dsl::paused.eq(false))
+        .filter(crate::schema::mt_schedules::dsl::next_run.le(now.naive_utc()))
+        .limit(BATCH_SIZE)
+        .load::<crate::models::MTSchedule>(&mut db_conn).await
+        .map_err(|e| e.to_string())?;
+
+    for schedule in &due {
+        let run_time = match schedule.next_run {
+            Some(t) => t,
+            None => continue
+        };
+
+        // Runs missed while no worker was running aren't caught up on, beyond this one
+        let next_run = crate::schedules::next_run(&schedule.cron, now);
+
+        // Only the scheduler that moves next_run on gets to generate this run
+        let claimed = diesel::update(crate::schema::mt_schedules::dsl::mt_schedules)
+            .filter(crate::schema::mt_schedules::dsl::id.eq(schedule.id))
+            .filter(crate::schema::mt_schedules::dsl::next_run.eq(run_time))
+            .filter(crate::schema::mt_schedules::dsl::paused.eq(false))
+            .set(crate::schema::mt_schedules::dsl::next_run.eq(next_run))
+            .execute(&mut db_conn).await
+            .map_err(|e| e.to_string())?;
+        if claimed == 0 {
+            continue;
+        }
+
+        let run = crate::models::MTScheduleRun {
+            id: uuid::Uuid::new_v4(),
+            schedule: schedule.id,
+            run_time,
+            message: None,
+            error: None,
+            batch: None,
+        };
+        let inserted = diesel::insert_into(crate::schema::mt_schedule_runs::dsl::mt_schedule_runs)
+            .values(&run)
+            .on_conflict_do_nothing()
+            .execute(&mut db_conn).await
+            .map_err(|e| e.to_string())?;
+        if inserted == 0 {
+            warn!("MT schedule {} already ran at {}", schedule.id, run_time);
+            continue;
+        }
+
+        let target = crate::schema::targets::dsl::targets
+            .filter(crate::schema::targets::dsl::id.eq(schedule.target))
+            .get_result::<crate::models::Target>(&mut db_conn).await
+            .map_err(|e| e.to_string())?;
+
+        let payload = BASE64_STANDARD.encode(&schedule.data);
+        let priority = if schedule.priority == 0 { None } else { Some(schedule.priority as u8) };
+
+        // Schedules for a group send a batch to whichever devices are in the group at the time
+        let (message, batch, error) = match (&schedule.imei, schedule.device_group) {
+            (Some(imei), _) => match crate::mt::submit_mt(&mut db_conn, celery_app, &target, crate::types::MTMessage {
+                imei: imei.clone(),
+                payload,
+                priority,
+                client_reference: schedule.client_reference.clone(),
+                metadata: schedule.metadata.clone(),
+                not_before: None,
+                expires_at: None,
+            }, None).await {
+                Ok(id) => {
+                    info!("MT schedule {} generated message {}", schedule.id, id);
+                    (Some(id), None, None)
+                }
+                Err(err) => {
+                    warn!("MT schedule {} failed to generate message: {:?}", schedule.id, err);
+                    (None, None, Some(format!("{:?}", err)))
+                }
+            },
+            (None, group) => match crate::batches::create(&mut db_conn, &target, crate::types::NewMTBatch {
+                imeis: Vec::new(),
+                group,
+                messages: Vec::new(),
+                payload: Some(payload),
+                priority,
+                client_reference: schedule.client_reference.clone(),
+                metadata: schedule.metadata.clone(),
+                not_before: None,
+                expires_at: None,
+                notify: false,
+            }, None).await {
+                Ok(b) => {
+                    info!("MT schedule {} generated batch {}", schedule.id, b.id);
+                    (None, Some(b.id), None)
+                }
+                Err(err) => {
+                    warn!("MT schedule {} failed to generate batch: {:?}", schedule.id, err);
+                    (None, None, Some(format!("{:?}", err)))
+                }
+            }
+        };
+
+        diesel::update(crate::schema::mt_schedule_runs::dsl::mt_schedule_runs)
+            .filter(crate::schema::mt_schedule_runs::dsl::id.eq(run.id))
+            .set((
+                crate::schema::mt_schedule_runs::dsl::message.eq(message),
+                crate::schema::mt_schedule_runs::dsl::batch.eq(batch),
+                crate::schema::mt_schedule_runs::dsl::error.eq(error),
+            ))
+            .execute(&mut db_conn).await
+            .map_err(|e| e.to_string())?;
+    }
+
+    Ok(due.len())
+}
+
+/// Marks batches as complete once none of their messages are waiting to be delivered, and sends
+/// their completion webhooks
+async fn complete_batches(db_pool: &crate::DBPool, celery_app: &celery::Celery) -> Result<(), String> {
+    let mut db_conn = db_pool.get().await.map_err(|e| e.to_string())?;
+
+    // Marking the batch complete in the same query means only one scheduler sends the webhook
+    let completed = diesel::update(crate::schema::mt_batches::dsl::mt_batches)
+        .filter(crate::schema::mt_batches::dsl::completed.is_null())
+        .filter(diesel::dsl::not(diesel::dsl::exists(
+            crate::schema::mt_messages::dsl::mt_messages
+                .filter(crate::schema::mt_messages::dsl::batch.eq(crate::schema::mt_batches::dsl::id.nullable()))
+                .filter(crate::schema::mt_messages::dsl::processing_status.eq_any([
+                    crate::models::ProcessingStatus::Received, crate::models::ProcessingStatus::Scheduled,
+                    crate::models::ProcessingStatus::Sending,
+                ]))
+        )))
+        .set(crate::schema::mt_batches::dsl::completed.eq(chrono::Utc::now().naive_utc()))
+        .returning((crate::schema::mt_batches::dsl::id, crate::schema::mt_batches::dsl::notify))
+        .get_results::<(uuid::Uuid, bool)>(&mut db_conn).await
+        .map_err(|e| e.to_string())?;
+
+    for (batch_id, notify) in completed {
+        info!("MT batch {} complete", batch_id);
+        if notify {
+            if let Err(err) = celery_app.send_task(crate::worker::send_batch_complete::new(batch_id)).await {
+                error!("Failed to send batch completion task: {}", err);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn prune_event_deliveries(db_pool: &crate::DBPool) -> Result<(), String> {
+    let mut db_conn = db_pool.get().await.map_err(|e| e.to_string())?;
+    crate::events::prune_deliveries(&mut db_conn).await
+        .map_err(|e| e.to_string())?;
+    Ok(())
+}
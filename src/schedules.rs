@@ -0,0 +1,209 @@
+use base64::prelude::*;
+use diesel::{BoolExpressionMethods, ExpressionMethods, OptionalExtension, QueryDsl};
+use diesel_async::RunQueryDsl;
+use std::str::FromStr;
+
+#[derive(Debug)]
+pub enum ScheduleError {
+    NotFound,
+    InvalidCron,
+    /// Neither or both of a device and a group were given
+    InvalidDestination,
+    UnknownGroup,
+    Invalid(crate::mt::SubmitError),
+    Internal,
+}
+
+impl From<diesel::result::Error> for ScheduleError {
+    fn from(err: diesel::result::Error) -> Self {
+        error!("Failed to access MT schedules: {}", err);
+        ScheduleError::Internal
+    }
+}
+
+/// Parses a standard five field cron expression; the cron crate also expects seconds, which are
+/// always zero here
+fn parse_cron(expr: &str) -> Option<cron::Schedule> {
+    if expr.split_whitespace().count() != 5 {
+        return None;
+    }
+    cron::Schedule::from_str(&format!("0 {}", expr)).ok()
+}
+
+/// The first time a schedule should run strictly after a given time
+pub(crate) fn next_run(expr: &str, after: chrono::DateTime<chrono::Utc>) -> Option<chrono::NaiveDateTime> {
+    parse_cron(expr)?.after(&after).next().map(|t| t.naive_utc())
+}
+
+pub(crate) fn schedule_info(schedule: crate::models::MTSchedule) -> crate::types::MTSchedule {
+    crate::types::MTSchedule {
+        id: schedule.id,
+        imei: schedule.imei,
+        group: schedule.device_group,
+        cron: schedule.cron,
+        payload: BASE64_STANDARD.encode(schedule.data),
+        priority: if schedule.priority == 0 { None } else { Some(schedule.priority as u8) },
+        client_reference: schedule.client_reference,
+        metadata: schedule.metadata,
+        paused: schedule.paused,
+        next_run: schedule.next_run.map(|t| t.and_utc()),
+        created: schedule.created.and_utc(),
+    }
+}
+
+pub(crate) async fn create(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target, request: crate::types::NewMTSchedule,
+) -> Result<crate::models::MTSchedule, ScheduleError> {
+    let now = chrono::Utc::now();
+    let next_run = next_run(&request.cron, now).ok_or(ScheduleError::InvalidCron)?;
+
+    let (destination, max_size) = match (&request.imei, request.group) {
+        (Some(imei), None) => {
+            crate::mt::check_destination(db_conn, target, imei).await.map_err(ScheduleError::Invalid)?;
+            (format!("IMEI {}", imei), crate::provider::max_payload_size(imei, &target.id, db_conn).await?)
+        }
+        (None, Some(group)) => {
+            match crate::devices::get_group(db_conn, target, group).await {
+                Ok(_) => {}
+                Err(crate::devices::DeviceError::NotFound) => return Err(ScheduleError::UnknownGroup),
+                Err(_) => return Err(ScheduleError::Internal),
+            }
+            // Each run is sent to the group's devices at the time, so this is only a first check
+            (format!("device group {}", group), crate::provider::max_group_payload_size(group, db_conn).await?)
+        }
+        _ => return Err(ScheduleError::InvalidDestination)
+    };
+    let data = crate::mt::decode_payload(&request.payload, max_size).map_err(ScheduleError::Invalid)?;
+    let priority = crate::mt::parse_priority(request.priority).map_err(ScheduleError::Invalid)?;
+    crate::mt::check_reference(request.client_reference.as_deref(), request.metadata.as_ref())
+        .map_err(ScheduleError::Invalid)?;
+
+    let schedule = crate::models::MTSchedule {
+        id: uuid::Uuid::new_v4(),
+        target: target.id,
+        imei: request.imei,
+        device_group: request.group,
+        cron: request.cron,
+        data,
+        priority,
+        client_reference: request.client_reference,
+        metadata: request.metadata,
+        paused: false,
+        next_run: Some(next_run),
+        created: now.naive_utc(),
+    };
+
+    diesel::insert_into(crate::schema::mt_schedules::dsl::mt_schedules)
+        .values(&schedule)
+        .execute(db_conn).await?;
+
+    crate::audit::record(
+        db_conn, Some(target.id), "mt_schedule_created",
+        format!("MT schedule {} created for {}", schedule.id, destination)
+    ).await;
+
+    Ok(schedule)
+}
+
+pub(crate) async fn get(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target, schedule_id: uuid::Uuid,
+) -> Result<crate::models::MTSchedule, ScheduleError> {
+    crate::schema::mt_schedules::dsl::mt_schedules
+        .filter(crate::schema::mt_schedules::dsl::id.eq(schedule_id))
+        .filter(crate::schema::mt_schedules::dsl::target.eq(&target.id))
+        .get_result::<crate::models::MTSchedule>(db_conn).await
+        .optional()?
+        .ok_or(ScheduleError::NotFound)
+}
+
+pub(crate) async fn list(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target,
+) -> Result<Vec<crate::models::MTSchedule>, ScheduleError> {
+    Ok(crate::schema::mt_schedules::dsl::mt_schedules
+        .filter(crate::schema::mt_schedules::dsl::target.eq(&target.id))
+        .order_by(crate::schema::mt_schedules::dsl::created.asc())
+        .load::<crate::models::MTSchedule>(db_conn).await?)
+}
+
+/// Pauses or resumes a schedule. Resumed schedules run next at their first time after now, rather
+/// than catching up on runs missed while paused.
+pub(crate) async fn set_paused(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target, schedule_id: uuid::Uuid, paused: bool,
+) -> Result<crate::models::MTSchedule, ScheduleError> {
+    let schedule = get(db_conn, target, schedule_id).await?;
+
+    let next_run = if paused {
+        None
+    } else {
+        next_run(&schedule.cron, chrono::Utc::now())
+    };
+
+    let schedule = diesel::update(crate::schema::mt_schedules::dsl::mt_schedules)
+        .filter(crate::schema::mt_schedules::dsl::id.eq(schedule.id))
+        .set((
+            crate::schema::mt_schedules::dsl::paused.eq(paused),
+            crate::schema::mt_schedules::dsl::next_run.eq(next_run),
+        ))
+        .get_result::<crate::models::MTSchedule>(db_conn).await?;
+
+    crate::audit::record(
+        db_conn, Some(target.id), if paused { "mt_schedule_paused" } else { "mt_schedule_resumed" },
+        format!("MT schedule {} {}", schedule.id, if paused { "paused" } else { "resumed" })
+    ).await;
+
+    Ok(schedule)
+}
+
+pub(crate) async fn delete(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target, schedule_id: uuid::Uuid,
+) -> Result<(), ScheduleError> {
+    let deleted = diesel::delete(crate::schema::mt_schedules::dsl::mt_schedules)
+        .filter(crate::schema::mt_schedules::dsl::id.eq(schedule_id))
+        .filter(crate::schema::mt_schedules::dsl::target.eq(&target.id))
+        .execute(db_conn).await?;
+    if deleted == 0 {
+        return Err(ScheduleError::NotFound);
+    }
+
+    crate::audit::record(db_conn, Some(target.id), "mt_schedule_deleted", format!("MT schedule {} deleted", schedule_id)).await;
+
+    Ok(())
+}
+
+/// Lists the runs of a schedule, newest first, returning the cursor for the next page if there is one
+pub(crate) async fn runs(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target, schedule_id: uuid::Uuid,
+    cursor: Option<crate::mt::Cursor>, limit: i64,
+) -> Result<(Vec<crate::models::MTScheduleRun>, Option<crate::mt::Cursor>), ScheduleError> {
+    let schedule = get(db_conn, target, schedule_id).await?;
+
+    let mut query = crate::schema::mt_schedule_runs::dsl::mt_schedule_runs
+        .filter(crate::schema::mt_schedule_runs::dsl::schedule.eq(schedule.id))
+        .into_boxed();
+
+    if let Some(cursor) = cursor {
+        query = query.filter(
+            crate::schema::mt_schedule_runs::dsl::run_time.lt(cursor.time).or(
+                crate::schema::mt_schedule_runs::dsl::run_time.eq(cursor.time)
+                    .and(crate::schema::mt_schedule_runs::dsl::id.lt(cursor.id))
+            )
+        );
+    }
+
+    let mut runs = query
+        .order_by((crate::schema::mt_schedule_runs::dsl::run_time.desc(), crate::schema::mt_schedule_runs::dsl::id.desc()))
+        .limit(limit + 1)
+        .load::<crate::models::MTScheduleRun>(db_conn).await?;
+
+    let next_cursor = if runs.len() as i64 > limit {
+        runs.truncate(limit as usize);
+        runs.last().map(|r| crate::mt::Cursor {
+            time: r.run_time,
+            id: r.id,
+        })
+    } else {
+        None
+    };
+
+    Ok((runs, next_cursor))
+}
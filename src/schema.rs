@@ -1,10 +1,26 @@
 // @generated automatically by Diesel CLI.
 
 pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "cloud_events_mode"))]
+    pub struct CloudEventsMode;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "delivery_channel"))]
+    pub struct DeliveryChannel;
+
     #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "message_status"))]
     pub struct MessageStatus;
 
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "mo_source"))]
+    pub struct MoSource;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "mt_provider_kind"))]
+    pub struct MtProviderKind;
+
     #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "processing_status"))]
     pub struct ProcessingStatus;
@@ -12,6 +28,45 @@ pub mod sql_types {
     #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
     #[diesel(postgres_type(name = "session_status"))]
     pub struct SessionStatus;
+
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "webhook_signing"))]
+    pub struct WebhookSigning;
+}
+
+diesel::table! {
+    audit_log (id) {
+        id -> Uuid,
+        time -> Timestamp,
+        target -> Nullable<Uuid>,
+        event -> Varchar,
+        detail -> Varchar,
+        message -> Nullable<Uuid>,
+    }
+}
+
+diesel::table! {
+    device_group_members (device_group, device) {
+        device_group -> Uuid,
+        device -> Uuid,
+    }
+}
+
+diesel::table! {
+    device_groups (id) {
+        id -> Uuid,
+        target -> Uuid,
+        name -> Varchar,
+        created -> Timestamp,
+    }
+}
+
+diesel::table! {
+    device_tags (device, key) {
+        device -> Uuid,
+        key -> Varchar,
+        value -> Varchar,
+    }
 }
 
 diesel::table! {
@@ -20,6 +75,30 @@ diesel::table! {
         #[max_length = 15]
         imei -> Bpchar,
         target -> Uuid,
+        mt_provider -> Nullable<Uuid>,
+        metadata -> Nullable<Jsonb>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::DeliveryChannel;
+
+    event_deliveries (event, channel) {
+        event -> Uuid,
+        channel -> DeliveryChannel,
+        delivered -> Timestamp,
+    }
+}
+
+diesel::table! {
+    idempotency_keys (target, key) {
+        target -> Uuid,
+        key -> Varchar,
+        request_hash -> Bytea,
+        message -> Nullable<Uuid>,
+        created -> Timestamp,
+        batch -> Nullable<Uuid>,
     }
 }
 
@@ -27,6 +106,7 @@ diesel::table! {
     use diesel::sql_types::*;
     use super::sql_types::SessionStatus;
     use super::sql_types::ProcessingStatus;
+    use super::sql_types::MoSource;
 
     mo_messages (id) {
         id -> Uuid,
@@ -43,6 +123,21 @@ diesel::table! {
         data -> Nullable<Bytea>,
         processing_status -> ProcessingStatus,
         received -> Timestamp,
+        source -> MoSource,
+        requeued -> Nullable<Timestamp>,
+        target -> Nullable<Uuid>,
+    }
+}
+
+diesel::table! {
+    mt_batches (id) {
+        id -> Uuid,
+        target -> Uuid,
+        client_reference -> Nullable<Varchar>,
+        notify -> Bool,
+        created -> Timestamp,
+        completed -> Nullable<Timestamp>,
+        device_group -> Nullable<Uuid>,
     }
 }
 
@@ -61,23 +156,160 @@ diesel::table! {
         processing_status -> ProcessingStatus,
         received -> Timestamp,
         target -> Uuid,
+        idempotency_key -> Nullable<Varchar>,
+        client_reference -> Nullable<Varchar>,
+        metadata -> Nullable<Jsonb>,
+        attempts -> Int4,
+        last_attempt -> Nullable<Timestamp>,
+        completed -> Nullable<Timestamp>,
+        not_before -> Nullable<Timestamp>,
+        expires_at -> Nullable<Timestamp>,
+        batch -> Nullable<Uuid>,
+        requeued -> Nullable<Timestamp>,
     }
 }
 
 diesel::table! {
-    targets (id) {
+    mt_permissions (id) {
         id -> Uuid,
-        hmac_key -> Bytea,
+        target -> Uuid,
+        #[max_length = 15]
+        imei -> Bpchar,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::MtProviderKind;
+
+    mt_providers (id) {
+        id -> Uuid,
+        kind -> MtProviderKind,
         endpoint -> Varchar,
+        username -> Varchar,
+        password -> Varchar,
+    }
+}
+
+diesel::table! {
+    mt_schedule_runs (id) {
+        id -> Uuid,
+        schedule -> Uuid,
+        run_time -> Timestamp,
+        message -> Nullable<Uuid>,
+        error -> Nullable<Varchar>,
+        batch -> Nullable<Uuid>,
+    }
+}
+
+diesel::table! {
+    mt_schedules (id) {
+        id -> Uuid,
+        target -> Uuid,
+        #[max_length = 15]
+        imei -> Nullable<Bpchar>,
+        cron -> Varchar,
+        data -> Bytea,
+        priority -> Int2,
+        client_reference -> Nullable<Varchar>,
+        metadata -> Nullable<Jsonb>,
+        paused -> Bool,
+        next_run -> Nullable<Timestamp>,
+        created -> Timestamp,
+        device_group -> Nullable<Uuid>,
+    }
+}
+
+diesel::table! {
+    request_nonces (target, nonce) {
+        target -> Uuid,
+        nonce -> Varchar,
+        seen -> Timestamp,
+    }
+}
+
+diesel::table! {
+    signing_keys (id) {
+        id -> Uuid,
+        private_key -> Bytea,
+        public_key -> Bytea,
+        created -> Timestamp,
+        activates -> Timestamp,
+        expires -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    target_keys (id) {
+        id -> Uuid,
+        target -> Uuid,
+        key -> Bytea,
+        created -> Timestamp,
+        expires -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::WebhookSigning;
+    use super::sql_types::CloudEventsMode;
+
+    targets (id) {
+        id -> Uuid,
+        endpoint -> Nullable<Varchar>,
+        mqtt_broker -> Nullable<Varchar>,
+        mqtt_username -> Nullable<Varchar>,
+        mqtt_password -> Nullable<Varchar>,
+        email_address -> Nullable<Varchar>,
+        mt_signature_version -> Int2,
+        webhook_signing -> WebhookSigning,
+        webhook_version -> Int2,
+        cloud_events -> Nullable<CloudEventsMode>,
+        email_token -> Nullable<Varchar>,
     }
 }
 
+diesel::joinable!(audit_log -> targets (target));
+diesel::joinable!(device_group_members -> device_groups (device_group));
+diesel::joinable!(device_group_members -> devices (device));
+diesel::joinable!(device_groups -> targets (target));
+diesel::joinable!(device_tags -> devices (device));
+diesel::joinable!(devices -> mt_providers (mt_provider));
+diesel::joinable!(idempotency_keys -> mt_batches (batch));
+diesel::joinable!(idempotency_keys -> mt_messages (message));
+diesel::joinable!(idempotency_keys -> targets (target));
 diesel::joinable!(devices -> targets (target));
+diesel::joinable!(mo_messages -> targets (target));
+diesel::joinable!(mt_batches -> device_groups (device_group));
+diesel::joinable!(mt_batches -> targets (target));
+diesel::joinable!(mt_messages -> mt_batches (batch));
 diesel::joinable!(mt_messages -> targets (target));
+diesel::joinable!(mt_permissions -> targets (target));
+diesel::joinable!(mt_schedule_runs -> mt_batches (batch));
+diesel::joinable!(mt_schedule_runs -> mt_messages (message));
+diesel::joinable!(mt_schedule_runs -> mt_schedules (schedule));
+diesel::joinable!(mt_schedules -> device_groups (device_group));
+diesel::joinable!(mt_schedules -> targets (target));
+diesel::joinable!(request_nonces -> targets (target));
+diesel::joinable!(target_keys -> targets (target));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    audit_log,
+    device_group_members,
+    device_groups,
+    device_tags,
     devices,
+    event_deliveries,
+    idempotency_keys,
     mo_messages,
+    mt_batches,
     mt_messages,
+    mt_permissions,
+    mt_providers,
+    mt_schedule_runs,
+    mt_schedules,
+    request_nonces,
+    signing_keys,
+    target_keys,
     targets,
 );
@@ -0,0 +1,260 @@
+use base64::prelude::*;
+use hmac::Mac;
+
+/// How far the timestamp on a version 2 request may be from the current time, in seconds
+pub const MAX_REQUEST_SKEW: i64 = 300;
+
+/// Version 1 request signature, covering only the request body
+pub fn request_mac_v1(key: &[u8], body: &[u8]) -> Vec<u8> {
+    let mut mac = crate::HmacSha256::new_from_slice(key).unwrap();
+    mac.update(body);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Version 2 request signature, covering the method, path, timestamp, and nonce of the request
+/// as well as its body
+pub fn request_mac_v2(key: &[u8], method: &str, path: &str, timestamp: i64, nonce: &str, body: &[u8]) -> Vec<u8> {
+    let mut mac = crate::HmacSha256::new_from_slice(key).unwrap();
+    mac.update(format!("{}\n{}\n{}\n{}\n", method.to_ascii_uppercase(), path, timestamp, nonce).as_bytes());
+    mac.update(body);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Signature over a webhook body, bound to the time it was sent
+pub fn webhook_mac(key: &[u8], timestamp: i64, body: &[u8]) -> Vec<u8> {
+    let mut mac = crate::HmacSha256::new_from_slice(key).unwrap();
+    mac.update(format!("{}.", timestamp).as_bytes());
+    mac.update(body);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Value of the `Kosmos-Signature` header for a webhook body
+pub fn webhook_signature_header(key_id: &uuid::Uuid, key: &[u8], timestamp: i64, body: &[u8]) -> String {
+    format!("t={},k={},v1={}", timestamp, key_id, BASE64_STANDARD.encode(webhook_mac(key, timestamp, body)))
+}
+
+/// A parsed `Kosmos-Signature` header
+#[derive(Debug)]
+pub struct SignatureHeader {
+    pub timestamp: i64,
+    pub key_id: uuid::Uuid,
+    pub v1: Option<Vec<u8>>,
+    pub ed25519: Option<Vec<u8>>,
+}
+
+pub fn parse_signature_header(value: &str) -> Option<SignatureHeader> {
+    let mut timestamp = None;
+    let mut key_id = None;
+    let mut v1 = None;
+    let mut ed25519 = None;
+
+    for part in value.split(',') {
+        let (name, value) = part.trim().split_once('=')?;
+        match name {
+            "t" => timestamp = Some(value.parse::<i64>().ok()?),
+            "k" => key_id = Some(uuid::Uuid::parse_str(value).ok()?),
+            "v1" => v1 = Some(BASE64_STANDARD.decode(value).ok()?),
+            "ed25519" => ed25519 = Some(BASE64_STANDARD.decode(value).ok()?),
+            // Unknown schemes are ignored, so that new ones can be added alongside
+            _ => {}
+        }
+    }
+
+    Some(SignatureHeader {
+        timestamp: timestamp?,
+        key_id: key_id?,
+        v1,
+        ed25519,
+    })
+}
+
+/// Checks the HMAC in a `Kosmos-Signature` header against a webhook body. The timestamp isn't
+/// checked against the current time.
+pub fn verify_webhook_signature(key: &[u8], header: &SignatureHeader, body: &[u8]) -> bool {
+    match &header.v1 {
+        Some(mac) => constant_time_eq::constant_time_eq(&webhook_mac(key, header.timestamp, body), mac),
+        None => false
+    }
+}
+
+/// Checks a Base64 encoded `Kosmos-MAC` header against a webhook body
+pub fn verify_webhook_mac(key: &[u8], body: &[u8], mac: &str) -> bool {
+    match BASE64_STANDARD.decode(mac.trim()) {
+        Ok(mac) => constant_time_eq::constant_time_eq(&request_mac_v1(key, body), &mac),
+        Err(_) => false
+    }
+}
+
+/// What `Kosmos-Signature` covers: the body, after any `ce-` headers as sorted, lowercase
+/// `name:value` lines and an empty line
+pub fn webhook_signed_content<'a>(headers: impl IntoIterator<Item = (&'a str, &'a str)>, body: &[u8]) -> Vec<u8> {
+    let mut attributes = headers.into_iter()
+        .map(|(name, value)| (name.to_ascii_lowercase(), value))
+        .filter(|(name, _)| name.starts_with("ce-"))
+        .collect::<Vec<_>>();
+    if attributes.is_empty() {
+        return body.to_vec();
+    }
+    attributes.sort_by(|a, b| a.0.cmp(&b.0));
+
+    let mut content = Vec::new();
+    for (name, value) in attributes {
+        content.extend_from_slice(format!("{}:{}\n", name, value.trim()).as_bytes());
+    }
+    content.push(b'\n');
+    content.extend_from_slice(body);
+    content
+}
+
+/// Ed25519 signature over a webhook body, bound to the time it was sent
+pub fn webhook_ed25519_signature(private_key: &[u8], timestamp: i64, body: &[u8]) -> Result<Vec<u8>, openssl::error::ErrorStack> {
+    let pkey = openssl::pkey::PKey::private_key_from_raw_bytes(private_key, openssl::pkey::Id::ED25519)?;
+    let mut signer = openssl::sign::Signer::new_without_digest(&pkey)?;
+    let mut data = format!("{}.", timestamp).into_bytes();
+    data.extend_from_slice(body);
+    signer.sign_oneshot_to_vec(&data)
+}
+
+/// Checks the Ed25519 signature in a `Kosmos-Signature` header against a webhook body, using the
+/// raw public key of the key it names. The timestamp isn't checked against the current time.
+pub fn verify_webhook_ed25519_signature(public_key: &[u8], header: &SignatureHeader, body: &[u8]) -> bool {
+    let signature = match &header.ed25519 {
+        Some(s) => s,
+        None => return false
+    };
+    let pkey = match openssl::pkey::PKey::public_key_from_raw_bytes(public_key, openssl::pkey::Id::ED25519) {
+        Ok(k) => k,
+        Err(_) => return false
+    };
+    let mut data = format!("{}.", header.timestamp).into_bytes();
+    data.extend_from_slice(body);
+    openssl::sign::Verifier::new_without_digest(&pkey)
+        .and_then(|mut verifier| verifier.verify_oneshot(signature, &data))
+        .unwrap_or(false)
+}
+
+/// Value of the `Kosmos-Signature` header for a webhook body signed with a Kosmos held key
+pub fn webhook_ed25519_signature_header(
+    key_id: &uuid::Uuid, private_key: &[u8], timestamp: i64, body: &[u8],
+) -> Result<String, openssl::error::ErrorStack> {
+    Ok(format!(
+        "t={},k={},ed25519={}", timestamp, key_id,
+        BASE64_STANDARD.encode(webhook_ed25519_signature(private_key, timestamp, body)?)
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use base64::prelude::*;
+
+    fn ed25519_key() -> (Vec<u8>, Vec<u8>) {
+        let pkey = openssl::pkey::PKey::generate_ed25519().unwrap();
+        (pkey.raw_private_key().unwrap(), pkey.raw_public_key().unwrap())
+    }
+
+    #[test]
+    fn ed25519_signature_valid() {
+        let (private_key, public_key) = ed25519_key();
+        let key_id = uuid::Uuid::new_v4();
+        let value = super::webhook_ed25519_signature_header(&key_id, &private_key, 1700000000, b"{}").unwrap();
+        let header = super::parse_signature_header(&value).unwrap();
+        assert_eq!(header.key_id, key_id);
+        assert_eq!(header.timestamp, 1700000000);
+        assert!(header.v1.is_none());
+        assert!(super::verify_webhook_ed25519_signature(&public_key, &header, b"{}"));
+    }
+
+    #[test]
+    fn ed25519_signature_rejects_changes() {
+        let (private_key, public_key) = ed25519_key();
+        let (_, other_public_key) = ed25519_key();
+        let value = super::webhook_ed25519_signature_header(
+            &uuid::Uuid::new_v4(), &private_key, 1700000000, b"{}"
+        ).unwrap();
+        let mut header = super::parse_signature_header(&value).unwrap();
+        assert!(!super::verify_webhook_ed25519_signature(&public_key, &header, b"{ }"));
+        assert!(!super::verify_webhook_ed25519_signature(&other_public_key, &header, b"{}"));
+        assert!(!super::verify_webhook_ed25519_signature(&[0; 3], &header, b"{}"));
+        header.timestamp += 1;
+        assert!(!super::verify_webhook_ed25519_signature(&public_key, &header, b"{}"));
+        header.ed25519 = None;
+        assert!(!super::verify_webhook_ed25519_signature(&public_key, &header, b"{}"));
+    }
+
+    #[test]
+    fn request_mac_v1_is_hmac_sha256() {
+        // RFC 4231 test case 2
+        assert_eq!(
+            hex::encode(super::request_mac_v1(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn request_mac_v2_covers_request() {
+        let mac = super::request_mac_v2(b"key", "post", "/mt/1?flush=true", 1700000000, "abc", b"{}");
+        assert_eq!(mac, super::request_mac_v1(b"key", b"POST\n/mt/1?flush=true\n1700000000\nabc\n{}"));
+        assert_ne!(mac, super::request_mac_v2(b"key", "DELETE", "/mt/1?flush=true", 1700000000, "abc", b"{}"));
+        assert_ne!(mac, super::request_mac_v2(b"key", "POST", "/mt/2?flush=true", 1700000000, "abc", b"{}"));
+        assert_ne!(mac, super::request_mac_v2(b"key", "POST", "/mt/1?flush=true", 1700000001, "abc", b"{}"));
+        assert_ne!(mac, super::request_mac_v2(b"key", "POST", "/mt/1?flush=true", 1700000000, "abd", b"{}"));
+    }
+
+    #[test]
+    fn webhook_signature_valid() {
+        let key_id = uuid::Uuid::new_v4();
+        let value = super::webhook_signature_header(&key_id, b"key", 1700000000, b"{}");
+        let header = super::parse_signature_header(&value).unwrap();
+        assert_eq!(header.key_id, key_id);
+        assert_eq!(header.timestamp, 1700000000);
+        assert!(header.ed25519.is_none());
+        assert!(super::verify_webhook_signature(b"key", &header, b"{}"));
+    }
+
+    #[test]
+    fn webhook_signature_rejects_changes() {
+        let value = super::webhook_signature_header(&uuid::Uuid::new_v4(), b"key", 1700000000, b"{}");
+        let mut header = super::parse_signature_header(&value).unwrap();
+        assert!(!super::verify_webhook_signature(b"other key", &header, b"{}"));
+        assert!(!super::verify_webhook_signature(b"key", &header, b"{ }"));
+        header.timestamp += 1;
+        assert!(!super::verify_webhook_signature(b"key", &header, b"{}"));
+        header.v1 = None;
+        assert!(!super::verify_webhook_signature(b"key", &header, b"{}"));
+    }
+
+    #[test]
+    fn parse_signature_header() {
+        let key_id = uuid::Uuid::new_v4();
+        let header = super::parse_signature_header(&format!("t=1700000000, k={}, v1=AAEC, v9=ignored", key_id)).unwrap();
+        assert_eq!(header.v1, Some(vec![0, 1, 2]));
+
+        assert!(super::parse_signature_header(&format!("k={},v1=AAEC", key_id)).is_none());
+        assert!(super::parse_signature_header("t=1700000000,v1=AAEC").is_none());
+        assert!(super::parse_signature_header(&format!("t=now,k={},v1=AAEC", key_id)).is_none());
+        assert!(super::parse_signature_header(&format!("t=1700000000,k={},v1=not base64!", key_id)).is_none());
+        assert!(super::parse_signature_header(&format!("t=1700000000,k={},v1", key_id)).is_none());
+    }
+
+    #[test]
+    fn verify_webhook_mac() {
+        let mac = BASE64_STANDARD.encode(super::request_mac_v1(b"key", b"{}"));
+        assert!(super::verify_webhook_mac(b"key", b"{}", &mac));
+        assert!(super::verify_webhook_mac(b"key", b"{}", &format!(" {}\n", mac)));
+        assert!(!super::verify_webhook_mac(b"other key", b"{}", &mac));
+        assert!(!super::verify_webhook_mac(b"key", b"{ }", &mac));
+        assert!(!super::verify_webhook_mac(b"key", b"{}", "not base64!"));
+    }
+
+    #[test]
+    fn webhook_signed_content() {
+        assert_eq!(super::webhook_signed_content([("Content-Type", "application/json")], b"{}"), b"{}");
+        assert_eq!(
+            super::webhook_signed_content([
+                ("ce-type", "kosmos.mo_message"), ("Content-Type", "application/json"),
+                ("CE-ID", " 1 "), ("ce-specversion", "1.0"),
+            ], b"{}"),
+            b"ce-id:1\nce-specversion:1.0\nce-type:kosmos.mo_message\n\n{}"
+        );
+    }
+}
\ No newline at end of file
@@ -1,23 +1,90 @@
 use chrono::{DateTime, Utc};
 
-#[derive(serde::Serialize)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 #[serde(tag = "type")]
 pub enum WebhookMessage {
     #[serde(rename = "mo_message")]
     MOMessage(MOMessage),
     #[serde(rename = "mt_message_status")]
     MTMessageStatus(MTMessageStatus),
+    #[serde(rename = "mt_batch_complete")]
+    MTBatchComplete(MTBatchStatus),
 }
 
-#[derive(serde::Serialize)]
+/// Version 2 of the webhook format, wrapping the same events as [`WebhookMessage`] in an envelope
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub struct WebhookEnvelope {
+    /// Unique to the event, and the same on every delivery attempt of it
+    pub event_id: uuid::Uuid,
+    pub event_time: DateTime<Utc>,
+    pub schema_version: u8,
+    /// The device the event relates to, absent for batch events
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub device: Option<EventDevice>,
+    #[serde(flatten)]
+    pub event: WebhookEvent,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+#[serde(tag = "type", content = "data")]
+pub enum WebhookEvent {
+    #[serde(rename = "mo_message")]
+    MOMessage(MOMessage),
+    #[serde(rename = "mt_message_status")]
+    MTMessageStatus(MTMessageStatus),
+    #[serde(rename = "mt_batch_complete")]
+    MTBatchComplete(MTBatchStatus),
+}
+
+impl From<WebhookMessage> for WebhookEvent {
+    fn from(value: WebhookMessage) -> Self {
+        match value {
+            WebhookMessage::MOMessage(m) => Self::MOMessage(m),
+            WebhookMessage::MTMessageStatus(m) => Self::MTMessageStatus(m),
+            WebhookMessage::MTBatchComplete(m) => Self::MTBatchComplete(m),
+        }
+    }
+}
+
+/// A webhook delivered as a structured mode [CloudEvents 1.0](https://cloudevents.io) event
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub struct CloudEvent<D = WebhookMessage> {
+    pub specversion: String,
+    /// Unique to the event, and the same on every delivery attempt of it
+    pub id: uuid::Uuid,
+    /// `/devices/<imei>` or `/batches/<id>`
+    pub source: String,
+    /// `kosmos.mo_message`, `kosmos.mt_message_status` or `kosmos.mt_batch_complete`
+    #[serde(rename = "type")]
+    pub event_type: String,
+    /// The ID of the MO or MT message, absent for batch events
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    pub time: DateTime<Utc>,
+    pub datacontenttype: String,
+    pub data: D,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub struct EventDevice {
+    pub imei: String,
+    pub metadata: Option<serde_json::Value>,
+    #[serde(default)]
+    pub tags: std::collections::BTreeMap<String, String>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 pub struct MOMessage {
     pub id: uuid::Uuid,
     pub header: MOHeader,
     pub location_information: Option<MOLocationInformation>,
+    /// Base64 encoded payload
     pub payload: Option<String>,
+    #[serde(default)]
+    pub tags: std::collections::BTreeMap<String, String>,
 }
 
-#[derive(serde::Serialize)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 pub struct MOHeader {
     pub imei: String,
     pub cdr_reference: u32,
@@ -27,14 +94,14 @@ pub struct MOHeader {
     pub time_of_session: DateTime<Utc>
 }
 
-#[derive(serde::Serialize)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 pub struct MOLocationInformation {
     pub latitude: f32,
     pub longitude: f32,
     pub cep_radius: u32,
 }
 
-#[derive(serde::Serialize)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 pub enum SessionStatus {
     #[serde(rename = "normal")]
     Normal,
@@ -42,15 +109,139 @@ pub enum SessionStatus {
     TooLarge,
     #[serde(rename = "unacceptable_location")]
     UnacceptableLocation,
+    // Unsuccessful sessions are only sent in version 2 webhooks, but are visible in the message history
+    #[serde(rename = "timeout")]
+    Timeout,
+    #[serde(rename = "mo_too_large")]
+    MOTooLarge,
+    #[serde(rename = "rf_link_lost")]
+    RFLinkLost,
+    #[serde(rename = "protocol_anomaly")]
+    ProtocolAnomaly,
+    #[serde(rename = "imei_blocked")]
+    IMEIBlocked,
 }
 
-#[derive(serde::Serialize)]
+impl From<crate::models::SessionStatus> for SessionStatus {
+    fn from(value: crate::models::SessionStatus) -> Self {
+        match value {
+            crate::models::SessionStatus::Successful => Self::Normal,
+            crate::models::SessionStatus::SuccessfulTooLarge => Self::TooLarge,
+            crate::models::SessionStatus::SuccessfulUnacceptableLocation => Self::UnacceptableLocation,
+            crate::models::SessionStatus::Timeout => Self::Timeout,
+            crate::models::SessionStatus::TooLarge => Self::MOTooLarge,
+            crate::models::SessionStatus::RfLinkLost => Self::RFLinkLost,
+            crate::models::SessionStatus::ProtocolAnomaly => Self::ProtocolAnomaly,
+            crate::models::SessionStatus::ImeiBlocked => Self::IMEIBlocked,
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub struct MOMessageInfo {
+    pub id: uuid::Uuid,
+    pub header: MOHeader,
+    pub location_information: Option<MOLocationInformation>,
+    pub payload_size: Option<u32>,
+    pub processing_status: ProcessingStatus,
+    pub source: MOSource,
+    pub received: DateTime<Utc>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub struct MOMessageList {
+    pub messages: Vec<MOMessageInfo>,
+    /// Pass as `cursor` to get the next page, absent on the last page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub enum MOSource {
+    #[serde(rename = "direct_ip")]
+    DirectIP,
+    #[serde(rename = "rockblock")]
+    RockBLOCK,
+}
+
+impl From<crate::models::MOSource> for MOSource {
+    fn from(value: crate::models::MOSource) -> Self {
+        match value {
+            crate::models::MOSource::DirectIp => Self::DirectIP,
+            crate::models::MOSource::RockBlock => Self::RockBLOCK,
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 pub struct MTMessageStatus {
     pub id: uuid::Uuid,
-    pub status: MessageStatus
+    pub status: MessageStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub idempotency_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_reference: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub batch: Option<uuid::Uuid>,
 }
 
-#[derive(serde::Serialize)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub struct MTMessageInfo {
+    pub id: uuid::Uuid,
+    pub imei: String,
+    pub priority: Option<u8>,
+    pub processing_status: ProcessingStatus,
+    pub message_status: Option<MessageStatus>,
+    pub received: DateTime<Utc>,
+    pub last_attempt: Option<DateTime<Utc>>,
+    pub completed: Option<DateTime<Utc>>,
+    pub not_before: Option<DateTime<Utc>>,
+    pub expires_at: Option<DateTime<Utc>>,
+    pub attempts: u32,
+    pub idempotency_key: Option<String>,
+    pub client_reference: Option<String>,
+    pub metadata: Option<serde_json::Value>,
+    pub batch: Option<uuid::Uuid>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub struct MTMessageList {
+    pub messages: Vec<MTMessageInfo>,
+    /// Pass as `cursor` to get the next page, absent on the last page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub enum ProcessingStatus {
+    #[serde(rename = "received")]
+    Received,
+    #[serde(rename = "done")]
+    Done,
+    #[serde(rename = "failed")]
+    Failed,
+    #[serde(rename = "scheduled")]
+    Scheduled,
+    /// Being sent to the gateway; MT messages only
+    #[serde(rename = "sending")]
+    Sending,
+}
+
+impl From<crate::models::ProcessingStatus> for ProcessingStatus {
+    fn from(value: crate::models::ProcessingStatus) -> Self {
+        match value {
+            crate::models::ProcessingStatus::Received => Self::Received,
+            crate::models::ProcessingStatus::Done => Self::Done,
+            crate::models::ProcessingStatus::Failed => Self::Failed,
+            crate::models::ProcessingStatus::Scheduled => Self::Scheduled,
+            crate::models::ProcessingStatus::Sending => Self::Sending,
+        }
+    }
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 pub enum MessageStatus {
     #[serde(rename = "delivered")]
     Delivered,
@@ -62,12 +253,511 @@ pub enum MessageStatus {
     MessageQueueFull,
     #[serde(rename = "resources_unavailable")]
     ResourcesUnavailable,
+    #[serde(rename = "cancelled")]
+    Cancelled,
+    #[serde(rename = "expired")]
+    Expired,
+    #[serde(rename = "rejected")]
+    Rejected,
+}
+
+impl From<crate::models::MessageStatus> for MessageStatus {
+    fn from(value: crate::models::MessageStatus) -> Self {
+        match value {
+            crate::models::MessageStatus::Delivered => Self::Delivered,
+            crate::models::MessageStatus::InvalidImei => Self::InvalidIMEI,
+            crate::models::MessageStatus::PayloadSizeExceeded => Self::PayloadSizeExceeded,
+            crate::models::MessageStatus::MessageQueueFull => Self::MessageQueueFull,
+            crate::models::MessageStatus::ResourcesUnavailable => Self::ResourcesUnavailable,
+            crate::models::MessageStatus::Cancelled => Self::Cancelled,
+            crate::models::MessageStatus::Expired => Self::Expired,
+            crate::models::MessageStatus::Rejected => Self::Rejected,
+        }
+    }
 }
 
-#[derive(serde::Deserialize)]
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
 pub struct MTMessage {
     pub imei: String,
+    /// Base64 encoded payload
+    pub payload: String,
+    /// Between 1 and 5
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub priority: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub client_reference: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub not_before: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct MQTTMTMessage {
+    pub payload: String,
+    #[serde(default)]
+    pub priority: Option<u8>,
+    #[serde(default)]
+    pub client_reference: Option<String>,
+    #[serde(default)]
+    pub metadata: Option<serde_json::Value>,
+    #[serde(default)]
+    pub not_before: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+/// An error from the API, as an RFC 9457 problem details object
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub struct Problem {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    /// Stable identifier of the error, safe to match on
+    pub code: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
+    /// The body field, query parameter, or header the error relates to
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub field: Option<String>,
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct RotateKeys {
+    #[serde(default)]
+    pub grace_period: Option<u32>,
+}
+
+#[derive(serde::Serialize, schemars::JsonSchema)]
+pub struct TargetKey {
+    pub id: uuid::Uuid,
+    pub created: DateTime<Utc>,
+    pub expires: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize, schemars::JsonSchema)]
+pub struct NewTargetKey {
+    pub id: uuid::Uuid,
+    pub key: String,
+    pub created: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub struct JWKSet {
+    pub keys: Vec<JWK>,
+}
+
+#[derive(serde::Serialize, schemars::JsonSchema)]
+pub struct SigningKey {
+    pub id: uuid::Uuid,
+    pub created: DateTime<Utc>,
+    /// When the key starts being used to sign webhooks
+    pub activates: DateTime<Utc>,
+    pub expires: Option<DateTime<Utc>>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub struct JWK {
+    pub kty: String,
+    pub crv: String,
+    pub x: String,
+    pub kid: uuid::Uuid,
+    #[serde(rename = "use")]
+    pub key_use: String,
+    pub alg: String,
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct NewMTSchedule {
+    /// Either a device or a group to send to
+    #[serde(default)]
+    pub imei: Option<String>,
+    #[serde(default)]
+    pub group: Option<uuid::Uuid>,
+    /// Standard five field cron expression, evaluated in UTC
+    pub cron: String,
+    pub payload: String,
+    #[serde(default)]
+    pub priority: Option<u8>,
+    #[serde(default)]
+    pub client_reference: Option<String>,
+    #[serde(default)]
+    pub metadata: Option<serde_json::Value>,
+}
+
+#[derive(serde::Serialize, schemars::JsonSchema)]
+pub struct MTSchedule {
+    pub id: uuid::Uuid,
+    pub imei: Option<String>,
+    pub group: Option<uuid::Uuid>,
+    pub cron: String,
     pub payload: String,
+    pub priority: Option<u8>,
+    pub client_reference: Option<String>,
+    pub metadata: Option<serde_json::Value>,
+    pub paused: bool,
+    pub next_run: Option<DateTime<Utc>>,
+    pub created: DateTime<Utc>,
+}
+
+#[derive(serde::Serialize, schemars::JsonSchema)]
+pub struct MTScheduleRun {
+    pub run_time: DateTime<Utc>,
+    pub message: Option<uuid::Uuid>,
+    pub batch: Option<uuid::Uuid>,
+    pub error: Option<String>,
+}
+
+#[derive(serde::Serialize, schemars::JsonSchema)]
+pub struct MTScheduleRunList {
+    pub runs: Vec<MTScheduleRun>,
+    /// Pass as `cursor` to get the next page, absent on the last page
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub struct NewMTBatch {
+    /// Devices to send the same payload to
+    #[serde(default)]
+    pub imeis: Vec<String>,
+    /// Group whose devices are sent the same payload
+    #[serde(default)]
+    pub group: Option<uuid::Uuid>,
+    /// Devices with their own payloads
+    #[serde(default)]
+    pub messages: Vec<MTBatchMessage>,
+    /// Payload for devices without their own
+    #[serde(default)]
+    pub payload: Option<String>,
     #[serde(default)]
     pub priority: Option<u8>,
+    #[serde(default)]
+    pub client_reference: Option<String>,
+    #[serde(default)]
+    pub metadata: Option<serde_json::Value>,
+    #[serde(default)]
+    pub not_before: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub expires_at: Option<DateTime<Utc>>,
+    /// Send a webhook once every message in the batch is finished
+    #[serde(default)]
+    pub notify: bool,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub struct MTBatchMessage {
+    pub imei: String,
+    #[serde(default)]
+    pub payload: Option<String>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub struct MTBatchStatus {
+    pub id: uuid::Uuid,
+    pub client_reference: Option<String>,
+    pub group: Option<uuid::Uuid>,
+    pub created: DateTime<Utc>,
+    pub completed: Option<DateTime<Utc>>,
+    pub total: u32,
+    pub counts: Vec<MTBatchCount>,
+}
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, schemars::JsonSchema)]
+pub struct MTBatchCount {
+    pub processing_status: ProcessingStatus,
+    pub message_status: Option<MessageStatus>,
+    pub count: u32,
+}
+
+#[derive(serde::Serialize, schemars::JsonSchema)]
+pub struct Device {
+    pub imei: String,
+    pub tags: std::collections::BTreeMap<String, String>,
+    pub groups: Vec<uuid::Uuid>,
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct NewDeviceGroup {
+    pub name: String,
+}
+
+#[derive(serde::Serialize, schemars::JsonSchema)]
+pub struct DeviceGroup {
+    pub id: uuid::Uuid,
+    pub name: String,
+    pub created: DateTime<Utc>,
+    pub devices: Vec<String>,
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, schemars::JsonSchema)]
+pub enum WebhookSigning {
+    #[serde(rename = "hmac")]
+    Hmac,
+    #[serde(rename = "ed25519")]
+    Ed25519,
+}
+
+impl From<crate::models::WebhookSigning> for WebhookSigning {
+    fn from(value: crate::models::WebhookSigning) -> Self {
+        match value {
+            crate::models::WebhookSigning::Hmac => Self::Hmac,
+            crate::models::WebhookSigning::Ed25519 => Self::Ed25519,
+        }
+    }
+}
+
+impl From<WebhookSigning> for crate::models::WebhookSigning {
+    fn from(value: WebhookSigning) -> Self {
+        match value {
+            WebhookSigning::Hmac => Self::Hmac,
+            WebhookSigning::Ed25519 => Self::Ed25519,
+        }
+    }
+}
+
+#[derive(serde::Serialize, serde::Deserialize, Clone, Copy, schemars::JsonSchema)]
+pub enum CloudEventsMode {
+    #[serde(rename = "structured")]
+    Structured,
+    #[serde(rename = "binary")]
+    Binary,
+}
+
+impl From<crate::models::CloudEventsMode> for CloudEventsMode {
+    fn from(value: crate::models::CloudEventsMode) -> Self {
+        match value {
+            crate::models::CloudEventsMode::Structured => Self::Structured,
+            crate::models::CloudEventsMode::Binary => Self::Binary,
+        }
+    }
+}
+
+impl From<CloudEventsMode> for crate::models::CloudEventsMode {
+    fn from(value: CloudEventsMode) -> Self {
+        match value {
+            CloudEventsMode::Structured => Self::Structured,
+            CloudEventsMode::Binary => Self::Binary,
+        }
+    }
+}
+
+/// A target's configuration, as given to the admin API. Updates replace the whole configuration.
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct TargetConfig {
+    #[serde(default)]
+    pub endpoint: Option<String>,
+    #[serde(default)]
+    pub mqtt_broker: Option<String>,
+    #[serde(default)]
+    pub mqtt_username: Option<String>,
+    /// Left out on update to keep the current password
+    #[serde(default)]
+    pub mqtt_password: Option<String>,
+    #[serde(default)]
+    pub email_address: Option<String>,
+    /// Secret that MT emails must be sent to, as `<anything>+<token>@<kosmos host>`; left out on
+    /// update to keep the current token
+    #[serde(default)]
+    pub email_token: Option<String>,
+    #[serde(default)]
+    pub mt_signature_version: Option<u8>,
+    #[serde(default)]
+    pub webhook_signing: Option<WebhookSigning>,
+    /// Format of webhooks and MQTT messages sent to the target, 1 or 2
+    #[serde(default)]
+    pub webhook_version: Option<u8>,
+    /// Deliver webhooks as CloudEvents, in structured or binary mode
+    #[serde(default)]
+    pub cloud_events: Option<CloudEventsMode>,
+}
+
+/// A target, as returned from the admin API. The MQTT password is never returned.
+#[derive(serde::Serialize, schemars::JsonSchema)]
+pub struct Target {
+    pub id: uuid::Uuid,
+    pub endpoint: Option<String>,
+    pub mqtt_broker: Option<String>,
+    pub mqtt_username: Option<String>,
+    pub email_address: Option<String>,
+    pub mt_signature_version: u8,
+    pub webhook_signing: WebhookSigning,
+    pub webhook_version: u8,
+    pub cloud_events: Option<CloudEventsMode>,
+}
+
+#[derive(serde::Serialize, schemars::JsonSchema)]
+pub struct NewTarget {
+    #[serde(flatten)]
+    pub target: Target,
+    pub key: NewTargetKey,
+}
+
+#[derive(serde::Deserialize, schemars::JsonSchema)]
+pub struct DeviceConfig {
+    pub imei: String,
+    pub target: uuid::Uuid,
+    #[serde(default)]
+    pub mt_provider: Option<uuid::Uuid>,
+    #[serde(default)]
+    pub metadata: Option<serde_json::Value>,
+}
+
+/// A device, as returned from the admin API
+#[derive(serde::Serialize, schemars::JsonSchema)]
+pub struct AdminDevice {
+    pub id: uuid::Uuid,
+    pub imei: String,
+    pub target: uuid::Uuid,
+    pub mt_provider: Option<uuid::Uuid>,
+    pub metadata: Option<serde_json::Value>,
+}
+
+/// Messages waiting to be processed, or that failed processing
+#[derive(serde::Serialize)]
+pub struct QueueDepths {
+    pub mo_received: i64,
+    pub mo_failed: i64,
+    pub mt_received: i64,
+    pub mt_scheduled: i64,
+    pub mt_sending: i64,
+    pub mt_failed: i64,
+}
+
+#[derive(serde::Serialize)]
+pub struct AuditLogEntry {
+    pub time: DateTime<Utc>,
+    pub target: Option<uuid::Uuid>,
+    pub event: String,
+    pub detail: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::TimeZone;
+
+    fn mo_message() -> super::WebhookMessage {
+        super::WebhookMessage::MOMessage(super::MOMessage {
+            id: uuid::uuid!("0b3b6a2e-6f5c-4a8e-9b1d-2c7e4f5a6b7c"),
+            header: super::MOHeader {
+                imei: "300234010753370".to_string(),
+                cdr_reference: 1234,
+                session_status: super::SessionStatus::Normal,
+                mo_msn: 12,
+                mt_msn: 3,
+                time_of_session: chrono::Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap(),
+            },
+            location_information: Some(super::MOLocationInformation {
+                latitude: 52.5,
+                longitude: -0.25,
+                cep_radius: 9,
+            }),
+            payload: Some("SGVsbG8=".to_string()),
+            tags: [("site".to_string(), "north".to_string())].into(),
+        })
+    }
+
+    fn mt_message_status() -> super::WebhookMessage {
+        super::WebhookMessage::MTMessageStatus(super::MTMessageStatus {
+            id: uuid::uuid!("5f6c2a2e-8c4a-4f4e-9d6b-3c1f0f8a7b21"),
+            status: super::MessageStatus::Delivered,
+            idempotency_key: None,
+            client_reference: Some("order-1234".to_string()),
+            metadata: None,
+            batch: None,
+        })
+    }
+
+    fn batch_complete() -> super::WebhookMessage {
+        super::WebhookMessage::MTBatchComplete(super::MTBatchStatus {
+            id: uuid::uuid!("9a8b7c6d-5e4f-4a3b-8c2d-1e0f9a8b7c6d"),
+            client_reference: None,
+            group: None,
+            created: chrono::Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap(),
+            completed: Some(chrono::Utc.with_ymd_and_hms(2026, 10, 18, 12, 5, 0).unwrap()),
+            total: 2,
+            counts: vec![super::MTBatchCount {
+                processing_status: super::ProcessingStatus::Done,
+                message_status: Some(super::MessageStatus::Delivered),
+                count: 2,
+            }],
+        })
+    }
+
+    // Version 1 webhooks must stay exactly as they were before versioned formats were added
+    #[test]
+    fn webhook_v1_unchanged() {
+        assert_eq!(
+            serde_json::to_string(&mo_message()).unwrap(),
+            concat!(
+                r#"{"type":"mo_message","id":"0b3b6a2e-6f5c-4a8e-9b1d-2c7e4f5a6b7c","#,
+                r#""header":{"imei":"300234010753370","cdr_reference":1234,"session_status":"normal","#,
+                r#""mo_msn":12,"mt_msn":3,"time_of_session":"2026-10-18T12:00:00Z"},"#,
+                r#""location_information":{"latitude":52.5,"longitude":-0.25,"cep_radius":9},"#,
+                r#""payload":"SGVsbG8=","tags":{"site":"north"}}"#,
+            )
+        );
+        assert_eq!(
+            serde_json::to_string(&mt_message_status()).unwrap(),
+            r#"{"type":"mt_message_status","id":"5f6c2a2e-8c4a-4f4e-9d6b-3c1f0f8a7b21","status":"delivered","client_reference":"order-1234"}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&batch_complete()).unwrap(),
+            concat!(
+                r#"{"type":"mt_batch_complete","id":"9a8b7c6d-5e4f-4a3b-8c2d-1e0f9a8b7c6d","client_reference":null,"#,
+                r#""group":null,"created":"2026-10-18T12:00:00Z","completed":"2026-10-18T12:05:00Z","total":2,"#,
+                r#""counts":[{"processing_status":"done","message_status":"delivered","count":2}]}"#,
+            )
+        );
+    }
+
+    #[test]
+    fn webhook_v2_envelope() {
+        let envelope = super::WebhookEnvelope {
+            event_id: uuid::uuid!("c1d2e3f4-a5b6-4c7d-8e9f-0a1b2c3d4e5f"),
+            event_time: chrono::Utc.with_ymd_and_hms(2026, 10, 18, 12, 0, 0).unwrap(),
+            schema_version: 2,
+            device: Some(super::EventDevice {
+                imei: "300234010753370".to_string(),
+                metadata: Some(serde_json::json!({"name": "buoy"})),
+                tags: [("site".to_string(), "north".to_string())].into(),
+            }),
+            event: mt_message_status().into(),
+        };
+        assert_eq!(serde_json::to_value(&envelope).unwrap(), serde_json::json!({
+            "event_id": "c1d2e3f4-a5b6-4c7d-8e9f-0a1b2c3d4e5f",
+            "event_time": "2026-10-18T12:00:00Z",
+            "schema_version": 2,
+            "device": {
+                "imei": "300234010753370",
+                "metadata": {"name": "buoy"},
+                "tags": {"site": "north"},
+            },
+            "type": "mt_message_status",
+            "data": {
+                "id": "5f6c2a2e-8c4a-4f4e-9d6b-3c1f0f8a7b21",
+                "status": "delivered",
+                "client_reference": "order-1234",
+            },
+        }));
+
+        // Batch events have no device
+        let envelope = super::WebhookEnvelope {
+            event_id: uuid::uuid!("c1d2e3f4-a5b6-4c7d-8e9f-0a1b2c3d4e5f"),
+            event_time: chrono::Utc.with_ymd_and_hms(2026, 10, 18, 12, 5, 0).unwrap(),
+            schema_version: 2,
+            device: None,
+            event: batch_complete().into(),
+        };
+        let value = serde_json::to_value(&envelope).unwrap();
+        assert!(value.get("device").is_none());
+        assert_eq!(value["type"], "mt_batch_complete");
+        assert_eq!(value["data"]["total"], 2);
+
+        let parsed = serde_json::from_value::<super::WebhookEnvelope>(value).unwrap();
+        assert!(matches!(parsed.event, super::WebhookEvent::MTBatchComplete(b) if b.total == 2));
+    }
 }
\ No newline at end of file
@@ -1,15 +1,18 @@
 use celery::prelude::*;
-use diesel::{ExpressionMethods, OptionalExtension, QueryDsl, SelectableHelper};
+use diesel::{BoolExpressionMethods, ExpressionMethods, NullableExpressionMethods, OptionalExtension, QueryDsl, SelectableHelper};
 use diesel_async::RunQueryDsl;
-use hmac::Mac;
 use base64::prelude::*;
 
 
 static HTTP_CLIENT: std::sync::OnceLock<reqwest::Client> = std::sync::OnceLock::new();
 static DB_POOL: std::sync::OnceLock<crate::DBPool> = std::sync::OnceLock::new();
 static CELERY_APP: std::sync::OnceLock<std::sync::Arc<celery::Celery>> = std::sync::OnceLock::new();
+static SMTP_TRANSPORT: std::sync::OnceLock<lettre::AsyncSmtpTransport<lettre::Tokio1Executor>> = std::sync::OnceLock::new();
+static EMAIL_FROM: std::sync::OnceLock<lettre::message::Mailbox> = std::sync::OnceLock::new();
 
-pub async fn run_worker(amqp_addr: String, db_pool: crate::DBPool) {
+pub async fn run_worker(
+    amqp_addr: String, db_pool: crate::DBPool, smtp_url: Option<String>, email_from: lettre::message::Mailbox,
+) {
     let client = reqwest::ClientBuilder::new()
         .user_agent(format!("Kosmos {}", env!("CARGO_PKG_VERSION")))
         .timeout(std::time::Duration::from_secs(30))
@@ -18,7 +21,7 @@ pub async fn run_worker(amqp_addr: String, db_pool: crate::DBPool) {
 
     let celery_app = match celery::app!(
         broker = AMQP { amqp_addr },
-        tasks = [process_message, deliver_mt],
+        tasks = [process_message, deliver_mt, send_mt_status, flush_mt_queue, send_batch_complete],
         task_routes = [],
         acks_late = false,
     ).await {
@@ -29,7 +32,32 @@ pub async fn run_worker(amqp_addr: String, db_pool: crate::DBPool) {
         }
     };
 
+    if let Some(smtp_url) = smtp_url {
+        let transport = match lettre::AsyncSmtpTransport::<lettre::Tokio1Executor>::from_url(&smtp_url) {
+            Ok(t) => t.build(),
+            Err(err) => {
+                error!("Failed to setup SMTP: {}", err);
+                return;
+            }
+        };
+        let _ = SMTP_TRANSPORT.set(transport);
+    }
+
+    match db_pool.get().await {
+        Ok(mut db_conn) => if let Err(err) = crate::jwks::ensure_key(&mut db_conn).await {
+            error!("Failed to setup webhook signing key: {}", err);
+            return;
+        },
+        Err(err) => {
+            error!("Failed to get DB connection: {}", err);
+            return;
+        }
+    }
+
+    tokio::spawn(crate::scheduler::run(db_pool.clone(), celery_app.clone()));
+
     let _ = HTTP_CLIENT.set(client);
+    let _ = EMAIL_FROM.set(email_from);
     let _ = DB_POOL.set(db_pool);
     let _ = CELERY_APP.set(celery_app.clone());
 
@@ -48,14 +76,59 @@ async fn set_mo_message_status(message_id: uuid::Uuid, status: crate::models::Pr
 }
 
 async fn set_mt_processing_status(message_id: uuid::Uuid, status: crate::models::ProcessingStatus, db_conn: &mut crate::DBConn) -> TaskResult<()> {
+    let completed = if matches!(status, crate::models::ProcessingStatus::Done | crate::models::ProcessingStatus::Failed) {
+        Some(chrono::Utc::now().naive_utc())
+    } else {
+        None
+    };
     diesel::update(crate::schema::mt_messages::dsl::mt_messages)
         .filter(crate::schema::mt_messages::dsl::id.eq(message_id))
-        .set(crate::schema::mt_messages::dsl::processing_status.eq(status))
+        .set((
+            crate::schema::mt_messages::dsl::processing_status.eq(status),
+            crate::schema::mt_messages::dsl::completed.eq(completed),
+        ))
         .execute(db_conn).await
         .with_expected_err(|| "Failed to update MT message processing status")?;
     Ok(())
 }
 
+/// How long a message can be left being sent before another worker may take it over, in case the
+/// worker sending it stopped part way through
+pub(crate) const MT_CLAIM_TIMEOUT: i64 = 5 * 60;
+
+/// Marks a message as being sent and records the delivery attempt, returning the message if it
+/// was waiting for delivery. Claiming it in a single update means a cancellation can't be lost.
+async fn claim_mt(message_id: uuid::Uuid, db_conn: &mut crate::DBConn) -> TaskResult<Option<crate::models::MTMessage>> {
+    let now = chrono::Utc::now().naive_utc();
+    diesel::update(crate::schema::mt_messages::dsl::mt_messages)
+        .filter(crate::schema::mt_messages::dsl::id.eq(message_id))
+        .filter(
+            crate::schema::mt_messages::dsl::processing_status.eq(crate::models::ProcessingStatus::Received)
+                .or(crate::schema::mt_messages::dsl::processing_status.eq(crate::models::ProcessingStatus::Sending)
+                    .and(crate::schema::mt_messages::dsl::last_attempt.lt(now - chrono::Duration::seconds(MT_CLAIM_TIMEOUT))))
+        )
+        .set((
+            crate::schema::mt_messages::dsl::processing_status.eq(crate::models::ProcessingStatus::Sending),
+            crate::schema::mt_messages::dsl::attempts.eq(crate::schema::mt_messages::dsl::attempts + 1),
+            crate::schema::mt_messages::dsl::last_attempt.eq(now),
+        ))
+        .get_result::<crate::models::MTMessage>(db_conn).await
+        .optional()
+        .with_expected_err(|| "Failed to claim MT message")
+}
+
+/// Returns a claimed message to waiting for delivery, so that it can be cancelled before it's
+/// tried again
+async fn release_mt(message_id: uuid::Uuid, db_conn: &mut crate::DBConn) -> TaskResult<()> {
+    diesel::update(crate::schema::mt_messages::dsl::mt_messages)
+        .filter(crate::schema::mt_messages::dsl::id.eq(message_id))
+        .filter(crate::schema::mt_messages::dsl::processing_status.eq(crate::models::ProcessingStatus::Sending))
+        .set(crate::schema::mt_messages::dsl::processing_status.eq(crate::models::ProcessingStatus::Received))
+        .execute(db_conn).await
+        .with_expected_err(|| "Failed to release MT message")?;
+    Ok(())
+}
+
 async fn set_mt_message_status(message_id: uuid::Uuid, status: crate::models::MessageStatus, db_conn: &mut crate::DBConn) -> TaskResult<()> {
     diesel::update(crate::schema::mt_messages::dsl::mt_messages)
         .filter(crate::schema::mt_messages::dsl::id.eq(message_id))
@@ -65,33 +138,102 @@ async fn set_mt_message_status(message_id: uuid::Uuid, status: crate::models::Me
     Ok(())
 }
 
-async fn send_webhook<D: serde::ser::Serialize>(target: &crate::models::Target, data: &D) -> bool {
-    let mut mac = crate::HmacSha256::new_from_slice(&target.hmac_key).unwrap();
-    let message_to_send_bytes = serde_json::to_vec(data).unwrap();
-    mac.update(&message_to_send_bytes);
-    let mac_result = mac.finalize().into_bytes();
+async fn send_webhook(key: &crate::keys::WebhookKey, endpoint: &str, webhook: crate::events::Webhook) -> bool {
+    let signature_headers = match key.signature_headers(&webhook.body, &webhook.signed_content()) {
+        Ok(h) => h,
+        Err(err) => {
+            error!("Failed to sign webhook to target {}: {}", endpoint, err);
+            return false;
+        }
+    };
+
+    let mut req = HTTP_CLIENT.get().unwrap().post(endpoint);
+    for (name, value) in signature_headers {
+        req = req.header(name, value);
+    }
+    for (name, value) in webhook.headers {
+        req = req.header(name, value);
+    }
 
-    let res = match HTTP_CLIENT.get().unwrap().post(&target.endpoint)
-        .header("Kosmos-MAC", BASE64_STANDARD.encode(mac_result))
-        .header("Content-Type", "application/json")
-        .body(message_to_send_bytes)
+    let res = match req
+        .header("Content-Type", webhook.content_type)
+        .body(webhook.body)
         .send().await {
         Ok(d) => d,
         Err(err) => {
-            warn!("Failed to send to target {}: {}", target.endpoint, err);
+            warn!("Failed to send to target {}: {}", endpoint, err);
 
             return false;
         }
     };
 
     if !res.status().is_success() {
-        warn!("Received error response from target {}", target.endpoint);
+        warn!("Received error response from target {}", endpoint);
+        false
+    } else {
+        true
+    }
+}
+
+async fn send_email(email: lettre::Message) -> bool {
+    use lettre::AsyncTransport;
+
+    let transport = match SMTP_TRANSPORT.get() {
+        Some(t) => t,
+        None => {
+            warn!("Email delivery requested, but SMTP isn't configured");
+            return false;
+        }
+    };
+
+    if let Err(err) = transport.send(email).await {
+        warn!("Failed to send email: {}", err);
         false
     } else {
         true
     }
 }
 
+/// Sends an event to a target's webhook, MQTT broker and email address, skipping channels it was
+/// already delivered over. Returns whether every channel has now been delivered to.
+async fn send_event(
+    db_conn: &mut crate::DBConn, target: &crate::models::Target, key: &crate::keys::WebhookKey, topic: &str,
+    event: crate::events::Event, email: Option<lettre::Message>,
+) -> TaskResult<bool> {
+    let event_id = event.id;
+    let delivered = crate::events::delivered_channels(db_conn, event_id).await
+        .with_expected_err(|| "Failed to get event deliveries")?;
+    let (webhook, body) = event.encode(target);
+
+    let mut results = vec![];
+    if let Some(endpoint) = &target.endpoint {
+        if !delivered.contains(&crate::models::DeliveryChannel::Webhook) {
+            results.push((crate::models::DeliveryChannel::Webhook, send_webhook(key, endpoint, webhook).await));
+        }
+    }
+    if target.mqtt_broker.is_some() && !delivered.contains(&crate::models::DeliveryChannel::Mqtt) {
+        let topic = format!("kosmos/{}", topic);
+        results.push((crate::models::DeliveryChannel::Mqtt, crate::mqtt::publish(target, &topic, body).await));
+    }
+    if let Some(email) = email {
+        if !delivered.contains(&crate::models::DeliveryChannel::Email) {
+            results.push((crate::models::DeliveryChannel::Email, send_email(email).await));
+        }
+    }
+
+    let mut all_delivered = true;
+    for (channel, sent) in results {
+        if sent {
+            crate::events::record_delivery(db_conn, event_id, channel).await
+                .with_expected_err(|| "Failed to record event delivery")?;
+        } else {
+            all_delivered = false;
+        }
+    }
+
+    Ok(all_delivered)
+}
+
 #[celery::task(bind = true)]
 pub async fn process_message(task: &Self, message_id: uuid::Uuid) -> TaskResult<()> {
     let mut db_conn = DB_POOL.get().unwrap().get().await
@@ -102,15 +244,11 @@ pub async fn process_message(task: &Self, message_id: uuid::Uuid) -> TaskResult<
     ).get_result::<crate::models::MOMessage>(&mut db_conn).await
         .with_expected_err(|| "Failed to get message from DB")?;
 
-    if !matches!(message.session_status, crate::models::SessionStatus::Successful |
-        crate::models::SessionStatus::SuccessfulTooLarge | crate::models::SessionStatus::SuccessfulUnacceptableLocation) {
-        set_mo_message_status(message_id, crate::models::ProcessingStatus::Done, &mut db_conn).await?;
-        return Ok(());
-    }
-
-    let (target, _) = match crate::schema::targets::dsl::targets
+    let (target, device) = match crate::schema::targets::dsl::targets
         .inner_join(crate::schema::devices::dsl::devices)
         .filter(crate::schema::devices::dsl::imei.eq(&message.imei))
+        // A device moved since the message was received doesn't send it to its new target
+        .filter(crate::schema::devices::dsl::target.nullable().eq(message.target))
         .select((crate::models::Target::as_select(), crate::models::Device::as_select()))
         .get_result::<(crate::models::Target, crate::models::Device)>(&mut db_conn).await.optional()
         .with_expected_err(|| "Failed to get target")? {
@@ -121,17 +259,34 @@ pub async fn process_message(task: &Self, message_id: uuid::Uuid) -> TaskResult<
         }
     };
 
+    // Unsuccessful sessions are only sent in version 2 webhooks, which can describe them
+    let successful = matches!(message.session_status, crate::models::SessionStatus::Successful |
+        crate::models::SessionStatus::SuccessfulTooLarge | crate::models::SessionStatus::SuccessfulUnacceptableLocation);
+    if !successful && target.webhook_version < 2 {
+        set_mo_message_status(message_id, crate::models::ProcessingStatus::Done, &mut db_conn).await?;
+        return Ok(());
+    }
+
+    let event_device = crate::events::device(&mut db_conn, device).await
+        .with_expected_err(|| "Failed to get device tags")?;
+
+    let email = match &target.email_address {
+        Some(address) if successful => match crate::email::mo_message_email(EMAIL_FROM.get().unwrap().clone(), address, &message) {
+            Ok(e) => Some(e),
+            Err(err) => {
+                warn!("Failed to format email for target {}: {}", target.id, err);
+                None
+            }
+        },
+        _ => None
+    };
+
     let message_to_send = crate::types::WebhookMessage::MOMessage(crate::types::MOMessage {
         id: message.id,
         header: crate::types::MOHeader {
-            imei: message.imei,
+            imei: message.imei.clone(),
             cdr_reference: message.cdr_reference as u32,
-            session_status: match message.session_status {
-                crate::models::SessionStatus::Successful => crate::types::SessionStatus::Normal,
-                crate::models::SessionStatus::SuccessfulTooLarge => crate::types::SessionStatus::TooLarge,
-                crate::models::SessionStatus::SuccessfulUnacceptableLocation => crate::types::SessionStatus::UnacceptableLocation,
-                _ => unreachable!()
-            },
+            session_status: message.session_status.into(),
             mo_msn: message.mo_msn as u16,
             mt_msn: message.mt_msn as u16,
             time_of_session: message.time_of_session.and_utc(),
@@ -145,11 +300,20 @@ pub async fn process_message(task: &Self, message_id: uuid::Uuid) -> TaskResult<
             _ => None
         },
         payload: message.data.map(|d| BASE64_STANDARD.encode(d)),
+        tags: event_device.tags.clone(),
     });
+    let event = crate::events::Event::new(
+        &format!("mo_message:{}", message.id), crate::events::device_source(&message.imei),
+        message.time_of_session.and_utc(), Some(event_device), message_to_send,
+    );
 
     let cutoff = chrono::Utc::now() - chrono::Duration::hours(24);
-    if !send_webhook(&target, &message_to_send).await {
-        return if cutoff > message.received.and_utc() {
+    let key = crate::keys::WebhookKey::for_target(&target, &mut db_conn).await
+        .with_expected_err(|| "Failed to get target signing key")?;
+
+    if !send_event(&mut db_conn, &target, &key, &format!("{}/mo", message.imei), event, email).await? {
+        // Delivery is retried for 24 hours from when the message was received, or last requeued
+        return if cutoff > message.requeued.unwrap_or(message.received).and_utc() {
             set_mo_message_status(message_id, crate::models::ProcessingStatus::Failed, &mut db_conn).await?;
             Ok(())
         } else {
@@ -167,100 +331,91 @@ pub async fn deliver_mt(task: &Self, message_id: uuid::Uuid) -> TaskResult<()> {
     let mut db_conn = DB_POOL.get().unwrap().get().await
         .with_expected_err(|| "Failed to get DB connection")?;
 
-    let message = crate::schema::mt_messages::dsl::mt_messages.filter(
-        crate::schema::mt_messages::dsl::id.eq(message_id)
-    ).get_result::<crate::models::MTMessage>(&mut db_conn).await
-        .with_expected_err(|| "Failed to get message from DB")?;
-
-    let mut socket = tokio::net::TcpStream::connect(crate::IRIDUM_MT_ADDR).await
-        .with_expected_err(|| "Failed to connect to Iridium gateway")?;
-
-    let client_message_id: u32 = rand::random();
-
-    let header = crate::ie::MTHeader {
-        client_message_id,
-        imei: message.imei,
-        flush_mt_queue: false,
-        send_ring_alert: false,
-        update_ssd_location: false,
-        high_priority_message: message.priority != 0,
-        assign_mtmsn: false,
-    };
-
-    let payload = crate::ie::MTPayload {
-        data: message.data
+    // The message may have been cancelled while waiting for a retry, or be being sent by another worker
+    let message = match claim_mt(message_id, &mut db_conn).await? {
+        Some(m) => m,
+        None => {
+            info!("MT message {} no longer pending, not delivering", message_id);
+            return Ok(());
+        }
     };
 
-    let mut elements = vec![header.to_element(), payload.to_element()];
-
-    if message.priority != 0 {
-        elements.push(crate::ie::MTPriority {
-            level: message.priority as u16,
-        }.to_element());
+    if message.expires_at.is_some_and(|e| chrono::Utc::now() > e.and_utc()) {
+        info!("MT message {} expired before delivery", message_id);
+        return expire_mt(message_id, &mut db_conn).await;
     }
 
-    let protocol_message = crate::ie::ProtocolMessage {
-        elements
+    let provider = match crate::provider::for_message(&message, HTTP_CLIENT.get().unwrap(), &mut db_conn).await {
+        Ok(p) => p,
+        Err(err) => {
+            release_mt(message_id, &mut db_conn).await?;
+            return Err(TaskError::ExpectedError(format!("Failed to get MT provider: {}", err)));
+        }
     };
 
-    trace!("Sending message: {:02x?}", protocol_message);
-
-    protocol_message.write(&mut socket).await
-        .with_expected_err(|| "Failed to write protocol message to socket")?;
-
-    let response_protocol_message = crate::ie::ProtocolMessage::read(&mut socket).await
-        .with_expected_err(|| "Failed to read response protocol message from socket")?;
-    let response_message = crate::ie::ResponseMessage::from_pm(response_protocol_message)
-        .with_unexpected_err(|| "Failed to decode response message")?;
-
-    trace!("Got response: {:02x?}", response_message);
-
-    if response_message.confirmation.client_message_id != header.client_message_id ||
-        response_message.confirmation.imei != header.imei {
-        warn!("Response not for sent message");
-        return task.retry_with_countdown(60);
-    }
+    let delivery_status = match provider.deliver(&message).await {
+        Ok(s) => s,
+        Err(crate::provider::Error::Expected(err)) => {
+            release_mt(message_id, &mut db_conn).await?;
+            return Err(TaskError::ExpectedError(err));
+        }
+        Err(crate::provider::Error::Unexpected(err)) => {
+            reject_mt(message_id, &mut db_conn).await?;
+            return Err(TaskError::UnexpectedError(err));
+        }
+    };
 
-    let cutoff = chrono::Utc::now() - chrono::Duration::hours(24);
+    // Messages without an explicit expiry are given 24 hours from when they were due to be delivered,
+    // or were last requeued
+    let deadline = message.expires_at.unwrap_or_else(
+        || message.requeued.or(message.not_before).unwrap_or(message.received) + chrono::Duration::hours(24)
+    ).and_utc();
+    let past_deadline = chrono::Utc::now() > deadline;
 
-    match response_message.confirmation.message_status {
-        crate::ie::MessageStatus::Successful(_) => {
+    match delivery_status {
+        crate::provider::DeliveryStatus::Delivered => {
             set_mt_processing_status(message_id, crate::models::ProcessingStatus::Done, &mut db_conn).await?;
             set_mt_message_status(message_id, crate::models::MessageStatus::Delivered, &mut db_conn).await?;
         }
-        crate::ie::MessageStatus::SuccessfulNoPayload => {
-            warn!("Expected payload to be acknowledged");
+        crate::provider::DeliveryStatus::Retry => {
+            release_mt(message_id, &mut db_conn).await?;
             return task.retry_with_countdown(60);
         }
-        crate::ie::MessageStatus::UnknownIMEI => {
+        crate::provider::DeliveryStatus::InvalidIMEI => {
             set_mt_processing_status(message_id, crate::models::ProcessingStatus::Done, &mut db_conn).await?;
             set_mt_message_status(message_id, crate::models::MessageStatus::InvalidImei, &mut db_conn).await?;
         }
-        crate::ie::MessageStatus::TooLarge => {
+        crate::provider::DeliveryStatus::PayloadSizeExceeded => {
             set_mt_processing_status(message_id, crate::models::ProcessingStatus::Done, &mut db_conn).await?;
             set_mt_message_status(message_id, crate::models::MessageStatus::PayloadSizeExceeded, &mut db_conn).await?;
         }
-        crate::ie::MessageStatus::QueueFull => {
-            if cutoff > message.received.and_utc() {
+        crate::provider::DeliveryStatus::QueueFull => {
+            if past_deadline && message.expires_at.is_some() {
+                return expire_mt(message_id, &mut db_conn).await;
+            } else if past_deadline {
                 set_mt_processing_status(message_id, crate::models::ProcessingStatus::Failed, &mut db_conn).await?;
                 set_mt_message_status(message_id, crate::models::MessageStatus::MessageQueueFull, &mut db_conn).await?;
             } else {
                 info!("Device queue full, retrying later");
+                release_mt(message_id, &mut db_conn).await?;
                 return task.retry_with_countdown(60);
             }
         }
-        crate::ie::MessageStatus::ResourcesUnavailable => {
-            warn!("Failed to send MT message, Iridium resources unavailable");
-            if cutoff > message.received.and_utc() {
+        crate::provider::DeliveryStatus::ResourcesUnavailable => {
+            warn!("Failed to send MT message, resources unavailable");
+            if past_deadline && message.expires_at.is_some() {
+                return expire_mt(message_id, &mut db_conn).await;
+            } else if past_deadline {
                 set_mt_processing_status(message_id, crate::models::ProcessingStatus::Failed, &mut db_conn).await?;
                 set_mt_message_status(message_id, crate::models::MessageStatus::ResourcesUnavailable, &mut db_conn).await?;
             } else {
+                release_mt(message_id, &mut db_conn).await?;
                 return task.retry_with_countdown(60);
             }
         }
-        o => {
-            set_mt_processing_status(message_id, crate::models::ProcessingStatus::Failed, &mut db_conn).await?;
-            return Err(TaskError::UnexpectedError(format!("unexpected response status: {:?}", o)));
+        crate::provider::DeliveryStatus::Rejected(err) => {
+            reject_mt(message_id, &mut db_conn).await?;
+            return Err(TaskError::UnexpectedError(err));
         }
     }
 
@@ -271,6 +426,57 @@ pub async fn deliver_mt(task: &Self, message_id: uuid::Uuid) -> TaskResult<()> {
     Ok(())
 }
 
+/// Marks a message as having not been delivered before its expiry
+async fn expire_mt(message_id: uuid::Uuid, db_conn: &mut crate::DBConn) -> TaskResult<()> {
+    set_mt_processing_status(message_id, crate::models::ProcessingStatus::Failed, db_conn).await?;
+    set_mt_message_status(message_id, crate::models::MessageStatus::Expired, db_conn).await?;
+
+    if let Err(err) = CELERY_APP.get().unwrap().send_task(send_mt_status::new(message_id)).await {
+        error!("Failed to send MT status task: {}", err);
+    }
+
+    Ok(())
+}
+
+/// Marks a message as refused by the provider, or as having an outcome that couldn't be understood
+async fn reject_mt(message_id: uuid::Uuid, db_conn: &mut crate::DBConn) -> TaskResult<()> {
+    set_mt_processing_status(message_id, crate::models::ProcessingStatus::Failed, db_conn).await?;
+    set_mt_message_status(message_id, crate::models::MessageStatus::Rejected, db_conn).await?;
+
+    if let Err(err) = CELERY_APP.get().unwrap().send_task(send_mt_status::new(message_id)).await {
+        error!("Failed to send MT status task: {}", err);
+    }
+
+    Ok(())
+}
+
+#[celery::task]
+pub async fn flush_mt_queue(message_id: uuid::Uuid) -> TaskResult<()> {
+    let mut db_conn = DB_POOL.get().unwrap().get().await
+        .with_expected_err(|| "Failed to get DB connection")?;
+
+    let message = crate::schema::mt_messages::dsl::mt_messages.filter(
+        crate::schema::mt_messages::dsl::id.eq(message_id)
+    ).get_result::<crate::models::MTMessage>(&mut db_conn).await
+        .with_expected_err(|| "Failed to get message from DB")?;
+
+    let provider = crate::provider::for_message(&message, HTTP_CLIENT.get().unwrap(), &mut db_conn).await
+        .with_expected_err(|| "Failed to get MT provider")?;
+
+    match provider.flush(&message.imei).await {
+        Ok(()) => {}
+        Err(crate::provider::Error::Expected(err)) => return Err(TaskError::ExpectedError(err)),
+        Err(crate::provider::Error::Unexpected(err)) => return Err(TaskError::UnexpectedError(err)),
+    }
+
+    crate::audit::record_message(
+        &mut db_conn, Some(message.target), Some(message.id), "mt_queue_flushed",
+        format!("MT queue for IMEI {} flushed to cancel message {}", message.imei, message.id)
+    ).await;
+
+    Ok(())
+}
+
 #[celery::task(bind = true)]
 pub async fn send_mt_status(task: &Self, message_id: uuid::Uuid) -> TaskResult<()> {
     let mut db_conn = DB_POOL.get().unwrap().get().await
@@ -292,21 +498,76 @@ pub async fn send_mt_status(task: &Self, message_id: uuid::Uuid) -> TaskResult<(
         }
     };
 
+    let device = match crate::schema::devices::dsl::devices
+        .filter(crate::schema::devices::dsl::imei.eq(&message.imei))
+        .filter(crate::schema::devices::dsl::target.eq(&target.id))
+        .get_result::<crate::models::Device>(&mut db_conn).await.optional()
+        .with_expected_err(|| "Failed to get device")? {
+        Some(d) => Some(crate::events::device(&mut db_conn, d).await
+            .with_expected_err(|| "Failed to get device tags")?),
+        None => None
+    };
+
+    // A requeued message can complete again, which is a new event
+    let event_key = format!(
+        "mt_message_status:{}:{}:{}", message.id, message_status,
+        message.completed.map(|c| c.and_utc().to_rfc3339_opts(chrono::SecondsFormat::Micros, true)).unwrap_or_default()
+    );
     let message_to_send = crate::types::WebhookMessage::MTMessageStatus(crate::types::MTMessageStatus {
         id: message.id,
-        status: match message_status {
-            crate::models::MessageStatus::Delivered => crate::types::MessageStatus::Delivered,
-            crate::models::MessageStatus::InvalidImei => crate::types::MessageStatus::InvalidIMEI,
-            crate::models::MessageStatus::PayloadSizeExceeded => crate::types::MessageStatus::PayloadSizeExceeded,
-            crate::models::MessageStatus::MessageQueueFull => crate::types::MessageStatus::MessageQueueFull,
-            crate::models::MessageStatus::ResourcesUnavailable => crate::types::MessageStatus::ResourcesUnavailable,
-        },
+        status: message_status.into(),
+        idempotency_key: message.idempotency_key,
+        client_reference: message.client_reference,
+        metadata: message.metadata,
+        batch: message.batch,
     });
+    let event = crate::events::Event::new(
+        &event_key, crate::events::device_source(&message.imei),
+        message.completed.map_or_else(chrono::Utc::now, |c| c.and_utc()), device, message_to_send,
+    );
+
+    let key = crate::keys::WebhookKey::for_target(&target, &mut db_conn).await
+        .with_expected_err(|| "Failed to get target signing key")?;
 
-    if !send_webhook(&target, &message_to_send).await {
+    if !send_event(&mut db_conn, &target, &key, &format!("{}/mt_status", message.imei), event, None).await? {
         info!("Failed to send status webhook, retrying later");
         return task.retry_with_countdown(60);
     }
 
     Ok(())
-}
\ No newline at end of file
+}
+
+#[celery::task(bind = true)]
+pub async fn send_batch_complete(task: &Self, batch_id: uuid::Uuid) -> TaskResult<()> {
+    let mut db_conn = DB_POOL.get().unwrap().get().await
+        .with_expected_err(|| "Failed to get DB connection")?;
+
+    let batch = crate::schema::mt_batches::dsl::mt_batches.filter(
+        crate::schema::mt_batches::dsl::id.eq(batch_id)
+    ).get_result::<crate::models::MTBatch>(&mut db_conn).await
+        .with_expected_err(|| "Failed to get batch from DB")?;
+    let target = crate::schema::targets::dsl::targets.filter(
+        crate::schema::targets::dsl::id.eq(batch.target)
+    ).get_result::<crate::models::Target>(&mut db_conn).await
+        .with_expected_err(|| "Failed to get target from DB")?;
+
+    let completed = batch.completed.map_or_else(chrono::Utc::now, |c| c.and_utc());
+    let message_to_send = crate::types::WebhookMessage::MTBatchComplete(
+        crate::batches::batch_status(&mut db_conn, batch).await
+            .with_expected_err(|| "Failed to get batch status")?
+    );
+    let event = crate::events::Event::new(
+        &format!("mt_batch_complete:{}", batch_id), crate::events::batch_source(batch_id), completed, None,
+        message_to_send,
+    );
+
+    let key = crate::keys::WebhookKey::for_target(&target, &mut db_conn).await
+        .with_expected_err(|| "Failed to get target signing key")?;
+
+    if !send_event(&mut db_conn, &target, &key, &format!("batches/{}/complete", batch_id), event, None).await? {
+        info!("Failed to send batch completion webhook, retrying later");
+        return task.retry_with_countdown(60);
+    }
+
+    Ok(())
+}